@@ -0,0 +1,8 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // the indexer only serves its query service, the chain clients come from
+    // cosmos-sdk-proto
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/indexer/v1/query.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,405 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header;
+use actix_web::web;
+use chrono::{NaiveDate, Utc};
+use lazy_static::lazy_static;
+use log::error;
+use rand::Rng;
+use rocksdb::{ColumnFamily, IteratorMode, DB};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::ApiError;
+
+pub const API_KEYS_CF: &str = "api_keys";
+
+// probes, scrapes and the API docs are never limited
+const EXEMPT_PATHS: [&str; 6] = [
+    "/healthz",
+    "/readyz",
+    "/metrics",
+    "/openapi.json",
+    "/docs",
+    "/docs/redoc.standalone.js",
+];
+// routes that need the admin token instead of an API key
+const ADMIN_PATH_PREFIX: &str = "/admin";
+// clients idle for IDLE_CLIENT_TIMEOUT are forgotten, the tracked clients
+// are swept for them every CLIENT_SWEEP_INTERVAL
+const IDLE_CLIENT_TIMEOUT: Duration = Duration::from_secs(3600);
+const CLIENT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An API key as stored in the database, under the sha256 hash of the key
+/// so the keys themselves can't be read back
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    /// short public identifier used to list and revoke the key
+    pub id: String,
+    pub name: String,
+    pub created: i64,
+    /// requests per minute, overrides the default for keys
+    pub rate_limit: Option<u32>,
+    /// requests per UTC day, overrides the default for keys
+    pub daily_quota: Option<u64>,
+}
+
+/// Authentication and limit settings, a limit of 0 means unlimited
+#[derive(Debug, Clone)]
+pub struct AccessControl {
+    /// reject requests without a valid API key instead of limiting them by IP
+    pub require_api_key: bool,
+    /// take the client IP from the last X-Forwarded-For entry, only safe
+    /// behind a proxy that appends the address it got the request from
+    pub trust_forwarded_for: bool,
+    pub ip_rate_limit: u32,
+    pub ip_daily_quota: u64,
+    pub key_rate_limit: u32,
+    pub key_daily_quota: u64,
+    /// token for the /admin routes, which are refused when it isn't set
+    pub admin_token: Option<String>,
+}
+
+/// token bucket and quota usage of a single key or IP
+struct ClientState {
+    tokens: f64,
+    last_seen: Instant,
+    day: NaiveDate,
+    used_today: u64,
+}
+
+/// the state of every client seen within IDLE_CLIENT_TIMEOUT
+struct Clients {
+    states: HashMap<String, ClientState>,
+    last_sweep: Instant,
+}
+
+lazy_static! {
+    static ref CLIENTS: Mutex<Clients> = Mutex::new(Clients {
+        states: HashMap::new(),
+        last_sweep: Instant::now(),
+    });
+}
+
+fn api_keys_cf(db: &DB) -> &ColumnFamily {
+    db.cf_handle(API_KEYS_CF)
+        .expect("API keys column family is missing")
+}
+
+/// stores a new key and returns it, it can't be retrieved again later
+pub fn create_api_key(
+    db: &DB,
+    name: &str,
+    rate_limit: Option<u32>,
+    daily_quota: Option<u64>,
+) -> Result<(String, ApiKey), rocksdb::Error> {
+    let key = format!("idx_{}", hex::encode(rand::thread_rng().gen::<[u8; 32]>()));
+    let hash = sha256::digest(key.as_str());
+    let api_key = ApiKey {
+        id: hash[..12].to_string(),
+        name: name.to_string(),
+        created: Utc::now().timestamp(),
+        rate_limit,
+        daily_quota,
+    };
+    db.put_cf(
+        api_keys_cf(db),
+        hash.as_bytes(),
+        serde_json::to_vec(&api_key).unwrap(),
+    )?;
+    Ok((key, api_key))
+}
+
+pub fn list_api_keys(db: &DB) -> Vec<ApiKey> {
+    let mut keys = Vec::new();
+    for item in db.iterator_cf(api_keys_cf(db), IteratorMode::Start) {
+        match item {
+            Ok((_, value)) => match serde_json::from_slice::<ApiKey>(&value) {
+                Ok(api_key) => keys.push(api_key),
+                Err(e) => error!("Failed to deserialize API key: {}", e),
+            },
+            Err(err) => error!("RocksDB iterator error: {}", err),
+        }
+    }
+    keys
+}
+
+/// deletes the key with the given id, returns whether it existed
+pub fn revoke_api_key(db: &DB, id: &str) -> Result<bool, rocksdb::Error> {
+    if id.is_empty() {
+        return Ok(false);
+    }
+    // ids are a prefix of the hash the key is stored under
+    for item in db.iterator_cf(api_keys_cf(db), IteratorMode::Start) {
+        let (hash, _) = item?;
+        if hash.starts_with(id.as_bytes()) {
+            db.delete_cf(api_keys_cf(db), hash)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn find_api_key(db: &DB, key: &str) -> Result<Option<ApiKey>, rocksdb::Error> {
+    let hash = sha256::digest(key);
+    Ok(db
+        .get_cf(api_keys_cf(db), hash.as_bytes())?
+        .and_then(|value| serde_json::from_slice(&value).ok()))
+}
+
+/// the key from the `x-api-key` header or an `Authorization: Bearer` header
+fn presented_key(req: &ServiceRequest) -> Option<String> {
+    let headers = req.headers();
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key.trim().to_string());
+    }
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|key| key.trim().to_string())
+}
+
+/// the peer address, or with `trust_forwarded_for` the address the proxy
+/// appended to X-Forwarded-For
+fn client_ip(req: &ServiceRequest, trust_forwarded_for: bool) -> String {
+    let forwarded = if trust_forwarded_for {
+        forwarded_for(req)
+    } else {
+        None
+    };
+    match forwarded.or_else(|| req.peer_addr().map(|address| address.ip())) {
+        Some(ip) => ip.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// the rightmost X-Forwarded-For entry, the one added by the proxy in front
+/// of the API. The entries before it come from the client, which could send a
+/// new address with every request to get a fresh limit. The Forwarded header
+/// is ignored, the proxy has to set X-Forwarded-For.
+fn forwarded_for(req: &ServiceRequest) -> Option<IpAddr> {
+    let last = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .last()?;
+    // some proxies add the port
+    last.parse::<IpAddr>()
+        .or_else(|_| last.parse::<SocketAddr>().map(|address| address.ip()))
+        .ok()
+}
+
+/// counts a request against the client's token bucket and daily quota
+fn take_request(client: &str, rate_limit: u32, daily_quota: u64) -> Result<(), ApiError> {
+    let now = Instant::now();
+    let today = Utc::now().date_naive();
+    let mut clients = CLIENTS.lock().unwrap();
+    // sweeping walks every client, so it is done once in a while rather than
+    // on every request
+    if now.duration_since(clients.last_sweep) >= CLIENT_SWEEP_INTERVAL {
+        clients
+            .states
+            .retain(|_, state| now.duration_since(state.last_seen) < IDLE_CLIENT_TIMEOUT);
+        clients.last_sweep = now;
+    }
+    let state = clients
+        .states
+        .entry(client.to_string())
+        .or_insert_with(|| ClientState {
+            tokens: rate_limit as f64,
+            last_seen: now,
+            day: today,
+            used_today: 0,
+        });
+
+    if state.day != today {
+        state.day = today;
+        state.used_today = 0;
+    }
+    // the bucket holds a minute worth of requests and refills continuously
+    let capacity = rate_limit as f64;
+    let refill = now.duration_since(state.last_seen).as_secs_f64() * capacity / 60.0;
+    state.tokens = (state.tokens + refill).min(capacity);
+    state.last_seen = now;
+
+    if daily_quota > 0 && state.used_today >= daily_quota {
+        let midnight = today
+            .succ_opt()
+            .and_then(|tomorrow| tomorrow.and_hms_opt(0, 0, 0))
+            .expect("Invalid date");
+        let retry_after = (midnight - Utc::now().naive_utc()).num_seconds().max(1) as u64;
+        return Err(ApiError::too_many_requests(
+            "quota_exceeded",
+            "The daily quota is used up, it resets at midnight UTC.",
+            retry_after,
+        ));
+    }
+    if rate_limit > 0 {
+        if state.tokens < 1.0 {
+            let retry_after = ((1.0 - state.tokens) * 60.0 / capacity).ceil() as u64;
+            return Err(ApiError::too_many_requests(
+                "rate_limited",
+                "Rate limit exceeded.",
+                retry_after.max(1),
+            ));
+        }
+        state.tokens -= 1.0;
+    }
+    state.used_today += 1;
+    Ok(())
+}
+
+/// checks the admin token of a request to an /admin route, API keys don't
+/// give access to them
+fn check_admin(req: &ServiceRequest, access: &AccessControl) -> Result<(), ApiError> {
+    let expected = access.admin_token.as_deref().ok_or_else(|| {
+        ApiError::forbidden(
+            "admin_disabled",
+            "The admin routes are disabled, set ADMIN_TOKEN to enable them.",
+        )
+    })?;
+    let presented = req
+        .headers()
+        .get("x-admin-token")
+        .and_then(|v| v.to_str().ok())
+        .map(|token| token.trim())
+        .ok_or_else(|| {
+            ApiError::unauthorized(
+                "admin_token_required",
+                "The admin token is required, pass it in the x-admin-token header.",
+            )
+        })?;
+    // comparing the hashes doesn't leak through timing how much of the
+    // token matched
+    if sha256::digest(presented) != sha256::digest(expected) {
+        return Err(ApiError::unauthorized(
+            "invalid_admin_token",
+            "The admin token is not valid.",
+        ));
+    }
+    Ok(())
+}
+
+/// checks the API key and the limits of a request, or the admin token for
+/// the /admin routes. Returns the error to respond with instead of calling
+/// the route if it is rejected
+pub fn check_request(req: &ServiceRequest) -> Option<ApiError> {
+    if EXEMPT_PATHS.contains(&req.path()) {
+        return None;
+    }
+    let access = req.app_data::<web::Data<AccessControl>>()?;
+    if req.path().starts_with(ADMIN_PATH_PREFIX) {
+        return check_admin(req, access).err();
+    }
+    let db = req.app_data::<web::Data<Arc<DB>>>()?;
+    check_client(db, access, presented_key(req), || {
+        client_ip(req, access.trust_forwarded_for)
+    })
+    .err()
+}
+
+/// checks a presented API key, or the client IP if there is none, against
+/// the limits. Shared by the HTTP and gRPC APIs.
+pub fn check_client(
+    db: &DB,
+    access: &AccessControl,
+    key: Option<String>,
+    client_ip: impl FnOnce() -> String,
+) -> Result<(), ApiError> {
+    let (client, rate_limit, daily_quota) = match key {
+        Some(key) => match find_api_key(db, &key) {
+            Ok(Some(api_key)) => (
+                format!("key:{}", api_key.id),
+                api_key.rate_limit.unwrap_or(access.key_rate_limit),
+                api_key.daily_quota.unwrap_or(access.key_daily_quota),
+            ),
+            Ok(None) => {
+                return Err(ApiError::unauthorized(
+                    "invalid_api_key",
+                    "The API key is not valid.",
+                ))
+            }
+            Err(e) => {
+                error!("Failed to look up API key: {}", e);
+                return Err(ApiError::internal("Failed to check the API key."));
+            }
+        },
+        None if access.require_api_key => {
+            return Err(ApiError::unauthorized(
+                "api_key_required",
+                "An API key is required, pass it in the x-api-key header.",
+            ))
+        }
+        None => (
+            format!("ip:{}", client_ip()),
+            access.ip_rate_limit,
+            access.ip_daily_quota,
+        ),
+    };
+    take_request(&client, rate_limit, daily_quota)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use actix_web::ResponseError;
+
+    use crate::transactions::database::tests::TestDb;
+
+    fn access(ip_rate_limit: u32) -> AccessControl {
+        AccessControl {
+            require_api_key: false,
+            trust_forwarded_for: true,
+            ip_rate_limit,
+            ip_daily_quota: 0,
+            key_rate_limit: 0,
+            key_daily_quota: 0,
+            admin_token: None,
+        }
+    }
+
+    fn request(forwarded_for: &str) -> TestRequest {
+        TestRequest::with_uri("/transactions/send")
+            .peer_addr("10.0.0.1:40000".parse().unwrap())
+            .insert_header(("x-forwarded-for", forwarded_for))
+    }
+
+    #[test]
+    fn takes_the_address_the_proxy_appended() {
+        let req = request("198.51.100.1, 198.51.100.2, 192.0.2.10").to_srv_request();
+        assert_eq!(client_ip(&req, true), "192.0.2.10");
+        assert_eq!(client_ip(&req, false), "10.0.0.1");
+
+        let req = request("192.0.2.10:51234").to_srv_request();
+        assert_eq!(client_ip(&req, true), "192.0.2.10");
+        // nothing usable from the proxy, the peer is limited instead
+        let req = request("198.51.100.1, not-an-ip").to_srv_request();
+        assert_eq!(client_ip(&req, true), "10.0.0.1");
+    }
+
+    #[test]
+    fn spoofed_entries_do_not_reset_the_limit() {
+        let test = TestDb::new();
+        let access = web::Data::new(access(2));
+        let db = web::Data::new(test.db.clone());
+        let status = |spoofed: &str| {
+            let req = request(&format!("{}, 192.0.2.77", spoofed))
+                .app_data(access.clone())
+                .app_data(db.clone())
+                .to_srv_request();
+            check_request(&req).map(|e| e.status_code())
+        };
+
+        assert_eq!(status("198.51.100.1"), None);
+        assert_eq!(status("198.51.100.2"), None);
+        assert_eq!(status("198.51.100.3"), Some(StatusCode::TOO_MANY_REQUESTS));
+    }
+}
@@ -0,0 +1,781 @@
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, process};
+
+use crate::auth::AccessControl;
+use crate::grpc::{parse_header, serialize_redacted, EndpointSettings, HeaderInterceptor};
+use crate::nodes::NodeEndpoint;
+use crate::transactions::database::{SyncOptions, MSG_TYPES};
+use crate::transactions::endpoints::ReadinessOptions;
+use crate::webhooks::WebhookTargets;
+
+const DEFAULT_CHAIN_NODE_GRPC: &str = "http://66.172.36.142:2119";
+const DEFAULT_BIND_ADDRESS: &str = "localhost";
+const DEFAULT_PORT: u16 = 9000;
+const DEFAULT_DB_PATH: &str = "transactions";
+const DEFAULT_BATCH_SIZE: u64 = 500;
+const DEFAULT_CONCURRENCY: usize = 10;
+const DEFAULT_MAX_RETRIES: usize = 5;
+const DEFAULT_TEST_BLOCK_LIMIT: u64 = 100000;
+const DEFAULT_READY_MAX_LAG: u64 = 20;
+const DEFAULT_READY_MAX_IDLE: u64 = 120;
+const DEFAULT_CATCH_UP_INTERVAL: u64 = 1;
+
+/// Every setting can come from the config file, an environment variable or a
+/// flag. Flags win over environment variables, which win over the file. On/off
+/// settings are plain flags, set by the flag, by their environment variable
+/// unless it is e.g. `false` or `0`, or by the file.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct Args {
+    /// TOML file to read settings from, see config.example.toml
+    #[clap(long = "config", env = "CONFIG_FILE")]
+    pub config_file: Option<PathBuf>,
+
+    /// gRPC endpoints to index from, comma separated or repeated, requests
+    /// fail over between them based on their health. An endpoint can be
+    /// limited to a height range with `url@from-to`, either bound is optional
+    #[clap(long, env = "CHAIN_NODE_GRPC", value_delimiter = ',')]
+    pub chain_node_grpc: Option<Vec<NodeEndpoint>>,
+
+    /// CometBFT websocket RPC (e.g. `ws://<node>:26657/websocket`), when set
+    /// new blocks are indexed as soon as they are announced instead of polled
+    #[clap(long, env = "CHAIN_NODE_RPC")]
+    pub chain_node_rpc: Option<String>,
+
+    /// metadata sent with every gRPC request as `name: value`, comma
+    /// separated or repeated, e.g. `x-api-key: <key>`
+    #[clap(long = "grpc-header", env = "GRPC_HEADERS", value_delimiter = ',')]
+    pub grpc_headers: Option<Vec<String>>,
+
+    /// address the API listens on
+    #[clap(long, env = "BIND_ADDRESS")]
+    pub bind_address: Option<String>,
+
+    #[clap(long, env = "PORT")]
+    pub port: Option<u16>,
+
+    /// port of the gRPC query service, it is only served when set
+    #[clap(long, env = "GRPC_PORT")]
+    pub grpc_port: Option<u16>,
+
+    /// origins allowed to call the API from a browser, comma separated, any
+    /// origin is allowed when empty
+    #[clap(long, env = "CORS_ORIGINS", value_delimiter = ',')]
+    pub cors_origins: Option<Vec<String>>,
+
+    /// reject requests without a valid API key
+    #[clap(long, env = "REQUIRE_API_KEY")]
+    pub require_api_key: bool,
+
+    /// token the /admin routes require in the x-admin-token header, they are
+    /// refused when it isn't set
+    #[clap(long, env = "ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// hosts webhooks may be sent to, comma separated, `*.example.com`
+    /// matches subdomains. Any host is allowed when empty.
+    #[clap(long, env = "WEBHOOK_ALLOWED_HOSTS", value_delimiter = ',')]
+    pub webhook_allowed_hosts: Option<Vec<String>>,
+
+    /// hosts webhooks may never be sent to, in the same notation
+    #[clap(long, env = "WEBHOOK_DENIED_HOSTS", value_delimiter = ',')]
+    pub webhook_denied_hosts: Option<Vec<String>>,
+
+    /// allow webhooks to loopback, private and link local addresses
+    #[clap(long, env = "WEBHOOK_ALLOW_PRIVATE")]
+    pub webhook_allow_private: bool,
+
+    /// take the client IP for rate limits from the last X-Forwarded-For
+    /// entry, the one the proxy appended
+    #[clap(long, env = "TRUST_FORWARDED_FOR")]
+    pub trust_forwarded_for: bool,
+
+    /// requests per minute per IP without an API key, 0 for unlimited
+    #[clap(long, env = "IP_RATE_LIMIT")]
+    pub ip_rate_limit: Option<u32>,
+
+    /// requests per UTC day per IP without an API key, 0 for unlimited
+    #[clap(long, env = "IP_DAILY_QUOTA")]
+    pub ip_daily_quota: Option<u64>,
+
+    /// requests per minute per API key unless set on the key, 0 for unlimited
+    #[clap(long, env = "KEY_RATE_LIMIT")]
+    pub key_rate_limit: Option<u32>,
+
+    /// requests per UTC day per API key unless set on the key, 0 for unlimited
+    #[clap(long, env = "KEY_DAILY_QUOTA")]
+    pub key_daily_quota: Option<u64>,
+
+    /// PEM certificate chain, the API is served over HTTPS when set
+    #[clap(long, env = "TLS_CERT")]
+    pub tls_cert: Option<String>,
+
+    /// PEM private key for --tls-cert
+    #[clap(long, env = "TLS_KEY")]
+    pub tls_key: Option<String>,
+
+    /// plain HTTP port that redirects every request to HTTPS
+    #[clap(long, env = "HTTP_REDIRECT_PORT")]
+    pub http_redirect_port: Option<u16>,
+
+    /// local copy of Redoc's redoc.standalone.js for /docs, which loads it
+    /// from the Redoc CDN otherwise
+    #[clap(long, env = "DOCS_SCRIPT")]
+    pub docs_script: Option<String>,
+
+    /// directory of the RocksDB database
+    #[clap(long, env = "DB_PATH")]
+    pub db_path: Option<String>,
+
+    /// directory for the secondary instance `serve` opens on top of the
+    /// database, defaults to a per process directory in the temp dir that is
+    /// removed on exit
+    #[clap(long, env = "SECONDARY_DB_PATH")]
+    pub secondary_db_path: Option<String>,
+
+    /// how many seconds `serve` waits between catching up with the indexer
+    #[clap(long, env = "CATCH_UP_INTERVAL")]
+    pub catch_up_interval: Option<u64>,
+
+    /// how many blocks are requested at once
+    #[clap(long, env = "BATCH_SIZE")]
+    pub batch_size: Option<u64>,
+
+    /// how many block ranges are downloaded in parallel
+    #[clap(long, env = "CONCURRENCY")]
+    pub concurrency: Option<usize>,
+
+    /// how many times a failing gRPC request is tried before giving up
+    #[clap(long, env = "MAX_RETRIES")]
+    pub max_retries: Option<usize>,
+
+    /// message types to index, comma separated (msgSend,msgIbcTransfer)
+    #[clap(long, env = "MSG_TYPES", value_delimiter = ',')]
+    pub msg_types: Option<Vec<String>>,
+
+    #[clap(long, env = "TEST_MODE")]
+    pub test_mode: bool,
+
+    #[clap(long, env = "TEST_BLOCK_LIMIT")]
+    pub test_block_limit: Option<u64>,
+
+    /// height to start the historical sync from on a fresh database, or to
+    /// skip ahead of the checkpoint
+    #[clap(long, env = "START_HEIGHT")]
+    pub start_height: Option<u64>,
+
+    /// height to stop the historical sync at, the chain is not followed after
+    #[clap(long, env = "END_HEIGHT")]
+    pub end_height: Option<u64>,
+
+    /// how many blocks behind the chain tip the indexer may be while /readyz
+    /// still reports ready
+    #[clap(long, env = "READY_MAX_LAG")]
+    pub ready_max_lag: Option<u64>,
+
+    /// how many seconds the indexer and the gRPC endpoints may go without
+    /// progress before /readyz reports not ready
+    #[clap(long, env = "READY_MAX_IDLE")]
+    pub ready_max_idle: Option<u64>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Index the chain and serve the API from the same database, the default
+    Run,
+    /// Only index the chain, the health, status and admin routes are served
+    Index,
+    /// Only serve the API from a read only copy of the database that follows
+    /// the process running `index`
+    Serve,
+    /// Write the coins an address sent and received as CSV, like
+    /// /transactions/{address}/export. Reads a read only copy of the
+    /// database, so the indexer can keep running.
+    Export {
+        address: String,
+        /// file to write, stdout if unset
+        #[clap(long, short)]
+        output: Option<String>,
+        /// only messages of this type, msgSend or msgIbcTransfer
+        #[clap(long = "type")]
+        msg_type: Option<String>,
+        /// only coins of this denom
+        #[clap(long)]
+        denom: Option<String>,
+        /// IANA zone for the dates and the date filters, UTC if unset
+        #[clap(long)]
+        tz: Option<String>,
+        /// first day to include, YYYY-MM-DD
+        #[clap(long)]
+        from: Option<String>,
+        /// last day to include, YYYY-MM-DD
+        #[clap(long)]
+        to: Option<String>,
+    },
+    /// One off maintenance on the database, the indexer must not be running
+    Admin {
+        #[clap(subcommand)]
+        command: AdminCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AdminCommand {
+    /// Index the blocks from <FROM> to <TO> (inclusive) and exit, without
+    /// touching the live checkpoint
+    Backfill { from: u64, to: u64 },
+    /// Delete everything indexed from --from to --to (inclusive) and index
+    /// it again, for example after the decoding logic changed. Stop the
+    /// indexer first.
+    Reindex {
+        #[clap(long)]
+        from: u64,
+        #[clap(long)]
+        to: u64,
+        /// only reindex these message types, e.g. msgSend,msgIbcTransfer, they
+        /// have to be enabled in msg_types
+        #[clap(long, value_delimiter = ',')]
+        types: Vec<String>,
+    },
+    /// Manage the API keys clients authenticate with. Stop the indexer
+    /// first, the keys are written to its database.
+    ApiKey {
+        #[clap(subcommand)]
+        command: ApiKeyCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ApiKeyCommand {
+    /// Create a key, it is printed once and can't be shown again
+    Create {
+        #[clap(long)]
+        name: String,
+        /// requests per minute, instead of the default for keys
+        #[clap(long)]
+        rate_limit: Option<u32>,
+        /// requests per UTC day, instead of the default for keys
+        #[clap(long)]
+        daily_quota: Option<u64>,
+    },
+    /// List the keys without the secrets
+    List,
+    /// Delete the key with the given id
+    Revoke { id: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Validate the merged configuration and print it
+    Check,
+}
+
+/// The config file, every key is optional and named like its flag
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    chain_node_grpc: Option<Vec<NodeEndpoint>>,
+    chain_node_rpc: Option<String>,
+    grpc_headers: Option<BTreeMap<String, String>>,
+    grpc_endpoints: Option<BTreeMap<String, EndpointSettings>>,
+    bind_address: Option<String>,
+    port: Option<u16>,
+    grpc_port: Option<u16>,
+    cors_origins: Option<Vec<String>>,
+    require_api_key: Option<bool>,
+    admin_token: Option<String>,
+    webhook_allowed_hosts: Option<Vec<String>>,
+    webhook_denied_hosts: Option<Vec<String>>,
+    webhook_allow_private: Option<bool>,
+    trust_forwarded_for: Option<bool>,
+    ip_rate_limit: Option<u32>,
+    ip_daily_quota: Option<u64>,
+    key_rate_limit: Option<u32>,
+    key_daily_quota: Option<u64>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    http_redirect_port: Option<u16>,
+    docs_script: Option<String>,
+    db_path: Option<String>,
+    secondary_db_path: Option<String>,
+    catch_up_interval: Option<u64>,
+    batch_size: Option<u64>,
+    concurrency: Option<usize>,
+    max_retries: Option<usize>,
+    msg_types: Option<Vec<String>>,
+    test_mode: Option<bool>,
+    test_block_limit: Option<u64>,
+    start_height: Option<u64>,
+    end_height: Option<u64>,
+    ready_max_lag: Option<u64>,
+    ready_max_idle: Option<u64>,
+}
+
+/// The merged and validated configuration
+#[derive(Serialize, Debug, Clone)]
+pub struct Config {
+    pub chain_node_grpc: Vec<NodeEndpoint>,
+    pub chain_node_rpc: Option<String>,
+    pub bind_address: String,
+    pub port: u16,
+    pub grpc_port: Option<u16>,
+    pub cors_origins: Vec<String>,
+    pub require_api_key: bool,
+    #[serde(serialize_with = "serialize_secret")]
+    pub admin_token: Option<String>,
+    pub webhook_allowed_hosts: Vec<String>,
+    pub webhook_denied_hosts: Vec<String>,
+    pub webhook_allow_private: bool,
+    pub trust_forwarded_for: bool,
+    pub ip_rate_limit: u32,
+    pub ip_daily_quota: u64,
+    pub key_rate_limit: u32,
+    pub key_daily_quota: u64,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub http_redirect_port: Option<u16>,
+    pub docs_script: Option<String>,
+    pub db_path: String,
+    pub secondary_db_path: Option<String>,
+    pub catch_up_interval: u64,
+    pub batch_size: u64,
+    pub concurrency: usize,
+    pub max_retries: usize,
+    pub msg_types: Vec<String>,
+    pub test_mode: bool,
+    pub test_block_limit: u64,
+    pub start_height: Option<u64>,
+    pub end_height: Option<u64>,
+    pub ready_max_lag: u64,
+    pub ready_max_idle: u64,
+    // tables have to come last to be printed as TOML
+    #[serde(serialize_with = "serialize_redacted")]
+    pub grpc_headers: BTreeMap<String, String>,
+    pub grpc_endpoints: BTreeMap<String, EndpointSettings>,
+}
+
+/// the admin token is never printed
+fn serialize_secret<S: Serializer>(
+    secret: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    secret.as_ref().map(|_| "<redacted>").serialize(serializer)
+}
+
+impl Config {
+    /// reads the config file if one is given, applies the environment and
+    /// flags from `args` on top and validates the result
+    pub fn load(args: &Args) -> Result<Config, String> {
+        let file = match &args.config_file {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                toml::from_str(&contents)
+                    .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?
+            }
+            None => FileConfig::default(),
+        };
+
+        let grpc_headers = match &args.grpc_headers {
+            Some(headers) => headers
+                .iter()
+                .map(|header| parse_header(header))
+                .collect::<Result<BTreeMap<_, _>, _>>()?,
+            None => file.grpc_headers.unwrap_or_default(),
+        };
+
+        let config = Config {
+            chain_node_grpc: args
+                .chain_node_grpc
+                .clone()
+                .or(file.chain_node_grpc)
+                .unwrap_or_else(|| vec![DEFAULT_CHAIN_NODE_GRPC.parse().unwrap()]),
+            chain_node_rpc: args.chain_node_rpc.clone().or(file.chain_node_rpc),
+            bind_address: args
+                .bind_address
+                .clone()
+                .or(file.bind_address)
+                .unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string()),
+            port: args.port.or(file.port).unwrap_or(DEFAULT_PORT),
+            grpc_port: args.grpc_port.or(file.grpc_port),
+            cors_origins: args
+                .cors_origins
+                .clone()
+                .or(file.cors_origins)
+                .unwrap_or_default(),
+            require_api_key: args.require_api_key || file.require_api_key.unwrap_or(false),
+            admin_token: args.admin_token.clone().or(file.admin_token),
+            webhook_allowed_hosts: args
+                .webhook_allowed_hosts
+                .clone()
+                .or(file.webhook_allowed_hosts)
+                .unwrap_or_default(),
+            webhook_denied_hosts: args
+                .webhook_denied_hosts
+                .clone()
+                .or(file.webhook_denied_hosts)
+                .unwrap_or_default(),
+            webhook_allow_private: args.webhook_allow_private
+                || file.webhook_allow_private.unwrap_or(false),
+            trust_forwarded_for: args.trust_forwarded_for
+                || file.trust_forwarded_for.unwrap_or(false),
+            ip_rate_limit: args.ip_rate_limit.or(file.ip_rate_limit).unwrap_or(0),
+            ip_daily_quota: args.ip_daily_quota.or(file.ip_daily_quota).unwrap_or(0),
+            key_rate_limit: args.key_rate_limit.or(file.key_rate_limit).unwrap_or(0),
+            key_daily_quota: args.key_daily_quota.or(file.key_daily_quota).unwrap_or(0),
+            tls_cert: args.tls_cert.clone().or(file.tls_cert),
+            tls_key: args.tls_key.clone().or(file.tls_key),
+            http_redirect_port: args.http_redirect_port.or(file.http_redirect_port),
+            docs_script: args.docs_script.clone().or(file.docs_script),
+            db_path: args
+                .db_path
+                .clone()
+                .or(file.db_path)
+                .unwrap_or_else(|| DEFAULT_DB_PATH.to_string()),
+            secondary_db_path: args.secondary_db_path.clone().or(file.secondary_db_path),
+            catch_up_interval: args
+                .catch_up_interval
+                .or(file.catch_up_interval)
+                .unwrap_or(DEFAULT_CATCH_UP_INTERVAL),
+            batch_size: args
+                .batch_size
+                .or(file.batch_size)
+                .unwrap_or(DEFAULT_BATCH_SIZE),
+            concurrency: args
+                .concurrency
+                .or(file.concurrency)
+                .unwrap_or(DEFAULT_CONCURRENCY),
+            max_retries: args
+                .max_retries
+                .or(file.max_retries)
+                .unwrap_or(DEFAULT_MAX_RETRIES),
+            msg_types: args
+                .msg_types
+                .clone()
+                .or(file.msg_types)
+                .unwrap_or_else(|| MSG_TYPES.iter().map(|t| t.to_string()).collect()),
+            test_mode: args.test_mode || file.test_mode.unwrap_or(false),
+            test_block_limit: args
+                .test_block_limit
+                .or(file.test_block_limit)
+                .unwrap_or(DEFAULT_TEST_BLOCK_LIMIT),
+            start_height: args.start_height.or(file.start_height),
+            end_height: args.end_height.or(file.end_height),
+            ready_max_lag: args
+                .ready_max_lag
+                .or(file.ready_max_lag)
+                .unwrap_or(DEFAULT_READY_MAX_LAG),
+            ready_max_idle: args
+                .ready_max_idle
+                .or(file.ready_max_idle)
+                .unwrap_or(DEFAULT_READY_MAX_IDLE),
+            grpc_headers,
+            grpc_endpoints: file.grpc_endpoints.unwrap_or_default(),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.chain_node_grpc.is_empty() {
+            return Err("At least one gRPC endpoint is required".to_string());
+        }
+        for endpoint in &self.chain_node_grpc {
+            if !endpoint.url.starts_with("http://") && !endpoint.url.starts_with("https://") {
+                return Err(format!(
+                    "Invalid gRPC endpoint {}, expected an http:// or https:// url",
+                    endpoint
+                ));
+            }
+        }
+        HeaderInterceptor::new(&self.grpc_headers)?;
+        for (url, settings) in &self.grpc_endpoints {
+            if !self
+                .chain_node_grpc
+                .iter()
+                .any(|endpoint| &endpoint.url == url)
+            {
+                return Err(format!(
+                    "Settings for {} don't match any configured gRPC endpoint",
+                    url
+                ));
+            }
+            if settings.uses_tls() && !url.starts_with("https://") {
+                return Err(format!("TLS settings for {} require an https:// url", url));
+            }
+            if settings.client_cert.is_some() != settings.client_key.is_some() {
+                return Err(format!(
+                    "client_cert and client_key for {} have to be set together",
+                    url
+                ));
+            }
+            HeaderInterceptor::new(&settings.headers)?;
+        }
+        if let Some(rpc) = &self.chain_node_rpc {
+            if !rpc.starts_with("ws://") && !rpc.starts_with("wss://") {
+                return Err(format!(
+                    "Invalid RPC endpoint {}, expected a ws:// or wss:// url",
+                    rpc
+                ));
+            }
+        }
+        if self.bind_address.is_empty() {
+            return Err("The bind address can not be empty".to_string());
+        }
+        if self.port == 0 {
+            return Err("The port can not be 0".to_string());
+        }
+        if let Some(grpc_port) = self.grpc_port {
+            if grpc_port == 0
+                || grpc_port == self.port
+                || Some(grpc_port) == self.http_redirect_port
+            {
+                return Err(format!(
+                    "Invalid grpc_port {}, it must differ from the other ports",
+                    grpc_port
+                ));
+            }
+        }
+        if let Some(origin) = self
+            .cors_origins
+            .iter()
+            .find(|origin| !origin.starts_with("http://") && !origin.starts_with("https://"))
+        {
+            return Err(format!(
+                "Invalid CORS origin {}, expected e.g. https://example.com",
+                origin
+            ));
+        }
+        if self
+            .admin_token
+            .as_deref()
+            .map_or(false, |token| token.trim().is_empty())
+        {
+            return Err("The admin token can not be empty".to_string());
+        }
+        if let Some(host) = self
+            .webhook_allowed_hosts
+            .iter()
+            .chain(self.webhook_denied_hosts.iter())
+            .find(|host| host.trim().is_empty() || host.contains(['/', ':']))
+        {
+            return Err(format!(
+                "Invalid webhook host {:?}, expected e.g. hooks.example.com or *.example.com",
+                host
+            ));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err("tls_cert and tls_key have to be set together".to_string());
+        }
+        if let Some(redirect_port) = self.http_redirect_port {
+            if self.tls_cert.is_none() {
+                return Err("http_redirect_port requires tls_cert and tls_key".to_string());
+            }
+            if redirect_port == 0 || redirect_port == self.port {
+                return Err(format!(
+                    "Invalid http_redirect_port {}, it must differ from the port",
+                    redirect_port
+                ));
+            }
+        }
+        if self.db_path.is_empty() {
+            return Err("The database path can not be empty".to_string());
+        }
+        if self.batch_size == 0 || self.concurrency == 0 || self.max_retries == 0 {
+            return Err("batch_size, concurrency and max_retries must be at least 1".to_string());
+        }
+        if self.catch_up_interval == 0 {
+            return Err("catch_up_interval must be at least 1".to_string());
+        }
+        if self.secondary_db_path.as_deref() == Some(self.db_path.as_str()) {
+            return Err(
+                "The secondary database path must differ from the database path".to_string(),
+            );
+        }
+        if self.msg_types.is_empty() {
+            return Err("At least one message type has to be enabled".to_string());
+        }
+        if let Some(unknown) = self
+            .msg_types
+            .iter()
+            .find(|t| !MSG_TYPES.contains(&t.as_str()))
+        {
+            return Err(format!(
+                "Unknown message type {}, expected one of {}",
+                unknown,
+                MSG_TYPES.join(",")
+            ));
+        }
+        if let (Some(start), Some(end)) = (self.start_height, self.end_height) {
+            if start > end {
+                return Err(format!(
+                    "The start height {} is after the end height {}",
+                    start, end
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn sync_options(&self) -> SyncOptions {
+        SyncOptions {
+            test_mode: self.test_mode,
+            test_block_limit: self.test_block_limit,
+            start_height: self.start_height,
+            end_height: self.end_height,
+            batch_size: self.batch_size,
+            concurrency: self.concurrency,
+            max_retries: self.max_retries,
+            msg_types: self.msg_types.clone(),
+        }
+    }
+
+    /// where `serve` keeps its secondary instance, each replica needs its own
+    pub fn secondary_db_path(&self) -> String {
+        self.secondary_db_path.clone().unwrap_or_else(|| {
+            env::temp_dir()
+                .join(format!("cosmos-indexer-secondary-{}", process::id()))
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+
+    pub fn access_control(&self) -> AccessControl {
+        AccessControl {
+            require_api_key: self.require_api_key,
+            trust_forwarded_for: self.trust_forwarded_for,
+            ip_rate_limit: self.ip_rate_limit,
+            ip_daily_quota: self.ip_daily_quota,
+            key_rate_limit: self.key_rate_limit,
+            key_daily_quota: self.key_daily_quota,
+            admin_token: self.admin_token.clone(),
+        }
+    }
+
+    pub fn webhook_targets(&self) -> WebhookTargets {
+        WebhookTargets {
+            allowed_hosts: self.webhook_allowed_hosts.clone(),
+            denied_hosts: self.webhook_denied_hosts.clone(),
+            allow_private: self.webhook_allow_private,
+        }
+    }
+
+    pub fn readiness_options(&self) -> ReadinessOptions {
+        ReadinessOptions {
+            max_lag_blocks: self.ready_max_lag,
+            max_idle: Duration::from_secs(self.ready_max_idle),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(flags: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("cosmos-indexer").chain(flags.iter().copied()))
+    }
+
+    /// loads `flags` on top of a config file with `contents`
+    fn load_with_file(contents: &str, flags: &[&str]) -> Result<Config, String> {
+        let path = env::temp_dir().join(format!(
+            "indexer-config-{:016x}.toml",
+            rand::random::<u64>()
+        ));
+        fs::write(&path, contents).unwrap();
+        let mut all_flags = vec!["--config", path.to_str().unwrap()];
+        all_flags.extend(flags);
+        let config = Config::load(&args(&all_flags).unwrap());
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn default_config() -> Config {
+        Config::load(&args(&[]).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn flags_win_over_the_file() {
+        let config = load_with_file(
+            "port = 8000\nbatch_size = 100\ntest_mode = true\nmsg_types = [\"msgSend\"]\n",
+            &["--port", "9100", "--require-api-key"],
+        )
+        .unwrap();
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.batch_size, 100);
+        assert!(config.test_mode);
+        assert!(config.require_api_key);
+        assert_eq!(config.msg_types, vec!["msgSend".to_string()]);
+        assert_eq!(config.concurrency, DEFAULT_CONCURRENCY);
+    }
+
+    #[test]
+    fn rejects_unknown_and_invalid_file_settings() {
+        assert!(load_with_file("prot = 8000\n", &[]).is_err());
+        assert!(load_with_file("batch_size = 0\n", &[]).is_err());
+        assert!(load_with_file("port = \"8000\"\n", &[]).is_err());
+    }
+
+    #[test]
+    fn on_off_settings_are_flags() {
+        let config =
+            Config::load(&args(&["--test-mode", "--trust-forwarded-for"]).unwrap()).unwrap();
+        assert!(config.test_mode);
+        assert!(config.trust_forwarded_for);
+        assert!(args(&["--test-mode", "true"]).is_err());
+    }
+
+    #[test]
+    fn environment_variables_turn_flags_on() {
+        // not asserted by any other test, they run in parallel
+        env::set_var("WEBHOOK_ALLOW_PRIVATE", "1");
+        assert!(default_config().webhook_allow_private);
+        env::set_var("WEBHOOK_ALLOW_PRIVATE", "true");
+        assert!(default_config().webhook_allow_private);
+        env::set_var("WEBHOOK_ALLOW_PRIVATE", "false");
+        assert!(!default_config().webhook_allow_private);
+        env::remove_var("WEBHOOK_ALLOW_PRIVATE");
+    }
+
+    #[test]
+    fn validates_the_merged_settings() {
+        assert!(default_config().validate().is_ok());
+
+        let invalid: Vec<fn(&mut Config)> = vec![
+            |c| c.chain_node_grpc = Vec::new(),
+            |c| c.chain_node_grpc = vec!["node:9090".parse().unwrap()],
+            |c| c.chain_node_rpc = Some("http://node:26657".to_string()),
+            |c| c.grpc_port = Some(c.port),
+            |c| c.cors_origins = vec!["example.com".to_string()],
+            |c| c.admin_token = Some(" ".to_string()),
+            |c| c.webhook_denied_hosts = vec!["hooks.example.com:443".to_string()],
+            |c| c.tls_cert = Some("cert.pem".to_string()),
+            |c| c.http_redirect_port = Some(8080),
+            |c| c.concurrency = 0,
+            |c| c.secondary_db_path = Some(c.db_path.clone()),
+            |c| c.msg_types = vec!["msgDelegate".to_string()],
+            |c| {
+                c.start_height = Some(10);
+                c.end_height = Some(5);
+            },
+        ];
+        for (i, invalidate) in invalid.iter().enumerate() {
+            let mut config = default_config();
+            invalidate(&mut config);
+            assert!(config.validate().is_err(), "case {} was accepted", i);
+        }
+    }
+}
@@ -0,0 +1,914 @@
+use actix_web::HttpResponse;
+use async_graphql::connection::{Connection, Edge};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Enum, Error, ErrorExtensions, InputObject,
+    Interface, Object, Result, Schema, SimpleObject,
+};
+use chrono_tz::Tz;
+use log::error;
+use rocksdb::DB;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::transactions::query::{self, FilterError, FilterParams, TxFilter};
+use crate::types::{
+    CustomCoin, CustomHeight, CustomMsgSend, CustomMsgTransfer, StoredMsg,
+    Transaction as IndexedMsg,
+};
+
+pub type IndexerSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+// connections below another object, e.g. the messages of every sender in a
+// page of messages, each load a page of their own
+const MAX_NESTED_PAGE_SIZE: usize = 20;
+
+// a connection costs its page size times the fields of each node, a field
+// scanning every indexed message costs this much on its own
+const FULL_SCAN_COMPLEXITY: usize = 1_000;
+const MAX_COMPLEXITY: usize = 5_000;
+
+pub fn build_schema(db: Arc<DB>) -> IndexerSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(db)
+        // nested lookups each read the database, keep queries bounded
+        .limit_depth(10)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+pub fn graphiql_page() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+fn database_error(e: rocksdb::Error) -> Error {
+    error!("Database error: {}", e);
+    Error::new("The database could not be read.")
+        .extend_with(|_, e| e.set("code", "internal_error"))
+}
+
+fn filter_error(e: FilterError) -> Error {
+    Error::new(e.to_string()).extend_with(|_, ext| ext.set("code", e.code()))
+}
+
+fn time_zone(tz: Option<&str>) -> Result<Tz> {
+    match tz {
+        Some(name) => query::parse_tz(name).map_err(filter_error),
+        None => Ok(Tz::UTC),
+    }
+}
+
+fn page_size(first: Option<i32>, max: usize) -> Result<usize> {
+    match first {
+        None => Ok(DEFAULT_PAGE_SIZE.min(max)),
+        Some(first) if first < 0 => Err(Error::new("first can't be negative")
+            .extend_with(|_, e| e.set("code", "invalid_page_size"))),
+        Some(first) => Ok((first as usize).min(max)),
+    }
+}
+
+/// the complexity of a connection, every node of the page counts
+fn connection_complexity(first: Option<i32>, max: usize, child_complexity: usize) -> usize {
+    let size = page_size(first, max).unwrap_or(0);
+    size.max(1)
+        .saturating_mul(child_complexity)
+        .saturating_add(1)
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    Send,
+    Receive,
+}
+
+impl From<TransferDirection> for query::Direction {
+    fn from(direction: TransferDirection) -> Self {
+        match direction {
+            TransferDirection::Send => query::Direction::Send,
+            TransferDirection::Receive => query::Direction::Receive,
+        }
+    }
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl From<SortOrder> for query::Order {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Asc => query::Order::Asc,
+            SortOrder::Desc => query::Order::Desc,
+        }
+    }
+}
+
+/// The filters of `/v1/transactions`
+#[derive(InputObject, Debug, Clone, Default)]
+pub struct MessageFilter {
+    pub address: Option<String>,
+    /// only with an address, whether it sent or received the messages
+    pub direction: Option<TransferDirection>,
+    /// `msgSend` or `msgIbcTransfer`
+    #[graphql(name = "type")]
+    pub msg_type: Option<String>,
+    pub denom: Option<String>,
+    /// IANA zone for the timestamps and the dates, UTC if unset
+    pub tz: Option<String>,
+    /// first and last day to include, `YYYY-MM-DD`
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl From<MessageFilter> for FilterParams {
+    fn from(filter: MessageFilter) -> Self {
+        FilterParams {
+            address: filter.address,
+            direction: filter.direction.map(query::Direction::from),
+            msg_type: filter.msg_type,
+            denom: filter.denom,
+            tz: filter.tz,
+            from: filter.from,
+            to: filter.to,
+        }
+    }
+}
+
+/// a page of at most `max` messages, newest first unless ordered otherwise
+fn message_connection(
+    db: &DB,
+    params: FilterParams,
+    order: Option<SortOrder>,
+    first: Option<i32>,
+    max: usize,
+    after: Option<String>,
+) -> Result<Connection<String, Message>> {
+    let (filter, tz) = params.into_filter().map_err(filter_error)?;
+    let page = query::load_page(
+        db,
+        &filter,
+        order.unwrap_or(SortOrder::Desc).into(),
+        tz,
+        after.as_deref(),
+        page_size(first, max)?,
+    )
+    .map_err(database_error)?;
+
+    let mut connection = Connection::new(after.is_some(), page.has_more);
+    connection.edges.extend(
+        page.transactions
+            .into_iter()
+            .map(|(cursor, msg)| Edge::new(cursor, Message::new(msg, tz))),
+    );
+    Ok(connection)
+}
+
+#[derive(SimpleObject, Debug, Clone)]
+pub struct Coin {
+    pub denom: String,
+    pub amount: String,
+}
+
+impl From<&CustomCoin> for Coin {
+    fn from(coin: &CustomCoin) -> Self {
+        Coin {
+            denom: coin.denom.clone(),
+            amount: coin.amount.clone(),
+        }
+    }
+}
+
+#[derive(SimpleObject, Debug, Clone)]
+pub struct IbcHeight {
+    pub revision_number: u64,
+    pub revision_height: u64,
+}
+
+impl From<&CustomHeight> for IbcHeight {
+    fn from(height: &CustomHeight) -> Self {
+        IbcHeight {
+            revision_number: height.revision_number,
+            revision_height: height.revision_height,
+        }
+    }
+}
+
+/// the fields every message has
+#[derive(Debug, Clone)]
+struct MessageInfo {
+    height: u64,
+    tx_hash: String,
+    msg_index: u32,
+    msg_type: String,
+    timestamp: String,
+    unix_time: i64,
+    tz: Tz,
+}
+
+impl MessageInfo {
+    async fn transaction(&self, db: &DB) -> Result<Option<Transaction>> {
+        let msgs = query::load_block(db, self.height, self.tz).map_err(database_error)?;
+        Ok(Transaction::from_block(msgs, self.tz)
+            .into_iter()
+            .find(|transaction| transaction.hash == self.tx_hash))
+    }
+}
+
+/// A single indexed message
+#[derive(Interface, Debug, Clone)]
+#[graphql(
+    field(name = "height", ty = "u64"),
+    field(name = "tx_hash", ty = "String"),
+    field(
+        name = "msg_index",
+        ty = "u32",
+        desc = "position of the message in its transaction"
+    ),
+    field(name = "msg_type", ty = "String"),
+    field(name = "timestamp", ty = "String", desc = "block time in RFC 3339"),
+    field(name = "unix_time", ty = "i64"),
+    field(name = "coins", ty = "Vec<Coin>", desc = "the coins the message moves"),
+    field(name = "sender", ty = "Address"),
+    field(name = "receiver", ty = "Address"),
+    field(name = "transaction", ty = "Option<Transaction>")
+)]
+pub enum Message {
+    MsgSend(MsgSend),
+    MsgTransfer(MsgTransfer),
+}
+
+impl Message {
+    fn new(msg: IndexedMsg, tz: Tz) -> Message {
+        let info = MessageInfo {
+            height: msg.height,
+            tx_hash: msg.tx_hash,
+            msg_index: msg.msg_index,
+            msg_type: msg.msg_type,
+            timestamp: msg.timestamp,
+            unix_time: msg.unix_time,
+            tz,
+        };
+        match msg.data {
+            StoredMsg::Send(msg) => Message::MsgSend(MsgSend { info, msg }),
+            StoredMsg::IbcTransfer(msg) => Message::MsgTransfer(MsgTransfer { info, msg }),
+        }
+    }
+
+    fn info(&self) -> &MessageInfo {
+        match self {
+            Message::MsgSend(msg) => &msg.info,
+            Message::MsgTransfer(msg) => &msg.info,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MsgSend {
+    info: MessageInfo,
+    msg: CustomMsgSend,
+}
+
+#[Object]
+impl MsgSend {
+    async fn height(&self) -> u64 {
+        self.info.height
+    }
+
+    async fn tx_hash(&self) -> String {
+        self.info.tx_hash.clone()
+    }
+
+    async fn msg_index(&self) -> u32 {
+        self.info.msg_index
+    }
+
+    async fn msg_type(&self) -> String {
+        self.info.msg_type.clone()
+    }
+
+    async fn timestamp(&self) -> String {
+        self.info.timestamp.clone()
+    }
+
+    async fn unix_time(&self) -> i64 {
+        self.info.unix_time
+    }
+
+    async fn coins(&self) -> Vec<Coin> {
+        self.msg.amount.iter().map(Coin::from).collect()
+    }
+
+    async fn sender(&self) -> Address {
+        Address::new(&self.msg.from_address, self.info.tz)
+    }
+
+    async fn receiver(&self) -> Address {
+        Address::new(&self.msg.to_address, self.info.tz)
+    }
+
+    async fn transaction(&self, ctx: &Context<'_>) -> Result<Option<Transaction>> {
+        self.info.transaction(ctx.data::<Arc<DB>>()?).await
+    }
+
+    async fn from_address(&self) -> &str {
+        &self.msg.from_address
+    }
+
+    async fn to_address(&self) -> &str {
+        &self.msg.to_address
+    }
+
+    async fn amount(&self) -> Vec<Coin> {
+        self.msg.amount.iter().map(Coin::from).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MsgTransfer {
+    info: MessageInfo,
+    msg: CustomMsgTransfer,
+}
+
+#[Object]
+impl MsgTransfer {
+    async fn height(&self) -> u64 {
+        self.info.height
+    }
+
+    async fn tx_hash(&self) -> String {
+        self.info.tx_hash.clone()
+    }
+
+    async fn msg_index(&self) -> u32 {
+        self.info.msg_index
+    }
+
+    async fn msg_type(&self) -> String {
+        self.info.msg_type.clone()
+    }
+
+    async fn timestamp(&self) -> String {
+        self.info.timestamp.clone()
+    }
+
+    async fn unix_time(&self) -> i64 {
+        self.info.unix_time
+    }
+
+    async fn coins(&self) -> Vec<Coin> {
+        self.msg.token.iter().map(Coin::from).collect()
+    }
+
+    async fn sender(&self) -> Address {
+        Address::new(&self.msg.sender, self.info.tz)
+    }
+
+    /// the address on the counterparty chain
+    async fn receiver(&self) -> Address {
+        Address::new(&self.msg.receiver, self.info.tz)
+    }
+
+    async fn transaction(&self, ctx: &Context<'_>) -> Result<Option<Transaction>> {
+        self.info.transaction(ctx.data::<Arc<DB>>()?).await
+    }
+
+    async fn source_port(&self) -> &str {
+        &self.msg.source_port
+    }
+
+    async fn source_channel(&self) -> &str {
+        &self.msg.source_channel
+    }
+
+    async fn token(&self) -> Vec<Coin> {
+        self.msg.token.iter().map(Coin::from).collect()
+    }
+
+    async fn timeout_height(&self) -> Option<IbcHeight> {
+        self.msg.timeout_height.as_ref().map(IbcHeight::from)
+    }
+
+    /// nanoseconds since the epoch, as a string since it doesn't fit a
+    /// GraphQL Int
+    async fn timeout_timestamp(&self) -> String {
+        self.msg.timeout_timestamp.to_string()
+    }
+}
+
+/// A transaction with the messages that were indexed from it
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    hash: String,
+    height: u64,
+    timestamp: String,
+    unix_time: i64,
+    messages: Vec<Message>,
+    tz: Tz,
+}
+
+impl Transaction {
+    /// groups the messages of a block by transaction, in order of their
+    /// first message
+    fn from_block(msgs: Vec<IndexedMsg>, tz: Tz) -> Vec<Transaction> {
+        let mut transactions: Vec<Transaction> = Vec::new();
+        for msg in msgs {
+            let message = Message::new(msg, tz);
+            let info = message.info().clone();
+            match transactions
+                .iter_mut()
+                .find(|transaction| transaction.hash == info.tx_hash)
+            {
+                Some(transaction) => transaction.messages.push(message),
+                None => transactions.push(Transaction {
+                    hash: info.tx_hash,
+                    height: info.height,
+                    timestamp: info.timestamp,
+                    unix_time: info.unix_time,
+                    messages: vec![message],
+                    tz,
+                }),
+            }
+        }
+        for transaction in transactions.iter_mut() {
+            transaction
+                .messages
+                .sort_by_key(|message| message.info().msg_index);
+        }
+        transactions
+    }
+}
+
+#[Object]
+impl Transaction {
+    async fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    async fn height(&self) -> u64 {
+        self.height
+    }
+
+    async fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    async fn unix_time(&self) -> i64 {
+        self.unix_time
+    }
+
+    async fn messages(&self) -> Vec<Message> {
+        self.messages.clone()
+    }
+
+    async fn block(&self, ctx: &Context<'_>) -> Result<Option<Block>> {
+        let msgs = query::load_block(ctx.data::<Arc<DB>>()?, self.height, self.tz)
+            .map_err(database_error)?;
+        Ok(Block::new(msgs, self.tz))
+    }
+}
+
+/// A block with indexed messages, blocks without any aren't stored
+#[derive(Debug, Clone)]
+pub struct Block {
+    height: u64,
+    timestamp: String,
+    unix_time: i64,
+    transactions: Vec<Transaction>,
+}
+
+impl Block {
+    fn new(msgs: Vec<IndexedMsg>, tz: Tz) -> Option<Block> {
+        let first = msgs.first()?;
+        let (height, timestamp, unix_time) =
+            (first.height, first.timestamp.clone(), first.unix_time);
+        Some(Block {
+            height,
+            timestamp,
+            unix_time,
+            transactions: Transaction::from_block(msgs, tz),
+        })
+    }
+}
+
+#[Object]
+impl Block {
+    async fn height(&self) -> u64 {
+        self.height
+    }
+
+    async fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    async fn unix_time(&self) -> i64 {
+        self.unix_time
+    }
+
+    async fn transactions(&self) -> Vec<Transaction> {
+        self.transactions.clone()
+    }
+
+    async fn messages(&self) -> Vec<Message> {
+        self.transactions
+            .iter()
+            .flat_map(|transaction| transaction.messages.clone())
+            .collect()
+    }
+}
+
+/// An address that sent or received messages
+#[derive(Debug, Clone)]
+pub struct Address {
+    address: String,
+    tz: Tz,
+}
+
+impl Address {
+    fn new(address: &str, tz: Tz) -> Address {
+        Address {
+            address: address.to_string(),
+            tz,
+        }
+    }
+}
+
+#[Object]
+impl Address {
+    async fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// messages the address sent or received, the address of the filter is
+    /// ignored. At most 20 per page, the top level `messages` pages up to 100.
+    #[graphql(complexity = "connection_complexity(first, MAX_NESTED_PAGE_SIZE, child_complexity)")]
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<MessageFilter>,
+        order: Option<SortOrder>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Message>> {
+        let mut params = FilterParams::from(filter.unwrap_or_default());
+        params.address = Some(self.address.clone());
+        if params.tz.is_none() {
+            params.tz = Some(self.tz.name().to_string());
+        }
+        message_connection(
+            ctx.data::<Arc<DB>>()?,
+            params,
+            order,
+            first,
+            MAX_NESTED_PAGE_SIZE,
+            after,
+        )
+    }
+}
+
+/// A denom moved by indexed messages
+#[derive(Debug, Clone)]
+pub struct Denom {
+    denom: String,
+    /// known when listing the denoms, counted when asked for otherwise
+    message_count: Option<u64>,
+}
+
+#[Object]
+impl Denom {
+    async fn denom(&self) -> &str {
+        &self.denom
+    }
+
+    async fn message_count(&self, ctx: &Context<'_>) -> Result<u64> {
+        if let Some(count) = self.message_count {
+            return Ok(count);
+        }
+        let mut count = 0;
+        query::for_each_transaction(
+            ctx.data::<Arc<DB>>()?,
+            &denom_filter(&self.denom),
+            Tz::UTC,
+            |_| count += 1,
+        )
+        .map_err(database_error)?;
+        Ok(count)
+    }
+
+    /// messages moving the denom, the denom of the filter is ignored. At most
+    /// 20 per page, the top level `messages` pages up to 100.
+    #[graphql(complexity = "connection_complexity(first, MAX_NESTED_PAGE_SIZE, child_complexity)")]
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<MessageFilter>,
+        order: Option<SortOrder>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Message>> {
+        let mut params = FilterParams::from(filter.unwrap_or_default());
+        params.denom = Some(self.denom.clone());
+        message_connection(
+            ctx.data::<Arc<DB>>()?,
+            params,
+            order,
+            first,
+            MAX_NESTED_PAGE_SIZE,
+            after,
+        )
+    }
+}
+
+/// counts the messages moving each denom, only the counts are held in
+/// memory
+fn load_denoms(db: &DB) -> Result<Vec<Denom>> {
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    query::for_each_transaction(db, &TxFilter::default(), Tz::UTC, |msg| {
+        for coin in msg.data.coins() {
+            *counts.entry(coin.denom.clone()).or_insert(0) += 1;
+        }
+    })
+    .map_err(database_error)?;
+    Ok(counts
+        .into_iter()
+        .map(|(denom, message_count)| Denom {
+            denom,
+            message_count: Some(message_count),
+        })
+        .collect())
+}
+
+fn denom_filter(denom: &str) -> TxFilter {
+    TxFilter {
+        denom: Some(denom.to_string()),
+        ..TxFilter::default()
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn block(
+        &self,
+        ctx: &Context<'_>,
+        height: u64,
+        tz: Option<String>,
+    ) -> Result<Option<Block>> {
+        let tz = time_zone(tz.as_deref())?;
+        let msgs = query::load_block(ctx.data::<Arc<DB>>()?, height, tz).map_err(database_error)?;
+        Ok(Block::new(msgs, tz))
+    }
+
+    /// blocks with indexed messages, newest first unless ordered otherwise.
+    /// The cursors are block heights.
+    #[graphql(complexity = "connection_complexity(first, MAX_PAGE_SIZE, child_complexity)")]
+    async fn blocks(
+        &self,
+        ctx: &Context<'_>,
+        order: Option<SortOrder>,
+        first: Option<i32>,
+        after: Option<String>,
+        tz: Option<String>,
+    ) -> Result<Connection<String, Block>> {
+        let tz = time_zone(tz.as_deref())?;
+        let after_height = match &after {
+            Some(cursor) => Some(cursor.parse::<u64>().map_err(|_| {
+                Error::new(format!("Invalid cursor {}", cursor))
+                    .extend_with(|_, e| e.set("code", "invalid_cursor"))
+            })?),
+            None => None,
+        };
+        let (blocks, has_more) = query::load_blocks(
+            ctx.data::<Arc<DB>>()?,
+            order.unwrap_or(SortOrder::Desc).into(),
+            tz,
+            after_height,
+            page_size(first, MAX_PAGE_SIZE)?,
+        )
+        .map_err(database_error)?;
+
+        let mut connection = Connection::new(after.is_some(), has_more);
+        connection.edges.extend(
+            blocks
+                .into_iter()
+                .filter_map(|msgs| Block::new(msgs, tz))
+                .map(|block| Edge::new(block.height.to_string(), block)),
+        );
+        Ok(connection)
+    }
+
+    /// looks a transaction up by hash
+    async fn transaction(
+        &self,
+        ctx: &Context<'_>,
+        hash: String,
+        tz: Option<String>,
+    ) -> Result<Option<Transaction>> {
+        let tz = time_zone(tz.as_deref())?;
+        let msgs = query::load_tx(ctx.data::<Arc<DB>>()?, &hash, tz).map_err(database_error)?;
+        Ok(Transaction::from_block(msgs, tz).into_iter().next())
+    }
+
+    #[graphql(complexity = "connection_complexity(first, MAX_PAGE_SIZE, child_complexity)")]
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<MessageFilter>,
+        order: Option<SortOrder>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Message>> {
+        let params = FilterParams::from(filter.unwrap_or_default());
+        message_connection(
+            ctx.data::<Arc<DB>>()?,
+            params,
+            order,
+            first,
+            MAX_PAGE_SIZE,
+            after,
+        )
+    }
+
+    async fn address(&self, address: String, tz: Option<String>) -> Result<Address> {
+        Ok(Address::new(&address, time_zone(tz.as_deref())?))
+    }
+
+    /// every denom moved by an indexed message, this scans every message.
+    /// The fields of the denoms count as if there were a page of them.
+    #[graphql(complexity = "FULL_SCAN_COMPLEXITY + MAX_NESTED_PAGE_SIZE * child_complexity")]
+    async fn denoms(&self, ctx: &Context<'_>) -> Result<Vec<Denom>> {
+        load_denoms(ctx.data::<Arc<DB>>()?)
+    }
+
+    #[graphql(complexity = "FULL_SCAN_COMPLEXITY + child_complexity")]
+    /// the denom if any message moves it, the scan stops at the first one.
+    /// Only `messageCount` reads every message.
+    async fn denom(&self, ctx: &Context<'_>, denom: String) -> Result<Option<Denom>> {
+        let moved = query::any_transaction(ctx.data::<Arc<DB>>()?, &denom_filter(&denom))
+            .map_err(database_error)?;
+        if !moved {
+            return Ok(None);
+        }
+        Ok(Some(Denom {
+            denom,
+            message_count: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    use crate::transactions::database::tests::{seed, send_msg, TestDb};
+
+    const ALICE: &str = "manifest1alice";
+    const BOB: &str = "manifest1bob";
+
+    /// five sends from alice to bob in blocks 1 to 5
+    fn schema(test: &TestDb) -> IndexerSchema {
+        seed(
+            &test.db,
+            (1..=5)
+                .map(|height| {
+                    send_msg(
+                        height,
+                        &format!("TX{}", height),
+                        0,
+                        ALICE,
+                        BOB,
+                        "10",
+                        "umfx",
+                    )
+                })
+                .collect(),
+        );
+        build_schema(test.db.clone())
+    }
+
+    fn data(response: async_graphql::Response) -> Value {
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        serde_json::to_value(&response.data).unwrap()
+    }
+
+    fn heights(page: &Value) -> Vec<u64> {
+        page["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|edge| edge["node"]["height"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pages_messages_with_cursors() {
+        let test = TestDb::new();
+        let schema = schema(&test);
+        let page_query = |after: Option<&str>| {
+            let after = after.map_or(String::new(), |cursor| format!(", after: \"{}\"", cursor));
+            format!(
+                "{{ messages(first: 2{}) {{ edges {{ node {{ height }} }} pageInfo {{ hasNextPage endCursor }} }} }}",
+                after
+            )
+        };
+
+        let mut seen = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let page = data(schema.execute(&page_query(after.as_deref())).await);
+            let messages = &page["messages"];
+            seen.extend(heights(messages));
+            if !messages["pageInfo"]["hasNextPage"].as_bool().unwrap() {
+                break;
+            }
+            after = Some(
+                messages["pageInfo"]["endCursor"]
+                    .as_str()
+                    .unwrap()
+                    .to_string(),
+            );
+        }
+        assert_eq!(seen, vec![5, 4, 3, 2, 1]);
+
+        let page = data(
+            schema
+                .execute("{ blocks(first: 2, order: ASC) { edges { cursor } } }")
+                .await,
+        );
+        let cursors: Vec<&str> = page["blocks"]["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|edge| edge["cursor"].as_str().unwrap())
+            .collect();
+        assert_eq!(cursors, vec!["1", "2"]);
+        let page = data(
+            schema
+                .execute("{ blocks(first: 2, order: ASC, after: \"2\") { edges { cursor } } }")
+                .await,
+        );
+        assert_eq!(page["blocks"]["edges"][0]["cursor"], json!("3"));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_cursors_and_page_sizes() {
+        let test = TestDb::new();
+        let schema = schema(&test);
+        let response = schema
+            .execute("{ blocks(after: \"abc\") { edges { cursor } } }")
+            .await;
+        assert_eq!(response.errors.len(), 1);
+        let response = schema
+            .execute("{ messages(first: -1) { edges { cursor } } }")
+            .await;
+        assert_eq!(response.errors.len(), 1);
+        // larger pages are cut to the maximum
+        let page = data(
+            schema
+                .execute("{ messages(first: 1000) { edges { cursor } } }")
+                .await,
+        );
+        assert_eq!(page["messages"]["edges"].as_array().unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn rejects_queries_over_the_complexity_limit() {
+        let test = TestDb::new();
+        let schema = schema(&test);
+        // 100 messages with 20 messages of their sender each
+        let nested = "{ messages(first: 100) { edges { node { sender { messages(first: 20) { edges { node { height txHash } } } } } } } }";
+        let response = schema.execute(nested).await;
+        assert_eq!(response.errors.len(), 1);
+        assert!(response.errors[0].message.contains("complex"));
+
+        let scans = "{ a: denoms { denom } b: denoms { denom } c: denoms { denom } d: denoms { denom } e: denoms { denom } }";
+        assert_eq!(schema.execute(scans).await.errors.len(), 1);
+
+        let small = "{ messages(first: 5) { edges { node { sender { messages(first: 5) { edges { node { height } } } } } } } }";
+        data(schema.execute(small).await);
+    }
+
+    #[tokio::test]
+    async fn looks_up_a_denom() {
+        let test = TestDb::new();
+        let schema = schema(&test);
+        let page = data(
+            schema.execute("{ denom(denom: \"umfx\") { denom messageCount } missing: denom(denom: \"uatom\") { denom } }").await,
+        );
+        assert_eq!(page["denom"], json!({ "denom": "umfx", "messageCount": 5 }));
+        assert_eq!(page["missing"], Value::Null);
+
+        let page = data(schema.execute("{ denoms { denom messageCount } }").await);
+        assert_eq!(
+            page["denoms"],
+            json!([{ "denom": "umfx", "messageCount": 5 }])
+        );
+    }
+}
@@ -0,0 +1,515 @@
+use cosmos_sdk_proto_althea::cosmos::base::tendermint::v1beta1::{
+    service_client::ServiceClient, GetBlockByHeightRequest, GetLatestBlockRequest,
+    GetSyncingRequest,
+};
+use cosmos_sdk_proto_althea::tendermint::types::Block;
+use deep_space::client::ChainStatus;
+use deep_space::error::CosmosGrpcError;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::{Ascii, MetadataKey, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+use tonic::{Code, Request, Status};
+
+/// TLS settings and extra metadata headers for a single gRPC endpoint, set in
+/// the config file under `[grpc_endpoints."<url>"]`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointSettings {
+    /// PEM CA certificate to trust instead of the system roots
+    pub ca_cert: Option<String>,
+    /// PEM client certificate and key for mutual TLS
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// name to verify the server certificate against when it differs from
+    /// the host in the url
+    pub tls_domain: Option<String>,
+    /// sent with every request to this endpoint, on top of the global headers
+    #[serde(default, serialize_with = "serialize_redacted")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl EndpointSettings {
+    pub fn uses_tls(&self) -> bool {
+        self.ca_cert.is_some() || self.client_cert.is_some() || self.tls_domain.is_some()
+    }
+}
+
+/// header values are usually API keys, they are never printed
+pub fn serialize_redacted<S: Serializer>(
+    headers: &BTreeMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let redacted: BTreeMap<&str, &str> = headers
+        .keys()
+        .map(|name| (name.as_str(), "<redacted>"))
+        .collect();
+    redacted.serialize(serializer)
+}
+
+/// parses `name: value` as passed to --grpc-header
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid header {}, expected `name: value`", header))?;
+    Ok((name.trim().to_lowercase(), value.trim().to_string()))
+}
+
+/// Adds the configured metadata to every request
+#[derive(Clone)]
+pub struct HeaderInterceptor {
+    headers: Vec<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
+}
+
+impl HeaderInterceptor {
+    pub fn new(headers: &BTreeMap<String, String>) -> Result<Self, String> {
+        let mut parsed = Vec::new();
+        for (name, value) in headers {
+            let key = MetadataKey::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid gRPC header name {}", name))?;
+            let value = MetadataValue::from_str(value)
+                .map_err(|_| format!("Invalid value for gRPC header {}", name))?;
+            parsed.push((key, value));
+        }
+        Ok(HeaderInterceptor { headers: parsed })
+    }
+}
+
+impl Interceptor for HeaderInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        for (key, value) in &self.headers {
+            request.metadata_mut().insert(key.clone(), value.clone());
+        }
+        Ok(request)
+    }
+}
+
+// blocks requested at once by get_block_range, the historical sync already
+// runs `concurrency` ranges in parallel
+const BLOCK_REQUESTS_IN_FLIGHT: usize = 20;
+
+type TendermintClient = ServiceClient<InterceptedService<Channel, HeaderInterceptor>>;
+
+/// A client for the Tendermint gRPC service of one endpoint, the channel is
+/// connected lazily and shared between clones
+#[derive(Clone)]
+pub struct GrpcClient {
+    client: TendermintClient,
+}
+
+impl GrpcClient {
+    pub fn new(
+        url: &str,
+        settings: &EndpointSettings,
+        headers: &BTreeMap<String, String>,
+        timeout: Duration,
+    ) -> Result<GrpcClient, CosmosGrpcError> {
+        let mut endpoint = Channel::from_shared(url.to_string())
+            .map_err(|e| CosmosGrpcError::BadInput(format!("Invalid url {}: {}", url, e)))?
+            .timeout(timeout)
+            .connect_timeout(timeout);
+
+        if url.starts_with("https://") {
+            endpoint = endpoint
+                .tls_config(tls_config(settings)?)
+                .map_err(|e| CosmosGrpcError::BadInput(format!("Invalid TLS settings: {}", e)))?;
+        }
+
+        // endpoint specific headers win over the global ones
+        let mut all_headers = headers.clone();
+        all_headers.extend(settings.headers.clone());
+        let interceptor =
+            HeaderInterceptor::new(&all_headers).map_err(CosmosGrpcError::BadInput)?;
+
+        Ok(GrpcClient {
+            client: ServiceClient::with_interceptor(endpoint.connect_lazy(), interceptor),
+        })
+    }
+
+    /// returns `None` if the node doesn't have the block, because it was
+    /// pruned or isn't produced yet
+    pub async fn get_block(&self, height: u64) -> Result<Option<Block>, CosmosGrpcError> {
+        let mut client = self.client.clone();
+        let request = GetBlockByHeightRequest {
+            height: height as i64,
+        };
+        match client.get_block_by_height(request).await {
+            Ok(response) => Ok(response.into_inner().block),
+            Err(status) if matches!(status.code(), Code::InvalidArgument | Code::NotFound) => {
+                Ok(None)
+            }
+            Err(status) => Err(request_error(status)),
+        }
+    }
+
+    /// fetches `start..=end` over the shared channel, a limited number of
+    /// blocks at a time, in height order
+    pub async fn get_block_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<Option<Block>>, CosmosGrpcError> {
+        stream::iter(start..=end)
+            .map(|height| self.get_block(height))
+            .buffered(BLOCK_REQUESTS_IN_FLIGHT)
+            .try_collect()
+            .await
+    }
+
+    pub async fn get_chain_status(&self) -> Result<ChainStatus, CosmosGrpcError> {
+        let mut client = self.client.clone();
+        let syncing = client
+            .get_syncing(GetSyncingRequest {})
+            .await
+            .map_err(request_error)?
+            .into_inner()
+            .syncing;
+        if syncing {
+            return Ok(ChainStatus::Syncing);
+        }
+        let latest = client
+            .get_latest_block(GetLatestBlockRequest {})
+            .await
+            .map_err(request_error)?
+            .into_inner();
+        match latest.block.and_then(|block| block.header) {
+            Some(header) => Ok(ChainStatus::Moving {
+                block_height: header.height as u64,
+            }),
+            None => Ok(ChainStatus::WaitingToStart),
+        }
+    }
+}
+
+fn tls_config(settings: &EndpointSettings) -> Result<ClientTlsConfig, CosmosGrpcError> {
+    let read = |path: &str| {
+        fs::read(path)
+            .map_err(|e| CosmosGrpcError::BadInput(format!("Failed to read {}: {}", path, e)))
+    };
+    let mut config = ClientTlsConfig::new();
+    if let Some(ca_cert) = &settings.ca_cert {
+        config = config.ca_certificate(Certificate::from_pem(read(ca_cert)?));
+    }
+    match (&settings.client_cert, &settings.client_key) {
+        (Some(cert), Some(key)) => {
+            config = config.identity(Identity::from_pem(read(cert)?, read(key)?));
+        }
+        (None, None) => {}
+        _ => {
+            return Err(CosmosGrpcError::BadInput(
+                "client_cert and client_key have to be set together".to_string(),
+            ))
+        }
+    }
+    if let Some(domain) = &settings.tls_domain {
+        config = config.domain_name(domain);
+    }
+    Ok(config)
+}
+
+fn request_error(status: Status) -> CosmosGrpcError {
+    CosmosGrpcError::BadResponse(format!("{:?}: {}", status.code(), status.message()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use cosmos_sdk_proto_althea::cosmos::base::tendermint::v1beta1::{
+        GetBlockByHeightResponse, GetLatestBlockResponse, GetSyncingResponse,
+    };
+    use cosmos_sdk_proto_althea::tendermint::types::{Data, Header};
+    use futures::future::{ready, Ready};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use tokio::net::TcpListener;
+    use tonic::body::BoxBody;
+    use tonic::codec::ProstCodec;
+    use tonic::codegen::{empty_body, http, BoxFuture, Service};
+    use tonic::server::Grpc;
+    use tonic::transport::{Body, NamedService, Server};
+
+    /// a node with the blocks up to `tip`, `failing` answers with an error
+    /// other than not found and `missing` as if it was pruned
+    #[derive(Clone)]
+    pub(crate) struct MockNode {
+        pub(crate) tip: u64,
+        pub(crate) failing: Option<u64>,
+        pub(crate) missing: Option<u64>,
+        pub(crate) syncing: bool,
+        // the x-api-key header of every request
+        pub(crate) api_keys: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl MockNode {
+        pub(crate) fn new(tip: u64) -> MockNode {
+            MockNode {
+                tip,
+                failing: None,
+                missing: None,
+                syncing: false,
+                api_keys: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        /// an empty block, a block every 5 seconds from the unix epoch
+        fn block(height: u64) -> Block {
+            Block {
+                header: Some(Header {
+                    height: height as i64,
+                    time: Some(prost_types::Timestamp {
+                        seconds: height as i64 * 5,
+                        nanos: 0,
+                    }),
+                    ..Default::default()
+                }),
+                data: Some(Data::default()),
+                ..Default::default()
+            }
+        }
+
+        fn get_block_by_height(
+            &self,
+            request: GetBlockByHeightRequest,
+        ) -> Result<GetBlockByHeightResponse, Status> {
+            let height = request.height as u64;
+            if Some(height) == self.failing {
+                return Err(Status::unavailable("node is overloaded"));
+            }
+            if Some(height) == self.missing {
+                return Err(Status::not_found("block is pruned"));
+            }
+            if height > self.tip {
+                return Err(Status::invalid_argument(
+                    "requested block height is bigger then the chain length",
+                ));
+            }
+            Ok(GetBlockByHeightResponse {
+                block: Some(MockNode::block(height)),
+                ..Default::default()
+            })
+        }
+
+        fn get_syncing(&self, _: GetSyncingRequest) -> Result<GetSyncingResponse, Status> {
+            Ok(GetSyncingResponse {
+                syncing: self.syncing,
+            })
+        }
+
+        fn get_latest_block(
+            &self,
+            _: GetLatestBlockRequest,
+        ) -> Result<GetLatestBlockResponse, Status> {
+            Ok(GetLatestBlockResponse {
+                block: Some(MockNode::block(self.tip)),
+                ..Default::default()
+            })
+        }
+    }
+
+    /// one method of the mock node as a unary gRPC service
+    struct Method<Req, Resp> {
+        node: MockNode,
+        handle: fn(&MockNode, Req) -> Result<Resp, Status>,
+    }
+
+    impl<Req, Resp> Service<Request<Req>> for Method<Req, Resp> {
+        type Response = tonic::Response<Resp>;
+        type Error = Status;
+        type Future = Ready<Result<tonic::Response<Resp>, Status>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Status>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Req>) -> Self::Future {
+            ready((self.handle)(&self.node, request.into_inner()).map(tonic::Response::new))
+        }
+    }
+
+    impl NamedService for MockNode {
+        const NAME: &'static str = "cosmos.base.tendermint.v1beta1.Service";
+    }
+
+    impl Service<http::Request<Body>> for MockNode {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Infallible>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+            let api_key = request
+                .headers()
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string());
+            self.api_keys.lock().unwrap().push(api_key);
+            let node = self.clone();
+            Box::pin(async move {
+                let response = match request.uri().path() {
+                    "/cosmos.base.tendermint.v1beta1.Service/GetBlockByHeight" => {
+                        let method = Method {
+                            node,
+                            handle: MockNode::get_block_by_height,
+                        };
+                        Grpc::new(ProstCodec::default())
+                            .unary(method, request)
+                            .await
+                    }
+                    "/cosmos.base.tendermint.v1beta1.Service/GetSyncing" => {
+                        let method = Method {
+                            node,
+                            handle: MockNode::get_syncing,
+                        };
+                        Grpc::new(ProstCodec::default())
+                            .unary(method, request)
+                            .await
+                    }
+                    "/cosmos.base.tendermint.v1beta1.Service/GetLatestBlock" => {
+                        let method = Method {
+                            node,
+                            handle: MockNode::get_latest_block,
+                        };
+                        Grpc::new(ProstCodec::default())
+                            .unary(method, request)
+                            .await
+                    }
+                    _ => http::Response::builder()
+                        .status(200)
+                        .header("grpc-status", "12")
+                        .header("content-type", "application/grpc")
+                        .body(empty_body())
+                        .unwrap(),
+                };
+                Ok(response)
+            })
+        }
+    }
+
+    /// serves `node` on a free local port and returns its url
+    pub(crate) async fn serve(node: MockNode) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = stream::unfold(listener, |listener| async move {
+            let connection = listener.accept().await.map(|(socket, _)| socket);
+            Some((connection, listener))
+        });
+        tokio::spawn(
+            Server::builder()
+                .add_service(node)
+                .serve_with_incoming(incoming),
+        );
+        format!("http://{}", address)
+    }
+
+    fn client(url: &str, settings: &EndpointSettings) -> GrpcClient {
+        GrpcClient::new(url, settings, &BTreeMap::new(), Duration::from_secs(5)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn returns_none_for_blocks_the_node_does_not_have() {
+        let url = serve(MockNode::new(10)).await;
+        let client = client(&url, &EndpointSettings::default());
+
+        let block = client.get_block(7).await.unwrap().unwrap();
+        assert_eq!(block.header.unwrap().height, 7);
+        assert!(client.get_block(11).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_on_other_errors() {
+        let url = serve(MockNode {
+            failing: Some(5),
+            ..MockNode::new(10)
+        })
+        .await;
+        let client = client(&url, &EndpointSettings::default());
+
+        assert!(client.get_block(5).await.is_err());
+        assert!(client.get_block_range(1, 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn returns_ranges_in_height_order() {
+        let url = serve(MockNode::new(40)).await;
+        let client = client(&url, &EndpointSettings::default());
+
+        let blocks = client.get_block_range(1, 50).await.unwrap();
+        assert_eq!(blocks.len(), 50);
+        for (height, block) in (1..=50).zip(blocks) {
+            match block {
+                Some(block) => assert_eq!(block.header.unwrap().height, height),
+                None => assert!(height > 40),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_the_chain_status() {
+        let url = serve(MockNode::new(42)).await;
+        let status = client(&url, &EndpointSettings::default())
+            .get_chain_status()
+            .await
+            .unwrap();
+        assert!(matches!(status, ChainStatus::Moving { block_height: 42 }));
+
+        let url = serve(MockNode {
+            syncing: true,
+            ..MockNode::new(42)
+        })
+        .await;
+        let status = client(&url, &EndpointSettings::default())
+            .get_chain_status()
+            .await
+            .unwrap();
+        assert!(matches!(status, ChainStatus::Syncing));
+    }
+
+    #[tokio::test]
+    async fn sends_the_headers_with_endpoint_settings_first() {
+        let node = MockNode::new(10);
+        let api_keys = node.api_keys.clone();
+        let url = serve(node).await;
+        let global = BTreeMap::from([("x-api-key".to_string(), "global".to_string())]);
+
+        let client = GrpcClient::new(
+            &url,
+            &EndpointSettings::default(),
+            &global,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        client.get_block(1).await.unwrap();
+
+        let settings = EndpointSettings {
+            headers: BTreeMap::from([("x-api-key".to_string(), "endpoint".to_string())]),
+            ..EndpointSettings::default()
+        };
+        let client = GrpcClient::new(&url, &settings, &global, Duration::from_secs(5)).unwrap();
+        client.get_block(1).await.unwrap();
+
+        assert_eq!(
+            *api_keys.lock().unwrap(),
+            vec![Some("global".to_string()), Some("endpoint".to_string())]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_urls_and_headers() {
+        let settings = EndpointSettings::default();
+        let timeout = Duration::from_secs(5);
+        assert!(GrpcClient::new("not a url", &settings, &BTreeMap::new(), timeout).is_err());
+
+        let headers = BTreeMap::from([("bad header".to_string(), "value".to_string())]);
+        assert!(GrpcClient::new("http://localhost:9090", &settings, &headers, timeout).is_err());
+    }
+}
@@ -0,0 +1,631 @@
+use actix_web::http::StatusCode;
+use actix_web::ResponseError;
+use chrono_tz::Tz;
+use futures::stream::{self, Stream};
+use log::{debug, error, info};
+use rocksdb::DB;
+use rust_decimal::Decimal;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tonic::service::Interceptor;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::auth::{check_client, AccessControl};
+use crate::error::ApiError;
+use crate::shutdown::wait_for_shutdown;
+use crate::tls::{server_config, ReloadableCert};
+use crate::transactions::feed::{FeedEvent, Tail};
+use crate::transactions::query::{self, FilterError, FilterParams, Order};
+use crate::types::{CustomCoin, StoredMsg, Transaction};
+
+pub mod proto {
+    tonic::include_proto!("indexer.v1");
+}
+
+use proto::query_server::{Query, QueryServer};
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+// handshakes run next to each other, a client that stalls one is dropped
+// after TLS_HANDSHAKE_TIMEOUT
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const TLS_ACCEPT_BACKLOG: usize = 64;
+
+fn database_status(e: rocksdb::Error) -> Status {
+    error!("Database error: {}", e);
+    Status::internal("The database could not be read.")
+}
+
+fn filter_status(e: FilterError) -> Status {
+    Status::invalid_argument(e.to_string())
+}
+
+/// proto3 strings are empty when unset
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+fn time_zone(tz: String) -> Result<Tz, Status> {
+    match non_empty(tz) {
+        Some(name) => query::parse_tz(&name).map_err(filter_status),
+        None => Ok(Tz::UTC),
+    }
+}
+
+fn direction(value: i32) -> Result<Option<query::Direction>, Status> {
+    match proto::Direction::from_i32(value) {
+        Some(proto::Direction::Unspecified) => Ok(None),
+        Some(proto::Direction::Send) => Ok(Some(query::Direction::Send)),
+        Some(proto::Direction::Receive) => Ok(Some(query::Direction::Receive)),
+        None => Err(Status::invalid_argument(format!(
+            "Invalid direction {}",
+            value
+        ))),
+    }
+}
+
+fn order(value: i32) -> Result<Order, Status> {
+    match proto::Order::from_i32(value) {
+        Some(proto::Order::Unspecified) | Some(proto::Order::Desc) => Ok(Order::Desc),
+        Some(proto::Order::Asc) => Ok(Order::Asc),
+        None => Err(Status::invalid_argument(format!("Invalid order {}", value))),
+    }
+}
+
+fn page_size(size: u32) -> usize {
+    match size {
+        0 => DEFAULT_PAGE_SIZE,
+        size => (size as usize).min(MAX_PAGE_SIZE),
+    }
+}
+
+fn to_proto(transaction: Transaction) -> proto::Msg {
+    let coins = |coins: Vec<CustomCoin>| {
+        coins
+            .into_iter()
+            .map(|coin| proto::Coin {
+                denom: coin.denom,
+                amount: coin.amount,
+            })
+            .collect()
+    };
+    let data = match transaction.data {
+        StoredMsg::Send(msg) => proto::msg::Data::Send(proto::MsgSend {
+            from_address: msg.from_address,
+            to_address: msg.to_address,
+            amount: coins(msg.amount),
+        }),
+        StoredMsg::IbcTransfer(msg) => proto::msg::Data::IbcTransfer(proto::MsgTransfer {
+            source_port: msg.source_port,
+            source_channel: msg.source_channel,
+            token: coins(msg.token),
+            sender: msg.sender,
+            receiver: msg.receiver,
+            timeout_height: msg.timeout_height.map(|height| proto::Height {
+                revision_number: height.revision_number,
+                revision_height: height.revision_height,
+            }),
+            timeout_timestamp: msg.timeout_timestamp,
+        }),
+    };
+    proto::Msg {
+        height: transaction.height,
+        tx_hash: transaction.tx_hash,
+        msg_index: transaction.msg_index,
+        r#type: transaction.msg_type,
+        timestamp: transaction.timestamp,
+        unix_time: transaction.unix_time,
+        data: Some(data),
+    }
+}
+
+/// The indexer query service, backed by the same storage layer as the REST
+/// endpoints
+pub struct QueryService {
+    db: Arc<DB>,
+}
+
+impl QueryService {
+    fn list(
+        &self,
+        params: FilterParams,
+        order: Order,
+        page_size: usize,
+        page_token: String,
+    ) -> Result<Response<proto::ListTxsResponse>, Status> {
+        let (filter, tz) = params.into_filter().map_err(filter_status)?;
+        let page = query::load_page(
+            &self.db,
+            &filter,
+            order,
+            tz,
+            non_empty(page_token).as_deref(),
+            page_size,
+        )
+        .map_err(database_status)?;
+
+        let next_page_token = match page.transactions.last() {
+            Some((cursor, _)) if page.has_more => cursor.clone(),
+            _ => String::new(),
+        };
+        Ok(Response::new(proto::ListTxsResponse {
+            msgs: page
+                .transactions
+                .into_iter()
+                .map(|(_, transaction)| to_proto(transaction))
+                .collect(),
+            next_page_token,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl Query for QueryService {
+    async fn get_tx(
+        &self,
+        request: Request<proto::GetTxRequest>,
+    ) -> Result<Response<proto::Tx>, Status> {
+        let request = request.into_inner();
+        let tz = time_zone(request.tz)?;
+        let msgs = query::load_tx(&self.db, &request.hash, tz).map_err(database_status)?;
+        let first = match msgs.first() {
+            Some(first) => first,
+            None => {
+                return Err(Status::not_found(format!(
+                    "Transaction {} is not indexed",
+                    request.hash
+                )))
+            }
+        };
+        Ok(Response::new(proto::Tx {
+            hash: first.tx_hash.clone(),
+            height: first.height,
+            timestamp: first.timestamp.clone(),
+            unix_time: first.unix_time,
+            msgs: msgs.into_iter().map(to_proto).collect(),
+        }))
+    }
+
+    async fn list_txs_by_address(
+        &self,
+        request: Request<proto::ListTxsByAddressRequest>,
+    ) -> Result<Response<proto::ListTxsResponse>, Status> {
+        let request = request.into_inner();
+        if request.address.is_empty() {
+            return Err(Status::invalid_argument("An address is required"));
+        }
+        let params = FilterParams {
+            address: Some(request.address),
+            direction: direction(request.direction)?,
+            msg_type: non_empty(request.r#type),
+            denom: non_empty(request.denom),
+            tz: non_empty(request.tz),
+            from: non_empty(request.from),
+            to: non_empty(request.to),
+        };
+        self.list(
+            params,
+            order(request.order)?,
+            page_size(request.page_size),
+            request.page_token,
+        )
+    }
+
+    async fn list_txs_by_type(
+        &self,
+        request: Request<proto::ListTxsByTypeRequest>,
+    ) -> Result<Response<proto::ListTxsResponse>, Status> {
+        let request = request.into_inner();
+        if request.r#type.is_empty() {
+            return Err(Status::invalid_argument("A message type is required"));
+        }
+        let params = FilterParams {
+            msg_type: Some(request.r#type),
+            denom: non_empty(request.denom),
+            tz: non_empty(request.tz),
+            from: non_empty(request.from),
+            to: non_empty(request.to),
+            ..FilterParams::default()
+        };
+        self.list(
+            params,
+            order(request.order)?,
+            page_size(request.page_size),
+            request.page_token,
+        )
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<proto::GetBlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        let request = request.into_inner();
+        let tz = time_zone(request.tz)?;
+        let msgs = query::load_block(&self.db, request.height, tz).map_err(database_status)?;
+        // blocks without indexed messages aren't stored
+        let first = match msgs.first() {
+            Some(first) => first,
+            None => {
+                return Err(Status::not_found(format!(
+                    "No messages are indexed at height {}",
+                    request.height
+                )))
+            }
+        };
+        Ok(Response::new(proto::Block {
+            height: first.height,
+            timestamp: first.timestamp.clone(),
+            unix_time: first.unix_time,
+            msgs: msgs.into_iter().map(to_proto).collect(),
+        }))
+    }
+
+    type SubscribeTxsStream = Pin<Box<dyn Stream<Item = Result<proto::Msg, Status>> + Send>>;
+
+    async fn subscribe_txs(
+        &self,
+        request: Request<proto::SubscribeTxsRequest>,
+    ) -> Result<Response<Self::SubscribeTxsStream>, Status> {
+        let request = request.into_inner();
+        let params = FilterParams {
+            address: non_empty(request.address),
+            direction: direction(request.direction)?,
+            msg_type: non_empty(request.r#type),
+            denom: non_empty(request.denom),
+            tz: non_empty(request.tz),
+            ..FilterParams::default()
+        };
+        let (mut filter, tz) = params.into_filter().map_err(filter_status)?;
+        if let Some(min_amount) = non_empty(request.min_amount) {
+            filter.min_amount = Some(Decimal::from_str(&min_amount).map_err(|_| {
+                Status::invalid_argument(format!("Invalid min_amount {}", min_amount))
+            })?);
+        }
+        let from_height = Some(request.from_height).filter(|height| *height > 0);
+        let tail =
+            Tail::new(self.db.clone(), filter, tz, None, from_height).map_err(database_status)?;
+        let msgs = stream::unfold(tail, |mut tail| async move {
+            loop {
+                match tail.next().await {
+                    Ok(FeedEvent::Transaction(_, transaction)) => {
+                        return Some((Ok(to_proto(transaction)), tail))
+                    }
+                    Ok(FeedEvent::Idle) => continue,
+                    // ending the streams lets the server shut down gracefully
+                    Ok(FeedEvent::Shutdown) => return None,
+                    Err(e) => return Some((Err(database_status(e)), tail)),
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(msgs)))
+    }
+}
+
+/// Applies the API keys and limits of the HTTP API to every call
+#[derive(Clone)]
+struct AccessInterceptor {
+    db: Arc<DB>,
+    access: AccessControl,
+}
+
+impl Interceptor for AccessInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let metadata = request.metadata();
+        let key = metadata
+            .get("x-api-key")
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| {
+                metadata
+                    .get("authorization")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix("Bearer "))
+            })
+            .map(|key| key.trim().to_string());
+        let client_ip = || match request.remote_addr() {
+            Some(address) => address.ip().to_string(),
+            None => "unknown".to_string(),
+        };
+        match check_client(&self.db, &self.access, key, client_ip) {
+            Ok(()) => Ok(request),
+            Err(e) => Err(access_status(&e)),
+        }
+    }
+}
+
+fn access_status(e: &ApiError) -> Status {
+    match e.status_code() {
+        StatusCode::UNAUTHORIZED => Status::unauthenticated(e.message()),
+        StatusCode::TOO_MANY_REQUESTS => Status::resource_exhausted(e.message()),
+        _ => Status::internal(e.message()),
+    }
+}
+
+/// accepts connections on `listener` and completes their TLS handshakes
+/// concurrently, so a slow client doesn't hold up the others
+fn tls_incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> impl Stream<Item = Result<TlsStream<TcpStream>, std::io::Error>> {
+    let (sender, receiver) = mpsc::channel(TLS_ACCEPT_BACKLOG);
+    tokio::spawn(async move {
+        while !sender.is_closed() {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = wait_for_shutdown() => break,
+            };
+            let socket = match accepted {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    error!("Failed to accept a gRPC connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => debug!("gRPC TLS handshake failed: {}", e),
+                    Err(_) => debug!("gRPC TLS handshake timed out"),
+                }
+            });
+        }
+    });
+    stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|stream| (stream, receiver))
+    })
+}
+
+/// serves the query service until shutdown is requested, over TLS with the
+/// certificate of the HTTP API when there is one
+pub async fn serve(
+    db: Arc<DB>,
+    access: AccessControl,
+    bind_address: String,
+    port: u16,
+    cert: Option<Arc<ReloadableCert>>,
+) {
+    let address = match lookup_host((bind_address.as_str(), port)).await {
+        Ok(mut addresses) => match addresses.next() {
+            Some(address) => address,
+            None => {
+                error!("No address found for {}", bind_address);
+                return;
+            }
+        },
+        Err(e) => {
+            error!("Failed to resolve {}: {}", bind_address, e);
+            return;
+        }
+    };
+    let service = QueryServer::with_interceptor(
+        QueryService { db: db.clone() },
+        AccessInterceptor { db, access },
+    );
+    let server = Server::builder().add_service(service);
+    let result = match cert {
+        Some(cert) => {
+            let listener = match TcpListener::bind(address).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!(
+                        "Failed to bind the gRPC query service to {}: {}",
+                        address, e
+                    );
+                    return;
+                }
+            };
+            let mut config = server_config(cert);
+            // gRPC clients require HTTP/2 to be negotiated
+            config.alpn_protocols = vec![b"h2".to_vec()];
+            let acceptor = TlsAcceptor::from(Arc::new(config));
+            info!("Serving the gRPC query service over TLS on {}", address);
+            server
+                .serve_with_incoming_shutdown(tls_incoming(listener, acceptor), wait_for_shutdown())
+                .await
+        }
+        None => {
+            info!("Serving the gRPC query service on {}", address);
+            server
+                .serve_with_shutdown(address, wait_for_shutdown())
+                .await
+        }
+    };
+    if let Err(e) = result {
+        error!("gRPC query service failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    use crate::auth::create_api_key;
+    use crate::transactions::database::tests::{seed, send_msg, TestDb};
+    use crate::transactions::database::MSG_SEND;
+
+    const ALICE: &str = "manifest1alice";
+    const BOB: &str = "manifest1bob";
+
+    /// five sends from alice to bob in blocks 1 to 5
+    fn service(test: &TestDb) -> QueryService {
+        seed(
+            &test.db,
+            (1..=5)
+                .map(|height| {
+                    send_msg(
+                        height,
+                        &format!("TX{}", height),
+                        0,
+                        ALICE,
+                        BOB,
+                        "10",
+                        "umfx",
+                    )
+                })
+                .collect(),
+        );
+        QueryService {
+            db: test.db.clone(),
+        }
+    }
+
+    fn heights(response: &proto::ListTxsResponse) -> Vec<u64> {
+        response.msgs.iter().map(|msg| msg.height).collect()
+    }
+
+    #[tokio::test]
+    async fn pages_with_page_tokens() {
+        let test = TestDb::new();
+        let service = service(&test);
+
+        let mut pages = Vec::new();
+        let mut page_token = String::new();
+        loop {
+            let response = service
+                .list_txs_by_address(Request::new(proto::ListTxsByAddressRequest {
+                    address: ALICE.to_string(),
+                    page_size: 2,
+                    page_token,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            pages.push(heights(&response));
+            if response.next_page_token.is_empty() {
+                break;
+            }
+            page_token = response.next_page_token;
+        }
+        assert_eq!(pages, vec![vec![5, 4], vec![3, 2], vec![1]]);
+
+        let first = service
+            .list_txs_by_type(Request::new(proto::ListTxsByTypeRequest {
+                r#type: MSG_SEND.to_string(),
+                page_size: 3,
+                order: proto::Order::Asc as i32,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(heights(&first), vec![1, 2, 3]);
+        let rest = service
+            .list_txs_by_type(Request::new(proto::ListTxsByTypeRequest {
+                r#type: MSG_SEND.to_string(),
+                page_size: 3,
+                order: proto::Order::Asc as i32,
+                page_token: first.next_page_token,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(heights(&rest), vec![4, 5]);
+        assert!(rest.next_page_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_hashes_and_heights_are_not_found() {
+        let test = TestDb::new();
+        let service = service(&test);
+
+        let tx = service
+            .get_tx(Request::new(proto::GetTxRequest {
+                hash: "TX3".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((tx.height, tx.msgs.len()), (3, 1));
+        let status = service
+            .get_tx(Request::new(proto::GetTxRequest {
+                hash: "TX9".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let block = service
+            .get_block(Request::new(proto::GetBlockRequest {
+                height: 2,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(block.msgs[0].tx_hash, "TX2");
+        let status = service
+            .get_block(Request::new(proto::GetBlockRequest {
+                height: 9,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    fn with_header(name: &'static str, value: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request.metadata_mut().insert(name, value.parse().unwrap());
+        request
+    }
+
+    #[test]
+    fn the_interceptor_checks_keys_and_limits() {
+        let test = TestDb::new();
+        let mut interceptor = AccessInterceptor {
+            db: test.db.clone(),
+            access: AccessControl {
+                require_api_key: true,
+                trust_forwarded_for: false,
+                ip_rate_limit: 0,
+                ip_daily_quota: 0,
+                key_rate_limit: 0,
+                key_daily_quota: 0,
+                admin_token: None,
+            },
+        };
+        let (key, _) = create_api_key(&test.db, "grpc", Some(1), None).unwrap();
+        let code = |interceptor: &mut AccessInterceptor, request| {
+            interceptor.call(request).err().map(|status| status.code())
+        };
+
+        assert_eq!(
+            code(&mut interceptor, Request::new(())),
+            Some(Code::Unauthenticated)
+        );
+        assert_eq!(
+            code(&mut interceptor, with_header("x-api-key", "idx_unknown")),
+            Some(Code::Unauthenticated)
+        );
+        assert_eq!(code(&mut interceptor, with_header("x-api-key", &key)), None);
+        // the key allows one call a minute, however it is presented
+        assert_eq!(
+            code(
+                &mut interceptor,
+                with_header("authorization", &format!("Bearer {}", key))
+            ),
+            Some(Code::ResourceExhausted)
+        );
+    }
+}
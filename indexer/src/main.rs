@@ -1,92 +1,734 @@
 extern crate lazy_static;
 
+pub mod auth;
+pub mod config;
+pub mod error;
+pub mod graphql;
+pub mod grpc;
+pub mod grpc_server;
+pub mod metrics;
+pub mod nodes;
+pub mod openapi;
+pub mod shutdown;
+pub mod tls;
 pub mod transactions;
 pub mod types;
-
-const DEVELOPMENT: bool = cfg!(feature = "development");
-
-const DOMAIN: &str = if cfg!(test) || DEVELOPMENT {
-    "localhost"
-} else {
-    "localhost"
-};
-const PORT: u16 = 9000;
+pub mod webhooks;
 
 use actix_cors::Cors;
+use actix_web::dev::Service;
 use actix_web::web::Path;
 use actix_web::{
-    error, get, middleware::Logger, middleware::NormalizePath, middleware::TrailingSlash, web, App,
-    HttpResponse, HttpServer, Responder,
+    delete, get, middleware::DefaultHeaders, middleware::Logger, middleware::NormalizePath,
+    middleware::TrailingSlash, post, web, App, HttpRequest, HttpServer, Responder, ResponseError,
 };
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
 
-use log::error;
+use log::{error, info, warn};
 
 use env_logger::Env;
 use rocksdb::Options;
 use rocksdb::DB;
-use serde_json::json;
 
+use auth::{create_api_key, list_api_keys, revoke_api_key, API_KEYS_CF};
+use chrono_tz::Tz;
 use clap::Parser;
+use config::{AdminCommand, ApiKeyCommand, Args, Command, Config, ConfigCommand};
+use deep_space::error::CosmosGrpcError;
+use error::ApiError;
+use futures::future::{ready, Either};
+use nodes::NodePool;
+use openapi::Docs;
+use shutdown::{request_shutdown, shutdown_signal};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
 use std::sync::Arc;
-use transactions::database::transaction_info_thread;
-
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    #[clap(long, default_value = "http://66.172.36.142:2119")]
-    chain_node_grpc: String,
-
-    #[clap(long, default_value = "manifest")]
-    chain_prefix: String,
-
-    #[clap(long)]
-    test_mode: bool,
-
-    #[clap(long, default_value = "100000")]
-    test_block_limit: u64,
-}
+use std::time::{Duration, Instant};
+use tls::{reload_on_sighup, ReloadableCert};
+use transactions::database::{
+    backfill, follow_primary, reindex, transaction_info_thread, SyncOptions, QUARANTINE_CF,
+    REQUEST_TIMEOUT, TX_HASH_CF, TX_INFO_CF,
+};
+use transactions::endpoints::{
+    ExportQuery, ReadinessOptions, StreamQuery, SummaryQuery, TimeZoneQuery, TransactionQuery,
+};
+use transactions::export::{CsvExport, CSV_HEADER};
+use transactions::query::{FilterParams, TxFilter};
+use webhooks::{WEBHOOKS_CF, WEBHOOK_DELIVERIES_CF, WEBHOOK_QUEUE_CF};
 
-#[get("/transactions/send")]
-async fn get_all_msg_send_transactions(db: web::Data<Arc<DB>>) -> impl Responder {
-    transactions::endpoints::get_all_msg_send_transactions(db).await
+#[utoipa::path(
+    get,
+    path = "/transactions/send",
+    tag = "deprecated",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(TimeZoneQuery),
+    responses(
+        (status = 200, description = "All MsgSend transactions, newest first. Deprecated, use /v1/transactions?type=msgSend instead.", body = [transactions::endpoints::TransactionResponse]),
+        (status = 400, description = "Invalid time zone", body = error::ErrorBody),
+    )
+)]
+#[get("/send")]
+async fn get_all_msg_send_transactions(
+    db: web::Data<Arc<DB>>,
+    query: web::Query<TimeZoneQuery>,
+) -> impl Responder {
+    transactions::endpoints::get_all_msg_send_transactions(db, query.into_inner().tz).await
 }
 
-#[get("/transactions/ibc_transfer")]
-async fn get_all_msg_ibc_transfer_transactions(db: web::Data<Arc<DB>>) -> impl Responder {
-    transactions::endpoints::get_all_msg_ibc_transfer_transactions(db).await
+#[utoipa::path(
+    get,
+    path = "/transactions/ibc_transfer",
+    tag = "deprecated",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(TimeZoneQuery),
+    responses(
+        (status = 200, description = "All MsgTransfer transactions grouped by block, oldest first. Deprecated, use /v1/transactions?type=msgIbcTransfer&group_by=block&order=asc instead.", body = [transactions::endpoints::BlockTransactions]),
+        (status = 400, description = "Invalid time zone", body = error::ErrorBody),
+    )
+)]
+#[get("/ibc_transfer")]
+async fn get_all_msg_ibc_transfer_transactions(
+    db: web::Data<Arc<DB>>,
+    query: web::Query<TimeZoneQuery>,
+) -> impl Responder {
+    transactions::endpoints::get_all_msg_ibc_transfer_transactions(db, query.into_inner().tz).await
 }
 
-#[get("/transactions/send/{address}")]
+#[utoipa::path(
+    get,
+    path = "/transactions/send/{address}",
+    tag = "deprecated",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(("address" = String, Path, description = "Sender or receiver"), TimeZoneQuery),
+    responses(
+        (status = 200, description = "MsgSend transactions of the address, newest first. Deprecated, use /v1/transactions/{address}?type=msgSend instead.", body = [transactions::endpoints::TransactionResponse]),
+        (status = 400, description = "Invalid time zone", body = error::ErrorBody),
+    )
+)]
+#[get("/send/{address}")]
 async fn get_msg_send_transactions_by_address(
     db: web::Data<Arc<DB>>,
     address: Path<String>,
+    query: web::Query<TimeZoneQuery>,
 ) -> impl Responder {
-    transactions::endpoints::get_msg_send_transactions_by_address(db, address.into_inner()).await
+    transactions::endpoints::get_msg_send_transactions_by_address(
+        db,
+        address.into_inner(),
+        query.into_inner().tz,
+    )
+    .await
 }
 
-#[get("/transactions/send/{address}/{direction}")]
+#[utoipa::path(
+    get,
+    path = "/transactions/send/{address}/{direction}",
+    tag = "deprecated",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(
+        ("address" = String, Path, description = "Sender or receiver"),
+        ("direction" = transactions::query::Direction, Path, description = "`send` or `receive`"),
+        TimeZoneQuery
+    ),
+    responses(
+        (status = 200, description = "MsgSend transactions the address sent or received, newest first. Deprecated, use /v1/transactions/{address}?type=msgSend&direction={direction} instead.", body = [transactions::endpoints::TransactionResponse]),
+        (status = 400, description = "Invalid direction or time zone", body = error::ErrorBody),
+    )
+)]
+#[get("/send/{address}/{direction}")]
 async fn get_msg_send_transactions_by_address_and_direction(
     db: web::Data<Arc<DB>>,
     path: Path<(String, String)>,
+    query: web::Query<TimeZoneQuery>,
 ) -> impl Responder {
     let (address, direction) = path.into_inner();
     transactions::endpoints::get_msg_send_transactions_by_address_and_direction(
-        db, address, direction,
+        db,
+        address,
+        direction,
+        query.into_inner().tz,
     )
     .await
 }
 
-#[get("/transactions")]
-async fn get_all_transactions(db: web::Data<Arc<DB>>) -> impl Responder {
-    transactions::endpoints::get_all_transactions(db).await
+#[utoipa::path(
+    get,
+    path = "/transactions",
+    tag = "deprecated",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(TimeZoneQuery),
+    responses(
+        (status = 200, description = "All transactions, newest first. Deprecated, use /v1/transactions instead.", body = [transactions::endpoints::AllTransactionResponse]),
+        (status = 400, description = "Invalid time zone", body = error::ErrorBody),
+    )
+)]
+#[get("")]
+async fn get_all_transactions(
+    db: web::Data<Arc<DB>>,
+    query: web::Query<TimeZoneQuery>,
+) -> impl Responder {
+    transactions::endpoints::get_all_transactions(db, query.into_inner().tz).await
 }
 
-#[get("/transactions/{address}")]
+#[utoipa::path(
+    get,
+    path = "/transactions/{address}",
+    tag = "deprecated",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(("address" = String, Path, description = "Sender or receiver"), TimeZoneQuery),
+    responses(
+        (status = 200, description = "Transactions of the address, newest first. Deprecated, use /v1/transactions/{address} instead.", body = [transactions::endpoints::AllTransactionResponse]),
+        (status = 400, description = "Invalid time zone", body = error::ErrorBody),
+    )
+)]
+#[get("/{address}")]
 async fn get_all_transactions_by_address(
     db: web::Data<Arc<DB>>,
     address: Path<String>,
+    query: web::Query<TimeZoneQuery>,
+) -> impl Responder {
+    transactions::endpoints::get_all_transactions_by_address(
+        db,
+        address.into_inner(),
+        query.into_inner().tz,
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/transactions/{address}/export",
+    tag = "transactions",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(("address" = String, Path, description = "Sender or receiver"), ExportQuery),
+    responses(
+        (status = 200, description = "One row per coin the address sent or received, oldest first, with the columns date, height, tx_hash, type, direction, counterparty, denom, amount, fee and memo", content_type = "text/csv", body = String),
+        (status = 400, description = "Invalid parameter", body = error::ErrorBody),
+    )
+)]
+#[get("/transactions/{address}/export")]
+async fn export_transactions(
+    db: web::Data<Arc<DB>>,
+    address: Path<String>,
+    query: web::Query<ExportQuery>,
+) -> impl Responder {
+    transactions::endpoints::export_transactions(db, address.into_inner(), query.into_inner()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/addresses/{address}/summary",
+    tag = "transactions",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(("address" = String, Path, description = "Sender or receiver"), SummaryQuery),
+    responses(
+        (status = 200, description = "Activity, message counts, totals per denom and top counterparties of the address", body = transactions::summary::AddressSummary),
+        (status = 400, description = "Invalid parameter", body = error::ErrorBody),
+    )
+)]
+#[get("/addresses/{address}/summary")]
+async fn get_address_summary(
+    db: web::Data<Arc<DB>>,
+    address: Path<String>,
+    query: web::Query<SummaryQuery>,
+) -> impl Responder {
+    transactions::endpoints::get_address_summary(db, address.into_inner(), query.into_inner()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/transactions",
+    tag = "transactions",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(TransactionQuery),
+    responses(
+        (status = 200, description = "A page of messages, a list of blocks with `group_by=block` or of days with `group_by=day`. The `X-Next-Cursor` header holds the `after` of the next page if there is one", body = types::TransactionList),
+        (status = 400, description = "Invalid parameter", body = error::ErrorBody),
+    )
+)]
+#[get("/v1/transactions")]
+async fn get_transactions_v1(
+    db: web::Data<Arc<DB>>,
+    query: web::Query<TransactionQuery>,
+) -> impl Responder {
+    transactions::endpoints::get_transactions_v1(db, None, query.into_inner()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/transactions/{address}",
+    tag = "transactions",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(("address" = String, Path, description = "Sender or receiver"), TransactionQuery),
+    responses(
+        (status = 200, description = "A page of the messages the address sent or received, grouped and paged like /v1/transactions", body = types::TransactionList),
+        (status = 400, description = "Invalid parameter", body = error::ErrorBody),
+    )
+)]
+#[get("/v1/transactions/{address}")]
+async fn get_transactions_by_address_v1(
+    db: web::Data<Arc<DB>>,
+    address: Path<String>,
+    query: web::Query<TransactionQuery>,
+) -> impl Responder {
+    transactions::endpoints::get_transactions_v1(db, Some(address.into_inner()), query.into_inner())
+        .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/status",
+    tag = "operations",
+    security((), ("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Sync progress of the indexer", body = transactions::endpoints::StatusResponse),
+        (status = 500, description = "The checkpoint can't be read", body = error::ErrorBody),
+    )
+)]
+#[get("/status")]
+async fn get_status(db: web::Data<Arc<DB>>, pool: web::Data<Arc<NodePool>>) -> impl Responder {
+    transactions::endpoints::get_status(db, pool).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "The process is up and the database can be read"),
+        (status = 503, description = "The database can't be read"),
+    )
+)]
+#[get("/healthz")]
+async fn get_healthz(db: web::Data<Arc<DB>>) -> impl Responder {
+    transactions::endpoints::get_healthz(db).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "The indexer is alive and caught up", body = transactions::endpoints::ReadinessResponse),
+        (status = 503, description = "The indexer is behind, stalled or can't reach a node", body = transactions::endpoints::ReadinessResponse),
+        (status = 500, description = "The checkpoint can't be read", body = error::ErrorBody),
+    )
+)]
+#[get("/readyz")]
+async fn get_readyz(
+    db: web::Data<Arc<DB>>,
+    pool: web::Data<Arc<NodePool>>,
+    options: web::Data<ReadinessOptions>,
+) -> impl Responder {
+    transactions::endpoints::get_readyz(db, pool, options).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "Prometheus metrics in the text format", content_type = "text/plain"))
+)]
+#[get("/metrics")]
+async fn get_metrics(db: web::Data<Arc<DB>>, pool: web::Data<Arc<NodePool>>) -> impl Responder {
+    transactions::endpoints::get_metrics(db, pool).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/quarantine",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses((status = 200, description = "Transactions that failed to decode", body = [types::QuarantinedTx]))
+)]
+#[get("/admin/quarantine")]
+async fn get_quarantined_transactions(db: web::Data<Arc<DB>>) -> impl Responder {
+    transactions::endpoints::get_quarantined_transactions(db).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/quarantine/retry",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Decodes the quarantined transactions again", body = transactions::endpoints::RetryResponse),
+        (status = 500, description = "The database could not be written", body = error::ErrorBody),
+    )
+)]
+#[post("/admin/quarantine/retry")]
+async fn retry_quarantined_transactions(
+    db: web::Data<Arc<DB>>,
+    options: web::Data<SyncOptions>,
+) -> impl Responder {
+    transactions::endpoints::retry_quarantined_transactions(db, options).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses((status = 200, description = "Every webhook, without the secrets", body = [webhooks::Webhook]))
+)]
+#[get("/admin/webhooks")]
+async fn get_webhooks(db: web::Data<Arc<DB>>) -> impl Responder {
+    webhooks::get_webhooks(db).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = webhooks::NewWebhook,
+    responses(
+        (status = 201, description = "The webhook with the secret of its signatures, which isn't shown again", body = webhooks::StoredWebhook),
+        (status = 400, description = "Invalid url or filter, or a target host that isn't allowed", body = error::ErrorBody),
+    )
+)]
+#[post("/admin/webhooks")]
+async fn post_webhook(
+    db: web::Data<Arc<DB>>,
+    targets: web::Data<webhooks::WebhookTargets>,
+    webhook: web::Json<webhooks::NewWebhook>,
+) -> impl Responder {
+    webhooks::post_webhook(db, targets, webhook.into_inner()).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/webhooks/{id}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("id" = String, Path, description = "Id of the webhook")),
+    responses(
+        (status = 204, description = "The webhook was deleted, its pending deliveries fail"),
+        (status = 404, description = "No such webhook", body = error::ErrorBody),
+    )
+)]
+#[delete("/admin/webhooks/{id}")]
+async fn delete_webhook(db: web::Data<Arc<DB>>, id: Path<String>) -> impl Responder {
+    webhooks::delete_webhook_by_id(db, id.into_inner()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/{id}/deliveries",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("id" = String, Path, description = "Id of the webhook")),
+    responses(
+        (status = 200, description = "The latest 100 deliveries, newest first", body = [webhooks::Delivery]),
+        (status = 404, description = "No such webhook", body = error::ErrorBody),
+    )
+)]
+#[get("/admin/webhooks/{id}/deliveries")]
+async fn get_webhook_deliveries(db: web::Data<Arc<DB>>, id: Path<String>) -> impl Responder {
+    webhooks::get_webhook_deliveries(db, id.into_inner()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/stream",
+    tag = "transactions",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "Server-sent `transaction` events with the messages as in /v1/transactions and their cursor as the event id", content_type = "text/event-stream", body = String),
+        (status = 400, description = "Invalid parameter", body = error::ErrorBody),
+    )
+)]
+#[get("/v1/stream")]
+async fn stream_transactions(
+    db: web::Data<Arc<DB>>,
+    query: web::Query<StreamQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    transactions::endpoints::stream_transactions_sse(db, query.into_inner(), last_event_id).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/stream/ws",
+    tag = "transactions",
+    security((), ("api_key" = []), ("bearer" = [])),
+    params(StreamQuery),
+    responses(
+        (status = 101, description = "WebSocket of JSON text frames, one per message", body = transactions::endpoints::StreamEvent),
+        (status = 400, description = "Invalid parameter", body = error::ErrorBody),
+    )
+)]
+#[get("/v1/stream/ws")]
+async fn stream_transactions_ws(
+    db: web::Data<Arc<DB>>,
+    query: web::Query<StreamQuery>,
+    req: HttpRequest,
+    body: web::Payload,
 ) -> impl Responder {
-    transactions::endpoints::get_all_transactions_by_address(db, address.into_inner()).await
+    transactions::endpoints::stream_transactions_ws(db, query.into_inner(), req, body).await
+}
+
+#[get("/openapi.json")]
+async fn get_openapi() -> impl Responder {
+    openapi::openapi_json()
+}
+
+#[get("/docs")]
+async fn get_docs(docs: web::Data<Docs>) -> impl Responder {
+    openapi::docs_page(&docs)
+}
+
+#[get("/docs/redoc.standalone.js")]
+async fn get_redoc_script(docs: web::Data<Docs>) -> impl Responder {
+    openapi::redoc_script(&docs)
+}
+
+#[post("/graphql")]
+async fn post_graphql(
+    schema: web::Data<graphql::IndexerSchema>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    schema.execute(request.into_inner()).await.into()
+}
+
+#[get("/graphql")]
+async fn get_graphiql() -> impl Responder {
+    graphql::graphiql_page()
+}
+
+/// Which parts of the indexer this process runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// indexes and serves the API from the same database
+    Run,
+    /// indexes, only the probes, status and admin routes are served
+    Index,
+    /// serves the API from a secondary instance following an `index` process
+    Serve,
+}
+
+fn routes(cfg: &mut web::ServiceConfig, mode: Mode) {
+    cfg.service(get_healthz)
+        .service(get_readyz)
+        .service(get_status)
+        .service(get_metrics);
+    if mode != Mode::Index {
+        cfg.service(get_openapi)
+            .service(get_docs)
+            .service(get_redoc_script)
+            .service(post_graphql)
+            .service(get_graphiql)
+            .service(get_transactions_v1)
+            .service(get_transactions_by_address_v1)
+            .service(stream_transactions)
+            .service(stream_transactions_ws)
+            .service(get_address_summary)
+            // registered ahead of the deprecated scope, which would
+            // otherwise take every path under /transactions
+            .service(export_transactions)
+            .service(
+                // the unversioned routes predate /v1 and keep their old
+                // response formats, `/{address}` goes last so it doesn't
+                // shadow the fixed paths
+                web::scope("/transactions")
+                    .wrap(
+                        DefaultHeaders::new()
+                            .add(("Deprecation", "true"))
+                            .add(("Link", "</v1/transactions>; rel=\"successor-version\"")),
+                    )
+                    .service(get_all_transactions)
+                    .service(get_all_msg_send_transactions)
+                    .service(get_all_msg_ibc_transfer_transactions)
+                    .service(get_msg_send_transactions_by_address)
+                    .service(get_msg_send_transactions_by_address_and_direction)
+                    .service(get_all_transactions_by_address),
+            );
+    }
+    // a secondary instance can't be written to
+    if mode != Mode::Serve {
+        cfg.service(get_quarantined_transactions)
+            .service(retry_quarantined_transactions)
+            .service(get_webhooks)
+            .service(post_webhook)
+            .service(delete_webhook)
+            .service(get_webhook_deliveries);
+    }
+}
+
+fn open_db(config: &Config, mode: Mode) -> Result<DB, rocksdb::Error> {
+    let mut db_options = Options::default();
+    if mode == Mode::Serve {
+        // a secondary has to keep every file open to follow the compactions
+        // of the primary
+        db_options.set_max_open_files(-1);
+        let secondary_path = config.secondary_db_path();
+        info!(
+            "Opening {} as a secondary instance in {}",
+            config.db_path, secondary_path
+        );
+        return DB::open_cf_as_secondary(
+            &db_options,
+            config.db_path.as_str(),
+            secondary_path.as_str(),
+            [
+                QUARANTINE_CF,
+                TX_INFO_CF,
+                TX_HASH_CF,
+                API_KEYS_CF,
+                WEBHOOKS_CF,
+                WEBHOOK_DELIVERIES_CF,
+                WEBHOOK_QUEUE_CF,
+            ],
+        );
+    }
+    db_options.create_if_missing(true);
+    db_options.create_missing_column_families(true);
+    DB::open_cf(
+        &db_options,
+        &config.db_path,
+        [
+            QUARANTINE_CF,
+            TX_INFO_CF,
+            TX_HASH_CF,
+            API_KEYS_CF,
+            WEBHOOKS_CF,
+            WEBHOOK_DELIVERIES_CF,
+            WEBHOOK_QUEUE_CF,
+        ],
+    )
+}
+
+fn new_pool(config: &Config) -> Result<NodePool, CosmosGrpcError> {
+    NodePool::new(
+        &config.chain_node_grpc,
+        &config.grpc_endpoints,
+        &config.grpc_headers,
+        REQUEST_TIMEOUT,
+    )
+}
+
+fn manage_api_keys(db: &DB, command: &ApiKeyCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        ApiKeyCommand::Create {
+            name,
+            rate_limit,
+            daily_quota,
+        } => {
+            let (key, api_key) = create_api_key(db, name, *rate_limit, *daily_quota)?;
+            println!("Created API key {} ({})", api_key.id, api_key.name);
+            println!("{}", key);
+            println!("Store it now, it can't be shown again.");
+        }
+        ApiKeyCommand::List => {
+            for api_key in list_api_keys(db) {
+                println!(
+                    "{}  {}  rate limit {}  daily quota {}",
+                    api_key.id,
+                    api_key.name,
+                    api_key
+                        .rate_limit
+                        .map_or("default".to_string(), |limit| limit.to_string()),
+                    api_key
+                        .daily_quota
+                        .map_or("default".to_string(), |quota| quota.to_string())
+                );
+            }
+        }
+        ApiKeyCommand::Revoke { id } => {
+            if revoke_api_key(db, id)? {
+                println!("Revoked API key {}", id);
+            } else {
+                println!("No API key with id {}", id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// any origin is allowed unless origins are configured
+fn cors(origins: &[String]) -> Cors {
+    let cors = Cors::default()
+        .allow_any_header()
+        .allow_any_method()
+        // browsers only let pages read it when it is listed
+        .expose_headers(["X-Next-Cursor"])
+        .max_age(3600);
+    if origins.is_empty() {
+        return cors.allow_any_origin();
+    }
+    origins
+        .iter()
+        .fold(cors, |cors, origin| cors.allowed_origin(origin))
+}
+
+async fn run_admin(config: &Config, command: &AdminCommand) -> std::io::Result<()> {
+    let db = open_db(config, Mode::Index).expect("Failed to open database");
+    let sync_options = config.sync_options();
+    let result = match command {
+        AdminCommand::Backfill { from, to } => {
+            let pool = new_pool(config).expect("Invalid gRPC endpoint");
+            backfill(&db, &pool, *from, *to, &sync_options).await
+        }
+        AdminCommand::Reindex { from, to, types } => {
+            let pool = new_pool(config).expect("Invalid gRPC endpoint");
+            reindex(&db, &pool, *from, *to, types, &sync_options).await
+        }
+        AdminCommand::ApiKey { command } => manage_api_keys(&db, command),
+    };
+    result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+}
+
+/// writes the CSV export of an address to `output` or stdout, reading a
+/// secondary instance so it works next to a running indexer
+fn export_csv(
+    config: &Config,
+    params: FilterParams,
+    output: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (filter, tz) = params.into_filter().map_err(|e| e.to_string())?;
+    let result = open_db(config, Mode::Serve)
+        .map_err(Into::into)
+        .and_then(|db| write_csv(Arc::new(db), filter, tz, output));
+    remove_default_secondary(config);
+    result
+}
+
+fn write_csv(
+    db: Arc<DB>,
+    filter: TxFilter,
+    tz: Tz,
+    output: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    out.write_all(CSV_HEADER.as_bytes())?;
+    let mut export = CsvExport::new(db, filter, tz);
+    while let Some(rows) = export.next_rows()? {
+        out.write_all(rows.as_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// the secondary instance is per process unless SECONDARY_DB_PATH is set, so
+/// it is removed on exit instead of leaving a directory behind every run
+fn remove_default_secondary(config: &Config) {
+    if config.secondary_db_path.is_some() {
+        return;
+    }
+    let path = config.secondary_db_path();
+    if let Err(e) = fs::remove_dir_all(&path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            warn!("Failed to remove the secondary instance in {}: {}", path, e);
+        }
+    }
 }
 
 #[actix_web::main]
@@ -95,52 +737,223 @@ async fn main() -> std::io::Result<()> {
     openssl_probe::init_ssl_cert_env_vars();
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let mut db_options = Options::default();
-    db_options.create_if_missing(true);
-    let db = Arc::new(DB::open(&db_options, "transactions").expect("Failed to open database"));
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mode = match &args.command {
+        Some(Command::Config {
+            command: ConfigCommand::Check,
+        }) => {
+            // the channels connect lazily, this only reads the TLS files
+            if let Err(e) = new_pool(&config) {
+                error!("Invalid gRPC endpoint settings: {:?}", e);
+                std::process::exit(1);
+            }
+            match toml::to_string_pretty(&config) {
+                Ok(rendered) => print!("{}", rendered),
+                Err(e) => error!("Failed to print configuration: {}", e),
+            }
+            info!("Configuration is valid");
+            return Ok(());
+        }
+        Some(Command::Admin { command }) => return run_admin(&config, command).await,
+        Some(Command::Export {
+            address,
+            output,
+            msg_type,
+            denom,
+            tz,
+            from,
+            to,
+        }) => {
+            let params = FilterParams {
+                address: Some(address.clone()),
+                msg_type: msg_type.clone(),
+                denom: denom.clone(),
+                tz: tz.clone(),
+                from: from.clone(),
+                to: to.clone(),
+                ..FilterParams::default()
+            };
+            return export_csv(&config, params, output.as_deref())
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()));
+        }
+        Some(Command::Index) => Mode::Index,
+        Some(Command::Serve) => Mode::Serve,
+        Some(Command::Run) | None => Mode::Run,
+    };
+    info!("Starting in {:?} mode", mode);
+    let sync_options = config.sync_options();
+
+    let db = Arc::new(open_db(&config, mode).expect("Failed to open database"));
     let api_db = web::Data::new(db.clone());
+    let graphql_schema = web::Data::new(graphql::build_schema(db.clone()));
+
+    let pool = Arc::new(new_pool(&config).expect("Invalid gRPC endpoint"));
 
-    // Pass the arguments to the transaction_info_thread
-    transaction_info_thread(
-        db.clone(),
-        args.chain_node_grpc,
-        args.chain_prefix,
-        args.test_mode,
-        args.test_block_limit,
+    let api_pool = web::Data::new(pool.clone());
+    let readiness = web::Data::new(config.readiness_options());
+    let api_sync_options = web::Data::new(sync_options.clone());
+    let access_control = web::Data::new(config.access_control());
+    let webhook_targets = web::Data::new(config.webhook_targets());
+    let docs = web::Data::new(
+        Docs::load(config.docs_script.as_deref()).unwrap_or_else(|e| {
+            error!("Failed to load the docs script: {}", e);
+            std::process::exit(1);
+        }),
     );
+    let cors_origins = config.cors_origins.clone();
+    // the HTTP and gRPC servers share the certificate, so a reload applies to
+    // both
+    let cert = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert = Arc::new(
+                ReloadableCert::load(cert_path, key_path).unwrap_or_else(|e| {
+                    error!("Failed to load TLS certificate: {}", e);
+                    std::process::exit(1);
+                }),
+            );
+            actix_rt::spawn(reload_on_sighup(cert.clone()));
+            Some(cert)
+        }
+        _ => None,
+    };
+
+    let indexer = if mode == Mode::Serve {
+        actix_rt::spawn(follow_primary(
+            db.clone(),
+            pool,
+            Duration::from_secs(config.catch_up_interval),
+        ));
+        None
+    } else {
+        // deliveries are written to the database, so they are sent by the
+        // process that indexes
+        actix_rt::spawn(webhooks::run_webhooks(db.clone(), config.webhook_targets()));
+        // Pass the arguments to the transaction_info_thread
+        Some(transaction_info_thread(
+            db.clone(),
+            pool,
+            config.chain_node_rpc.clone(),
+            sync_options,
+        ))
+    };
+
+    if let (Some(grpc_port), true) = (config.grpc_port, mode != Mode::Index) {
+        actix_rt::spawn(grpc_server::serve(
+            db.clone(),
+            config.access_control(),
+            config.bind_address.clone(),
+            grpc_port,
+            cert.clone(),
+        ));
+    }
 
     let server = HttpServer::new(move || {
         App::new()
+            .wrap_fn(|req, srv| match auth::check_request(&req) {
+                None => Either::Left(srv.call(req)),
+                Some(err) => Either::Right(ready(Ok(req.into_response(err.error_response())))),
+            })
+            .wrap_fn(|req, srv| {
+                let start = Instant::now();
+                let method = req.method().to_string();
+                let fut = srv.call(req);
+                async move {
+                    let res = fut.await?;
+                    metrics::observe_http_request(&res, &method, start.elapsed());
+                    Ok(res)
+                }
+            })
             .wrap(Logger::default())
             .wrap(Logger::new("%a %{User-Agent}i"))
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allow_any_header()
-                    .allow_any_method()
-                    .max_age(3600),
-            )
+            .wrap(cors(&cors_origins))
             .wrap(NormalizePath::new(TrailingSlash::Trim))
             .app_data(api_db.clone())
+            .app_data(graphql_schema.clone())
+            .app_data(api_pool.clone())
+            .app_data(readiness.clone())
+            .app_data(api_sync_options.clone())
+            .app_data(access_control.clone())
+            .app_data(webhook_targets.clone())
+            .app_data(docs.clone())
             .app_data(web::JsonConfig::default().error_handler(|err, _req| {
                 error!("JSON error: {:?}", err);
-                error::InternalError::from_response(err, HttpResponse::BadRequest().finish()).into()
+                ApiError::bad_request("invalid_json", err.to_string()).into()
             }))
-            .service(get_all_transactions)
-            .service(get_all_transactions_by_address)
-            .service(get_all_msg_send_transactions)
-            .service(get_all_msg_ibc_transfer_transactions)
-            .service(get_msg_send_transactions_by_address)
-            .service(get_msg_send_transactions_by_address_and_direction)
+            .app_data(web::QueryConfig::default().error_handler(|err, _req| {
+                ApiError::bad_request("invalid_query", err.to_string()).into()
+            }))
+            .configure(|cfg| routes(cfg, mode))
             .service(web::scope("").default_service(web::route().to(|| async {
-                HttpResponse::NotFound().json(json!({
-                    "error": "Not Found",
-                    "message": "The requested resource could not be found."
-                }))
+                ApiError::not_found("The requested resource could not be found.").error_response()
             })))
     });
 
-    let server = server.bind(format!("{}:{}", DOMAIN, PORT))?;
-    server.run().await?;
-    Ok(())
+    let address = (config.bind_address.as_str(), config.port);
+    let server = match cert {
+        Some(cert) => {
+            info!("Serving HTTPS on port {}", config.port);
+            server.bind_rustls(address, tls::server_config(cert))?
+        }
+        None => server.bind(address)?,
+    }
+    .disable_signals()
+    .run();
+
+    let redirect_handle = match config.http_redirect_port {
+        Some(redirect_port) => {
+            let https_port = config.port;
+            info!("Redirecting HTTP on port {} to HTTPS", redirect_port);
+            let redirect_server = HttpServer::new(move || {
+                App::new().default_service(web::to(move |req: HttpRequest| async move {
+                    tls::redirect_to_https(&req, https_port)
+                }))
+            })
+            .bind((config.bind_address.as_str(), redirect_port))?
+            .disable_signals()
+            .run();
+            let handle = redirect_server.handle();
+            actix_rt::spawn(redirect_server);
+            Some(handle)
+        }
+        None => None,
+    };
+
+    // on ctrl-c the indexer is stopped first so that its progress is saved and
+    // flushed, the API keeps serving until then
+    let server_handle = server.handle();
+    let shutdown_db = db.clone();
+    actix_rt::spawn(async move {
+        shutdown_signal().await;
+        request_shutdown();
+        if let Some(indexer) = indexer {
+            info!("Shutting down, waiting for the indexer to stop");
+            match web::block(move || indexer.join()).await {
+                Ok(Ok(())) => info!("Indexer stopped"),
+                _ => error!("Transaction info thread did not stop cleanly"),
+            }
+            if let Err(e) = shutdown_db.flush() {
+                error!("Failed to flush database: {}", e);
+            }
+        } else {
+            info!("Shutting down");
+        }
+        if let Some(redirect_handle) = redirect_handle {
+            redirect_handle.stop(true).await;
+        }
+        server_handle.stop(true).await;
+    });
+
+    let result = server.await;
+    if mode == Mode::Serve {
+        // the files are only unlinked while the database is still open, the
+        // process exits right after
+        remove_default_secondary(&config);
+    }
+    result
 }
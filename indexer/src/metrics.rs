@@ -0,0 +1,140 @@
+use actix_web::dev::ServiceResponse;
+use lazy_static::lazy_static;
+use log::error;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use rocksdb::DB;
+use std::time::Duration;
+
+use crate::nodes::NodePool;
+use crate::transactions::database::{
+    counters, load_last_download_block, MSG_IBC_TRANSFER, MSG_SEND,
+};
+
+lazy_static! {
+    static ref INDEXED_HEIGHT: IntGauge = register_int_gauge!(
+        "indexer_indexed_height",
+        "Last block height saved in the checkpoint"
+    )
+    .unwrap();
+    static ref CHAIN_TIP: IntGauge = register_int_gauge!(
+        "indexer_chain_tip",
+        "Highest block height reported by the gRPC endpoints"
+    )
+    .unwrap();
+    static ref INDEXED_BLOCKS: IntGauge =
+        register_int_gauge!("indexer_indexed_blocks", "Blocks indexed").unwrap();
+    static ref INDEXED_TRANSACTIONS: IntGauge = register_int_gauge!(
+        "indexer_indexed_transactions",
+        "Transactions containing indexed messages"
+    )
+    .unwrap();
+    static ref INDEXED_MESSAGES: IntGaugeVec = register_int_gauge_vec!(
+        "indexer_indexed_messages",
+        "Messages indexed by message type",
+        &["type"]
+    )
+    .unwrap();
+    static ref GRPC_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "indexer_grpc_request_duration_seconds",
+        "Latency of gRPC requests by endpoint",
+        &["endpoint"]
+    )
+    .unwrap();
+    static ref GRPC_REQUEST_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "indexer_grpc_request_errors_total",
+        "Failed gRPC requests by endpoint",
+        &["endpoint"]
+    )
+    .unwrap();
+    static ref RETRIES: IntCounterVec = register_int_counter_vec!(
+        "indexer_retries_total",
+        "Retried operations by operation",
+        &["operation"]
+    )
+    .unwrap();
+    static ref ROCKSDB_PROPERTIES: IntGaugeVec = register_int_gauge_vec!(
+        "indexer_rocksdb_property",
+        "RocksDB size and write stall properties",
+        &["property"]
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "indexer_http_request_duration_seconds",
+        "Latency of HTTP requests by route",
+        &["route", "method", "status"]
+    )
+    .unwrap();
+}
+
+// RocksDB properties exported on every scrape
+const ROCKSDB_PROPERTY_NAMES: [&str; 6] = [
+    "rocksdb.total-sst-files-size",
+    "rocksdb.estimate-live-data-size",
+    "rocksdb.cur-size-all-mem-tables",
+    "rocksdb.estimate-num-keys",
+    "rocksdb.actual-delayed-write-rate",
+    "rocksdb.is-write-stopped",
+];
+
+pub fn observe_grpc_request(endpoint: &str, elapsed: Duration, success: bool) {
+    GRPC_REQUEST_DURATION
+        .with_label_values(&[endpoint])
+        .observe(elapsed.as_secs_f64());
+    if !success {
+        GRPC_REQUEST_ERRORS.with_label_values(&[endpoint]).inc();
+    }
+}
+
+pub fn observe_retry(operation: &str) {
+    RETRIES.with_label_values(&[operation]).inc();
+}
+
+/// records the latency of a request under the route pattern it matched, so
+/// that addresses and hashes in the path don't create new series
+pub fn observe_http_request<B>(response: &ServiceResponse<B>, method: &str, elapsed: Duration) {
+    let route = response
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&route, method, response.status().as_str()])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// refreshes the gauges that are read from the database and the node pool
+/// and encodes every metric in the Prometheus text format
+pub fn render(db: &DB, pool: &NodePool) -> Result<String, prometheus::Error> {
+    match load_last_download_block(db) {
+        Ok(Some(height)) => INDEXED_HEIGHT.set(height as i64),
+        Ok(None) => {}
+        Err(e) => error!("{}", e),
+    }
+    if let Some(tip) = pool.latest_block() {
+        CHAIN_TIP.set(tip as i64);
+    }
+
+    let counters = counters();
+    INDEXED_BLOCKS.set(counters.blocks as i64);
+    INDEXED_TRANSACTIONS.set(counters.transactions as i64);
+    INDEXED_MESSAGES
+        .with_label_values(&[MSG_SEND])
+        .set(counters.send_msgs as i64);
+    INDEXED_MESSAGES
+        .with_label_values(&[MSG_IBC_TRANSFER])
+        .set(counters.ibc_msgs as i64);
+
+    for property in ROCKSDB_PROPERTY_NAMES {
+        if let Ok(Some(value)) = db.property_int_value(property) {
+            ROCKSDB_PROPERTIES
+                .with_label_values(&[property])
+                .set(value as i64);
+        }
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
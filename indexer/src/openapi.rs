@@ -0,0 +1,198 @@
+use actix_web::{HttpResponse, ResponseError};
+use lazy_static::lazy_static;
+use std::fs;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::error::{ApiError, ErrorBody};
+use crate::transactions::database::{Counters, SyncPhase};
+use crate::transactions::endpoints::{
+    AllTransactionResponse, BlockTransactions, ExportFormat, GroupBy, ReadinessResponse,
+    RetryResponse, StatusResponse, StreamEvent, TransactionResponse,
+};
+use crate::transactions::query::{Direction, Order};
+use crate::transactions::summary::{AddressSummary, Counterparty, DenomSummary, Sighting};
+use crate::types::{
+    ApiResponse, CustomCoin, CustomHeight, CustomMsgSend, CustomMsgTransfer, QuarantinedTx,
+    StoredMsg, Transaction, TransactionBlock, TransactionDay, TransactionList,
+};
+use crate::webhooks::{
+    Delivery, DeliveryStatus, NewWebhook, StoredWebhook, Webhook, WebhookFilter,
+};
+
+/// The OpenAPI document of the HTTP API, built from the route annotations in
+/// main.rs and the response types
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Cosmos Indexer API"),
+    paths(
+        crate::get_transactions_v1,
+        crate::get_transactions_by_address_v1,
+        crate::stream_transactions,
+        crate::stream_transactions_ws,
+        crate::export_transactions,
+        crate::get_address_summary,
+        crate::get_all_transactions,
+        crate::get_all_transactions_by_address,
+        crate::get_all_msg_send_transactions,
+        crate::get_all_msg_ibc_transfer_transactions,
+        crate::get_msg_send_transactions_by_address,
+        crate::get_msg_send_transactions_by_address_and_direction,
+        crate::get_status,
+        crate::get_healthz,
+        crate::get_readyz,
+        crate::get_metrics,
+        crate::get_quarantined_transactions,
+        crate::retry_quarantined_transactions,
+        crate::get_webhooks,
+        crate::post_webhook,
+        crate::delete_webhook,
+        crate::get_webhook_deliveries,
+    ),
+    components(schemas(
+        Transaction,
+        TransactionBlock,
+        TransactionDay,
+        TransactionList,
+        StoredMsg,
+        CustomMsgSend,
+        CustomMsgTransfer,
+        CustomCoin,
+        CustomHeight,
+        Direction,
+        Order,
+        GroupBy,
+        ExportFormat,
+        AddressSummary,
+        Sighting,
+        DenomSummary,
+        Counterparty,
+        StreamEvent,
+        AllTransactionResponse,
+        TransactionResponse,
+        BlockTransactions,
+        ApiResponse,
+        StatusResponse,
+        SyncPhase,
+        Counters,
+        ReadinessResponse,
+        QuarantinedTx,
+        RetryResponse,
+        ErrorBody,
+        NewWebhook,
+        WebhookFilter,
+        Webhook,
+        StoredWebhook,
+        Delivery,
+        DeliveryStatus,
+    )),
+    modifiers(&ApiKeySecurity),
+    tags(
+        (name = "transactions", description = "Indexed messages"),
+        (name = "deprecated", description = "Unversioned routes kept for existing clients"),
+        (name = "operations", description = "Status, probes and metrics"),
+        (name = "admin", description = "Quarantine and webhook management"),
+    )
+)]
+pub struct ApiDoc;
+
+/// documents the two ways of passing an API key and the admin token, the
+/// routes refer to them by name
+struct ApiKeySecurity;
+
+impl Modify for ApiKeySecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+            );
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-admin-token"))),
+            );
+        }
+    }
+}
+
+lazy_static! {
+    static ref OPENAPI_JSON: String = ApiDoc::openapi()
+        .to_pretty_json()
+        .expect("Failed to serialize the OpenAPI document");
+}
+
+// the Redoc release the docs page loads from its CDN, pinned so a new
+// release can't change what runs on the page
+const REDOC_VERSION: &str = "2.1.3";
+
+// where the docs page loads a local copy of Redoc from, see get_redoc_script
+// in main.rs
+const REDOC_SCRIPT_PATH: &str = "/docs/redoc.standalone.js";
+
+const DOCS_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>Cosmos Indexer API</title>
+    <meta charset="utf-8"/>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+  </head>
+  <body>
+    <redoc spec-url="/openapi.json"></redoc>
+    <script src="{script_url}"></script>
+  </body>
+</html>
+"#;
+
+/// The docs page and the Redoc script it runs, which is served by the API
+/// itself when a local copy is configured and loaded from the CDN otherwise
+pub struct Docs {
+    page: String,
+    script: Option<Vec<u8>>,
+}
+
+impl Docs {
+    pub fn load(script_path: Option<&str>) -> Result<Docs, String> {
+        let script = match script_path {
+            Some(path) => {
+                Some(fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?)
+            }
+            None => None,
+        };
+        let script_url = match script {
+            Some(_) => REDOC_SCRIPT_PATH.to_string(),
+            None => format!(
+                "https://cdn.redoc.ly/redoc/v{}/bundles/redoc.standalone.js",
+                REDOC_VERSION
+            ),
+        };
+        Ok(Docs {
+            page: DOCS_PAGE.replace("{script_url}", &script_url),
+            script,
+        })
+    }
+}
+
+pub fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(OPENAPI_JSON.as_str())
+}
+
+pub fn docs_page(docs: &Docs) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(docs.page.clone())
+}
+
+pub fn redoc_script(docs: &Docs) -> HttpResponse {
+    match &docs.script {
+        Some(script) => HttpResponse::Ok()
+            .content_type("application/javascript")
+            .body(script.clone()),
+        None => ApiError::not_found("No local copy of Redoc is configured.").error_response(),
+    }
+}
@@ -0,0 +1,60 @@
+use lazy_static::lazy_static;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+lazy_static! {
+    // the receiver is kept here so that sending never fails for lack of one
+    static ref SHUTDOWN: (watch::Sender<bool>, watch::Receiver<bool>) = watch::channel(false);
+}
+
+/// tells the indexer to stop taking on new work
+pub fn request_shutdown() {
+    let _ = SHUTDOWN.0.send(true);
+}
+
+pub fn shutdown_requested() -> bool {
+    *SHUTDOWN.1.borrow()
+}
+
+/// resolves once shutdown has been requested
+pub async fn wait_for_shutdown() {
+    let mut receiver = SHUTDOWN.1.clone();
+    while !*receiver.borrow() {
+        if receiver.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// sleeps for the given duration unless shutdown is requested first
+pub async fn sleep_or_shutdown(duration: Duration) {
+    tokio::select! {
+        _ = sleep(duration) => {}
+        _ = wait_for_shutdown() => {}
+    }
+}
+
+/// resolves on ctrl-c or, on unix, SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,137 @@
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use log::{error, info};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls_pemfile::Item;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+
+use crate::shutdown::wait_for_shutdown;
+
+/// The certificate and key read from PEM files, kept behind a lock so they
+/// can be swapped with `reload` while the server keeps running
+pub struct ReloadableCert {
+    cert_path: String,
+    key_path: String,
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    pub fn load(cert_path: &str, key_path: &str) -> Result<Self, String> {
+        Ok(ReloadableCert {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            certified_key: RwLock::new(Arc::new(load_certified_key(cert_path, key_path)?)),
+        })
+    }
+
+    /// reads both files again, the old certificate stays in use if they are
+    /// missing or invalid
+    pub fn reload(&self) -> Result<(), String> {
+        let certified_key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.certified_key.write().unwrap() = Arc::new(certified_key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.read().unwrap().clone())
+    }
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey, String> {
+    let cert_file =
+        File::open(cert_path).map_err(|e| format!("Failed to open {}: {}", cert_path, e))?;
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .map_err(|e| format!("Failed to read {}: {}", cert_path, e))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", cert_path));
+    }
+
+    let key_file =
+        File::open(key_path).map_err(|e| format!("Failed to open {}: {}", key_path, e))?;
+    let key = rustls_pemfile::read_all(&mut BufReader::new(key_file))
+        .map_err(|e| format!("Failed to read {}: {}", key_path, e))?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key found in {}", key_path))?;
+    let signing_key = any_supported_type(&key)
+        .map_err(|e| format!("Unsupported private key in {}: {:?}", key_path, e))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+pub fn server_config(cert: Arc<ReloadableCert>) -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(cert)
+}
+
+/// reloads the certificate on every SIGHUP until shutdown, so renewed
+/// certificates are picked up without dropping connections
+#[cfg(unix)]
+pub async fn reload_on_sighup(cert: Arc<ReloadableCert>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!(
+                "Failed to listen for SIGHUP, certificates won't be reloaded: {}",
+                e
+            );
+            return;
+        }
+    };
+    loop {
+        tokio::select! {
+            received = hangup.recv() => {
+                if received.is_none() {
+                    return;
+                }
+            }
+            _ = wait_for_shutdown() => return,
+        }
+        match cert.reload() {
+            Ok(()) => info!("Reloaded TLS certificate from {}", cert.cert_path),
+            Err(e) => error!(
+                "Failed to reload TLS certificate, keeping the old one: {}",
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(_cert: Arc<ReloadableCert>) {}
+
+/// sends plain HTTP requests to the same host and path on the HTTPS port
+pub fn redirect_to_https(req: &HttpRequest, https_port: u16) -> HttpResponse {
+    let connection_info = req.connection_info();
+    let host = connection_info.host();
+    // drop the port of the plain HTTP listener, IPv6 hosts are bracketed
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    let location = if https_port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, https_port, path)
+    };
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
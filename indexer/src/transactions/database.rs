@@ -1,45 +1,185 @@
 use actix_rt::System;
 use cosmos_sdk_proto_althea::{
     cosmos::bank::v1beta1::MsgSend,
-    cosmos::tx::v1beta1::{TxBody, TxRaw},
+    cosmos::tx::v1beta1::{AuthInfo, TxBody, TxRaw},
     ibc::{applications::transfer::v1::MsgTransfer, core::client::v1::Height},
     tendermint::types::Block,
 };
-use deep_space::{client::Contact, utils::decode_any};
+use deep_space::utils::decode_any;
 use futures::future::join_all;
 
 use lazy_static::lazy_static;
-use log::{error, info};
-use rocksdb::DB;
+use log::{error, info, warn};
+use rocksdb::{ColumnFamily, Direction, IteratorMode, WriteBatch, DB};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 use std::{
     sync::{Arc, RwLock},
-    thread,
+    thread::{self, JoinHandle},
     time::Instant,
 };
-use tokio::time::sleep;
+use utoipa::ToSchema;
 
-use crate::types::{CustomCoin, CustomHeight, CustomMsgSend, CustomMsgTransfer};
+use super::feed::notify_stored;
+use super::subscription::NewBlockSubscription;
+use crate::metrics::observe_retry;
+use crate::nodes::NodePool;
+use crate::shutdown::{shutdown_requested, sleep_or_shutdown, wait_for_shutdown};
+use crate::types::{
+    CustomCoin, CustomHeight, CustomMsgSend, CustomMsgTransfer, QuarantinedTx, TxInfo,
+};
 
 pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 
+// message types as they appear in the database keys
+pub const MSG_SEND: &str = "msgSend";
+pub const MSG_IBC_TRANSFER: &str = "msgIbcTransfer";
+pub const MSG_TYPES: [&str; 2] = [MSG_SEND, MSG_IBC_TRANSFER];
+
+// digits of the message index in the keys, so messages of a tx sort by it
+const MSG_INDEX_WIDTH: usize = 5;
+
+/// The decoded contents of a block
+pub struct DecodedBlock {
+    pub block_number: u64,
+    pub timestamp: i64,
+    pub msgs: Vec<IndexedMsg>,
+    /// fee and memo of the transactions with indexed messages
+    pub txs: Vec<IndexedTx>,
+    pub quarantined: Vec<QuarantinedTx>,
+}
+
+/// A decoded message ready to be stored, the key is made of the zero padded
+/// block number, message type, block timestamp, tx hash and the zero padded
+/// index of the message in the tx so that rows sort by height and index
+pub struct IndexedMsg {
+    pub block_number: u64,
+    pub timestamp: i64,
+    pub tx_hash: String,
+    pub msg_index: u32,
+    pub msg_type: &'static str,
+    pub data: String,
+}
+
+impl IndexedMsg {
+    pub fn key(&self) -> String {
+        format!(
+            "{:012}:{}:{}:{}:{:05}",
+            self.block_number, self.msg_type, self.timestamp, self.tx_hash, self.msg_index
+        )
+    }
+}
+
+/// The fee and memo of a decoded transaction, stored once per transaction in
+/// the tx info column family rather than with each of its messages
+pub struct IndexedTx {
+    pub block_number: u64,
+    pub tx_hash: String,
+    pub info: TxInfo,
+}
+
+impl IndexedTx {
+    pub fn key(&self) -> String {
+        tx_info_key(self.block_number, &self.tx_hash)
+    }
+}
+
+/// `{height:012}:{tx_hash}`, like the quarantine keys
+fn tx_info_key(block_number: u64, tx_hash: &str) -> String {
+    format!("{:012}:{}", block_number, tx_hash)
+}
+
 lazy_static! {
-    static ref COUNTER: Arc<RwLock<Counters>> = Arc::new(RwLock::new(Counters {
-        blocks: 0,
-        transactions: 0,
-        msgs: 0,
-        ibc_msgs: 0,
-        send_msgs: 0
+    static ref COUNTER: Arc<RwLock<Counters>> = Arc::new(RwLock::new(Counters::default()));
+    static ref STATUS: Arc<RwLock<SyncStatus>> = Arc::new(RwLock::new(SyncStatus {
+        phase: SyncPhase::Starting,
+        phase_started: Instant::now(),
+        phase_start_height: 0,
+        last_block_time: None,
+        last_heartbeat: None,
     }));
 }
 
+/// totals of everything indexed, persisted along with the checkpoint
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, ToSchema)]
 pub struct Counters {
-    blocks: u64,
-    transactions: u64,
-    msgs: u64,
-    ibc_msgs: u64,
-    send_msgs: u64, // Changed from send_eth_msgs
+    pub blocks: u64,
+    pub transactions: u64,
+    pub msgs: u64,
+    pub ibc_msgs: u64,
+    pub send_msgs: u64, // Changed from send_eth_msgs
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncPhase {
+    Starting,
+    Historical,
+    Live,
+    /// serving a secondary instance that follows another process' writes
+    Replica,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SyncStatus {
+    pub phase: SyncPhase,
+    // when the current phase started and the checkpoint at that time, used
+    // to compute throughput
+    pub phase_started: Instant,
+    pub phase_start_height: u64,
+    // timestamp of the newest block indexed by this process
+    pub last_block_time: Option<i64>,
+    // last time the indexer thread made it through a loop, it stops moving
+    // if the thread dies or hangs
+    pub last_heartbeat: Option<Instant>,
+}
+
+impl Counters {
+    fn add_block(&mut self, block: &DecodedBlock) {
+        let tx_hashes: HashSet<&str> = block.msgs.iter().map(|msg| msg.tx_hash.as_str()).collect();
+        self.blocks += 1;
+        self.transactions += tx_hashes.len() as u64;
+        self.msgs += block.msgs.len() as u64;
+        for msg in block.msgs.iter() {
+            match msg.msg_type {
+                MSG_SEND => self.send_msgs += 1,
+                MSG_IBC_TRANSFER => self.ibc_msgs += 1,
+                _ => {}
+            }
+        }
+    }
+
+    fn add(&mut self, other: &Counters) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.msgs += other.msgs;
+        self.ibc_msgs += other.ibc_msgs;
+        self.send_msgs += other.send_msgs;
+    }
+}
+
+pub fn counters() -> Counters {
+    *COUNTER.read().unwrap()
+}
+
+pub fn sync_status() -> SyncStatus {
+    *STATUS.read().unwrap()
+}
+
+fn heartbeat() {
+    STATUS.write().unwrap().last_heartbeat = Some(Instant::now());
+}
+
+fn set_phase(db: &DB, phase: SyncPhase) {
+    let mut status = STATUS.write().unwrap();
+    status.last_heartbeat = Some(Instant::now());
+    status.phase = phase;
+    status.phase_started = Instant::now();
+    status.phase_start_height = load_last_download_block(db).ok().flatten().unwrap_or(0);
 }
 impl From<&Height> for CustomHeight {
     fn from(height: &Height) -> Self {
@@ -89,61 +229,59 @@ impl From<&MsgTransfer> for CustomMsgTransfer {
     }
 }
 
-const MAX_RETRIES: usize = 5;
-
-/// finds earliest available block using binary search, keep in mind this cosmos
-/// node will not have history from chain halt upgrades and could be state synced
-/// and missing history before the state sync
-/// Iterative implementation due to the limitations of async recursion in rust.
-async fn get_earliest_block(contact: &Contact, mut start: u64, mut end: u64) -> u64 {
-    while start <= end {
-        let mid = start + (end - start) / 2;
-        let mid_block = contact.get_block(mid).await;
-        if let Ok(Some(_)) = mid_block {
-            end = mid - 1;
-        } else {
-            start = mid + 1;
-        }
-    }
-    // off by one error correction fix bounds logic up top
-    start + 1
-}
-
-async fn get_latest_block(contact: &Contact) -> Result<u64, Box<dyn std::error::Error>> {
+async fn get_latest_block(
+    pool: &NodePool,
+    max_retries: usize,
+) -> Result<u64, Box<dyn std::error::Error>> {
     let mut retries = 0;
     loop {
-        match contact.get_chain_status().await {
+        match pool.get_chain_status().await {
             Ok(deep_space::client::ChainStatus::Moving { block_height }) => {
                 return Ok(block_height);
             }
             Ok(_) => {
                 retries += 1;
-                if retries >= MAX_RETRIES {
+                if retries >= max_retries {
                     return Err("Failed to get moving chain status".into());
                 }
+                observe_retry("chain_status");
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
             Err(e) => {
                 retries += 1;
-                if retries >= MAX_RETRIES {
+                if retries >= max_retries {
                     return Err(Box::new(e));
                 }
+                observe_retry("chain_status");
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
         }
     }
 }
 
-// Loads sendToEth & MsgTransfer messages from grpc endpoint & downlaods to DB
-async fn search(contact: &Contact, start: u64, end: u64, db: &DB) {
+// Loads MsgSend & MsgTransfer messages from grpc endpoint & downlaods to DB
+// returns the counts of the range once all of it was indexed, None if it was
+// abandoned early on shutdown or failed
+async fn search(
+    pool: &NodePool,
+    start: u64,
+    end: u64,
+    db: &DB,
+    options: &SyncOptions,
+) -> Option<Counters> {
+    let mut indexed = Counters::default();
     if start > end {
-        return;
+        return Some(indexed);
     }
     let mut current_start = start;
     let retries = AtomicUsize::new(0);
 
     loop {
-        let blocks_result = contact.get_block_range(current_start, end).await;
+        if shutdown_requested() {
+            return None;
+        }
+        heartbeat();
+        let blocks_result = pool.get_block_range(current_start, end).await;
 
         let blocks = match blocks_result {
             Ok(result) => {
@@ -152,11 +290,12 @@ async fn search(contact: &Contact, start: u64, end: u64, db: &DB) {
             }
             Err(e) => {
                 let current_retries = retries.fetch_add(1, Ordering::Relaxed);
-                if current_retries >= MAX_RETRIES {
+                if current_retries >= options.max_retries {
                     error!("Error getting block range: {:?}, exceeded max retries", e);
-                    break;
+                    return None;
                 } else {
                     error!("Error getting block range: {:?}, retrying", e);
+                    observe_retry("block_range");
                     tokio::time::sleep(Duration::from_secs(1)).await;
                     continue;
                 }
@@ -167,247 +306,388 @@ async fn search(contact: &Contact, start: u64, end: u64, db: &DB) {
             break;
         }
 
+        // a block the node doesn't have would be left behind the checkpoint,
+        // the batch fails instead so the range is fetched again
+        if let Some(missing) = blocks.iter().position(|block| block.is_none()) {
+            error!(
+                "Block {} is not available, blocks {} to {} were not indexed",
+                current_start + missing as u64,
+                current_start,
+                end
+            );
+            return None;
+        }
+
         // gets the last block that was successfully fetched to be referenced
         // in case of grpc error
-        let last_block_height = blocks
-            .last()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .header
-            .as_ref()
-            .unwrap()
-            .height;
-
-        // counters for transactions, messages, blocks & tx types
-        let mut tx_counter = 0;
-        let mut msg_counter = 0;
-        let mut ibc_transfer_counter = 0;
-        let mut send_msg_counter = 0;
-        let blocks_len = blocks.len() as u64;
-
-        for block in blocks.into_iter() {
-            let block = block.unwrap();
-            // Get the block number
-            let block_number = block.header.as_ref().unwrap().height;
-
-            // tx fetching
-            for tx in block.data.unwrap().txs {
-                let raw_tx_any = prost_types::Any {
-                    type_url: "/cosmos.tx.v1beta1.TxRaw".to_string(),
-                    value: tx,
-                };
-                let tx_raw: TxRaw = decode_any(raw_tx_any.clone()).unwrap();
-                let value_ref: &[u8] = raw_tx_any.value.as_ref();
-                let tx_hash = sha256::digest(value_ref).to_uppercase();
-                let body_any = prost_types::Any {
-                    type_url: "/cosmos.tx.v1beta1.TxBody".to_string(),
-                    value: tx_raw.body_bytes,
-                };
-                let tx_body: TxBody = decode_any(body_any).unwrap();
-
-                let mut has_msg_ibc_transfer = false;
-
-                // tx sorting
-                for message in tx_body.messages {
-                    if message.type_url == "/cosmos.bank.v1beta1.MsgSend" {
-                        msg_counter += 1;
-
-                        let msg_send_any = prost_types::Any {
-                            type_url: "/cosmos.bank.v1beta1.MsgSend".to_string(),
-                            value: message.value,
-                        };
-                        let msg_send: Result<MsgSend, _> = decode_any(msg_send_any);
-
-                        if let Ok(msg_send) = msg_send {
-                            let custom_msg_send = CustomMsgSend::from(&msg_send);
-                            let timestamp = block
-                                .header
-                                .as_ref()
-                                .unwrap()
-                                .time
-                                .as_ref()
-                                .unwrap()
-                                .seconds;
-                            let key =
-                                format!("{:012}:msgSend:{}:{}", block_number, timestamp, tx_hash);
-                            save_msg_send(db, &key, &custom_msg_send);
-                            send_msg_counter += 1;
-                        }
-                    } else if message.type_url == "/ibc.applications.transfer.v1.MsgTransfer" {
-                        has_msg_ibc_transfer = true;
-                        msg_counter += 1;
-
-                        let msg_ibc_transfer_any = prost_types::Any {
-                            type_url: "/ibc.applications.transfer.v1.MsgTransfer".to_string(),
-                            value: message.value,
-                        };
-                        let msg_ibc_transfer: Result<MsgTransfer, _> =
-                            decode_any(msg_ibc_transfer_any);
-
-                        if let Ok(msg_ibc_transfer) = msg_ibc_transfer {
-                            let custom_ibc_transfer = CustomMsgTransfer::from(&msg_ibc_transfer);
-                            let timestamp = block
-                                .header
-                                .as_ref()
-                                .unwrap()
-                                .time
-                                .as_ref()
-                                .unwrap()
-                                .seconds;
-                            let key = format!(
-                                "{:012}:msgIbcTransfer:{}:{}",
-                                block_number, timestamp, tx_hash
-                            );
-                            save_msg_ibc_transfer(db, &key, &custom_ibc_transfer);
-                        }
-                    }
-                }
+        let last_block_height = match blocks
+            .iter()
+            .rev()
+            .flatten()
+            .find_map(|block| block.header.as_ref())
+        {
+            Some(header) => header.height,
+            None => {
+                error!("No block headers in range {} to {}", current_start, end);
+                return None;
+            }
+        };
 
-                if has_msg_ibc_transfer {
-                    tx_counter += 1;
-                    ibc_transfer_counter += 1;
+        for block in blocks.into_iter().flatten() {
+            let decoded = match decode_block(&block, &options.msg_types) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    error!("Skipping malformed block: {}", e);
+                    continue;
                 }
-            }
-            current_start = (last_block_height as u64) + 1;
-            if current_start > end {
-                break;
-            }
+            };
+            save_block(db, &decoded);
+            record_block(&decoded);
+            indexed.add_block(&decoded);
+        }
+
+        current_start = (last_block_height as u64) + 1;
+        if current_start > end {
+            break;
         }
-        let mut c = COUNTER.write().unwrap();
-        c.blocks += blocks_len;
-        c.transactions += tx_counter;
-        c.msgs += msg_counter;
-        c.ibc_msgs += ibc_transfer_counter;
-        c.send_msgs += send_msg_counter;
     }
+    Some(indexed)
 }
 
-async fn continuous_indexing(db: &DB, chain_node_grpc: &str, chain_prefix: &str) {
-    let contact: Contact = Contact::new(chain_node_grpc, REQUEST_TIMEOUT, chain_prefix).unwrap();
+/// indexes every block after the checkpoint up to and including `latest_block`.
+/// It stops at the first block that can't be fetched without moving the
+/// checkpoint past it, the next event or poll starts over from there. NewBlock
+/// events often arrive before the gRPC node serves the block.
+async fn index_new_blocks(db: &DB, pool: &NodePool, latest_block: u64, options: &SyncOptions) {
+    let last_indexed_block = match load_last_download_block(db) {
+        Ok(height) => height.unwrap_or(0),
+        Err(e) => {
+            error!("{}, retrying later", e);
+            return;
+        }
+    };
+    if latest_block <= last_indexed_block {
+        return;
+    }
+    for block_height in (last_indexed_block + 1)..=latest_block {
+        if shutdown_requested() {
+            return;
+        }
+        heartbeat();
+        let indexed = match pool.get_block(block_height).await {
+            Ok(Some(block)) => {
+                let indexed = process_block(&block, db, &options.msg_types).await;
+                info!("Processed block {}", block_height);
+                indexed
+            }
+            Ok(None) => {
+                warn!(
+                    "Block {} is not available yet, retrying later",
+                    block_height
+                );
+                return;
+            }
+            Err(e) => {
+                error!(
+                    "Error fetching block {}: {:?}, retrying later",
+                    block_height, e
+                );
+                return;
+            }
+        };
+        save_last_download_block(db, block_height, &indexed);
+    }
+}
 
+/// indexes new blocks as soon as the node announces them, returns once the
+/// subscription drops or shutdown is requested
+async fn follow_new_blocks(
+    db: &DB,
+    pool: &NodePool,
+    rpc_url: &str,
+    options: &SyncOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut subscription = NewBlockSubscription::connect(rpc_url).await?;
     loop {
-        let last_indexed_block = load_last_download_block(db).unwrap_or(0);
-        let latest_block = match get_latest_block(&contact).await {
+        let height = tokio::select! {
+            height = subscription.next_height() => height?,
+            _ = wait_for_shutdown() => return Ok(()),
+        };
+        // anything missed between polling and subscribing, or while a block
+        // was being processed, is picked up here as well
+        index_new_blocks(db, pool, height, options).await;
+    }
+}
+
+/// follows the chain tip, by subscribing to NewBlock events when a CometBFT
+/// websocket RPC is configured and by polling the gRPC endpoints otherwise or
+/// while the subscription is down
+async fn continuous_indexing(
+    db: &DB,
+    pool: &NodePool,
+    chain_node_rpc: Option<&str>,
+    options: &SyncOptions,
+) {
+    set_phase(db, SyncPhase::Live);
+    while !shutdown_requested() {
+        heartbeat();
+        pool.refresh().await;
+        let latest_block = match get_latest_block(pool, options.max_retries).await {
             Ok(block) => block,
             Err(e) => {
                 error!("Error getting latest block: {:?}", e);
-                sleep(Duration::from_secs(10)).await;
+                sleep_or_shutdown(Duration::from_secs(10)).await;
                 continue;
             }
         };
+        index_new_blocks(db, pool, latest_block, options).await;
 
-        if latest_block > last_indexed_block {
-            for block_height in (last_indexed_block + 1)..=latest_block {
-                match contact.get_block(block_height).await {
-                    Ok(Some(block)) => {
-                        process_block(&contact, &block, db).await;
-                        info!("Processed block {}", block_height);
-                    }
-                    Ok(None) => {
-                        error!("Block {} not found", block_height);
-                    }
-                    Err(e) => {
-                        error!("Error fetching block {}: {:?}", block_height, e);
-                    }
-                }
+        if let Some(rpc_url) = chain_node_rpc {
+            if let Err(e) = follow_new_blocks(db, pool, rpc_url, options).await {
+                error!(
+                    "NewBlock subscription dropped: {:?}, falling back to polling",
+                    e
+                );
             }
-            save_last_download_block(db, latest_block);
         }
 
-        sleep(Duration::from_secs(5)).await;
+        sleep_or_shutdown(Duration::from_secs(5)).await;
     }
 }
 
-async fn process_block(_contact: &Contact, block: &Block, db: &DB) {
-    let block_number = block.header.as_ref().unwrap().height;
-    let timestamp = block
-        .header
-        .as_ref()
-        .unwrap()
+/// stores the block and returns its counts, they are added to the counters
+/// along with the checkpoint
+async fn process_block(block: &Block, db: &DB, msg_types: &[String]) -> Counters {
+    let mut indexed = Counters::default();
+    match decode_block(block, msg_types) {
+        Ok(decoded) => {
+            save_block(db, &decoded);
+            record_block(&decoded);
+            indexed.add_block(&decoded);
+        }
+        Err(e) => error!("Skipping malformed block: {}", e),
+    }
+    indexed
+}
+
+/// adds a block to the sync status
+fn record_block(block: &DecodedBlock) {
+    let mut status = STATUS.write().unwrap();
+    if status
+        .last_block_time
+        .map_or(true, |time| block.timestamp > time)
+    {
+        status.last_block_time = Some(block.timestamp);
+    }
+}
+
+/// decodes every supported message in the block's transactions, transactions
+/// that fail to decode are set aside for the quarantine instead of aborting
+/// the block
+pub fn decode_block(block: &Block, msg_types: &[String]) -> Result<DecodedBlock, String> {
+    let header = block.header.as_ref().ok_or("Block has no header")?;
+    let block_number = header.height as u64;
+    let timestamp = header
         .time
         .as_ref()
-        .unwrap()
+        .ok_or_else(|| format!("Block {} has no time", block_number))?
         .seconds;
+    let data = block
+        .data
+        .as_ref()
+        .ok_or_else(|| format!("Block {} has no data", block_number))?;
 
-    for tx in block.data.as_ref().unwrap().txs.iter() {
-        let raw_tx_any = prost_types::Any {
-            type_url: "/cosmos.tx.v1beta1.TxRaw".to_string(),
-            value: tx.clone(),
-        };
-        let tx_raw: TxRaw = decode_any(raw_tx_any.clone()).unwrap();
-        let value_ref: &[u8] = raw_tx_any.value.as_ref();
-        let tx_hash = sha256::digest(value_ref).to_uppercase();
-        let body_any = prost_types::Any {
-            type_url: "/cosmos.tx.v1beta1.TxBody".to_string(),
-            value: tx_raw.body_bytes,
-        };
-        let tx_body: TxBody = decode_any(body_any).unwrap();
-
-        for message in tx_body.messages {
-            info!("Processing message: {:?}", message.type_url);
-            match message.type_url.as_str() {
-                "/cosmos.bank.v1beta1.MsgSend" => {
-                    let msg_send: MsgSend = decode_any(message).unwrap();
-                    let custom_msg_send = CustomMsgSend::from(&msg_send);
-                    let key = format!("{:012}:msgSend:{}:{}", block_number, timestamp, tx_hash);
-                    save_msg_send(db, &key, &custom_msg_send);
-                }
-                "/ibc.applications.transfer.v1.MsgTransfer" => {
-                    let msg_ibc_transfer: MsgTransfer = decode_any(message).unwrap();
-                    let custom_ibc_transfer = CustomMsgTransfer::from(&msg_ibc_transfer);
-                    let key = format!(
-                        "{:012}:msgIbcTransfer:{}:{}",
-                        block_number, timestamp, tx_hash
-                    );
-                    save_msg_ibc_transfer(db, &key, &custom_ibc_transfer);
-                }
-                _ => {}
+    let mut decoded = DecodedBlock {
+        block_number,
+        timestamp,
+        msgs: Vec::new(),
+        txs: Vec::new(),
+        quarantined: Vec::new(),
+    };
+    for tx in data.txs.iter() {
+        let tx_hash = sha256::digest(tx.as_slice()).to_uppercase();
+        match decode_tx(block_number, timestamp, &tx_hash, tx, msg_types) {
+            Ok((msgs, indexed_tx)) => {
+                decoded.msgs.extend(msgs);
+                decoded.txs.extend(indexed_tx);
+            }
+            Err(e) => {
+                error!(
+                    "Quarantining tx {} in block {}: {}",
+                    tx_hash, block_number, e
+                );
+                decoded.quarantined.push(QuarantinedTx {
+                    block_number,
+                    timestamp,
+                    tx_hash,
+                    error: e,
+                    raw_tx: hex::encode(tx),
+                });
             }
         }
     }
+    Ok(decoded)
+}
+
+/// decodes the supported messages of a single raw transaction, along with its
+/// fee and memo if any message is indexed
+pub fn decode_tx(
+    block_number: u64,
+    timestamp: i64,
+    tx_hash: &str,
+    tx: &[u8],
+    msg_types: &[String],
+) -> Result<(Vec<IndexedMsg>, Option<IndexedTx>), String> {
+    let raw_tx_any = prost_types::Any {
+        type_url: "/cosmos.tx.v1beta1.TxRaw".to_string(),
+        value: tx.to_vec(),
+    };
+    let tx_raw: TxRaw =
+        decode_any(raw_tx_any).map_err(|e| format!("Failed to decode TxRaw: {:?}", e))?;
+    let body_any = prost_types::Any {
+        type_url: "/cosmos.tx.v1beta1.TxBody".to_string(),
+        value: tx_raw.body_bytes,
+    };
+    let tx_body: TxBody =
+        decode_any(body_any).map_err(|e| format!("Failed to decode TxBody: {:?}", e))?;
+    let memo = tx_body.memo.clone();
+
+    let mut msgs = Vec::new();
+    // tx sorting
+    for (msg_index, message) in tx_body.messages.into_iter().enumerate() {
+        let msg_type = match message.type_url.as_str() {
+            "/cosmos.bank.v1beta1.MsgSend" => MSG_SEND,
+            "/ibc.applications.transfer.v1.MsgTransfer" => MSG_IBC_TRANSFER,
+            _ => continue,
+        };
+        // disabled types are skipped before decoding so they never quarantine a tx
+        if !msg_types.iter().any(|enabled| enabled == msg_type) {
+            continue;
+        }
+        let data = match msg_type {
+            MSG_SEND => {
+                let msg_send: MsgSend = decode_any(message)
+                    .map_err(|e| format!("Failed to decode MsgSend: {:?}", e))?;
+                serde_json::to_string(&CustomMsgSend::from(&msg_send)).map_err(|e| e.to_string())?
+            }
+            MSG_IBC_TRANSFER => {
+                let msg_ibc_transfer: MsgTransfer = decode_any(message)
+                    .map_err(|e| format!("Failed to decode MsgTransfer: {:?}", e))?;
+                serde_json::to_string(&CustomMsgTransfer::from(&msg_ibc_transfer))
+                    .map_err(|e| e.to_string())?
+            }
+            _ => continue,
+        };
+        msgs.push(IndexedMsg {
+            block_number,
+            timestamp,
+            tx_hash: tx_hash.to_string(),
+            msg_index: msg_index as u32,
+            msg_type,
+            data,
+        });
+    }
+    if msgs.is_empty() {
+        return Ok((msgs, None));
+    }
+
+    // the auth info is only needed for the fee, a tx is never quarantined
+    // over it. Its messages are stored with an empty fee instead.
+    let auth_info_any = prost_types::Any {
+        type_url: "/cosmos.tx.v1beta1.AuthInfo".to_string(),
+        value: tx_raw.auth_info_bytes,
+    };
+    let auth_info: Option<AuthInfo> = match decode_any(auth_info_any) {
+        Ok(auth_info) => Some(auth_info),
+        Err(e) => {
+            error!(
+                "Failed to decode the AuthInfo of tx {} at height {}, storing it without a fee: {:?}",
+                tx_hash, block_number, e
+            );
+            None
+        }
+    };
+    let fee = auth_info
+        .and_then(|auth_info| auth_info.fee)
+        .map(|fee| {
+            fee.amount
+                .iter()
+                .map(|coin| CustomCoin {
+                    denom: coin.denom.clone(),
+                    amount: coin.amount.clone(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let indexed_tx = IndexedTx {
+        block_number,
+        tx_hash: tx_hash.to_string(),
+        info: TxInfo { fee, memo },
+    };
+    Ok((msgs, Some(indexed_tx)))
+}
+
+/// Controls which blocks are downloaded and how
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    pub test_mode: bool,
+    pub test_block_limit: u64,
+    /// start here on a fresh database or when it is ahead of the checkpoint
+    pub start_height: Option<u64>,
+    /// stop here instead of at the chain tip, live indexing is skipped
+    pub end_height: Option<u64>,
+    /// how many blocks to search per future
+    pub batch_size: u64,
+    /// how many futures to execute at once
+    pub concurrency: usize,
+    /// how many times a failing gRPC request is retried
+    pub max_retries: usize,
+    /// message types to index, see MSG_TYPES
+    pub msg_types: Vec<String>,
 }
 
 pub fn transaction_info_thread(
     db: Arc<DB>,
-    chain_node_grpc: String,
-    chain_prefix: String,
-    test_mode: bool,
-    test_block_limit: u64,
-) {
+    pool: Arc<NodePool>,
+    chain_node_rpc: Option<String>,
+    options: SyncOptions,
+) -> JoinHandle<()> {
     info!("Starting transaction info thread");
 
     thread::spawn(move || {
+        *COUNTER.write().unwrap() = load_counters(&db).unwrap_or_default();
+        if let Err(e) = build_tx_hash_index(&db) {
+            error!("Failed to build the tx hash index: {}", e);
+        }
         let runner = System::new();
         runner.block_on(async {
-            loop {
-                match transactions(
-                    &db,
-                    &chain_node_grpc,
-                    &chain_prefix,
-                    test_mode,
-                    test_block_limit,
-                )
-                .await
-                {
+            while !shutdown_requested() {
+                match transactions(&db, &pool, &options).await {
                     Ok(_) => {
-                        continuous_indexing(&db, &chain_node_grpc, &chain_prefix).await;
+                        if shutdown_requested() {
+                            break;
+                        }
+                        if let Some(end_height) = options.end_height {
+                            info!(
+                                "Indexed up to end height {}, not following the chain",
+                                end_height
+                            );
+                            return;
+                        }
+                        continuous_indexing(&db, &pool, chain_node_rpc.as_deref(), &options).await;
                     }
                     Err(e) => {
                         error!("Error downloading transactions: {:?}", e);
                         let mut retry_interval = Duration::from_secs(1);
                         loop {
+                            sleep_or_shutdown(retry_interval).await;
+                            if shutdown_requested() {
+                                break;
+                            }
+                            heartbeat();
+                            observe_retry("historical_sync");
                             info!("Retrying block download");
-                            sleep(retry_interval).await;
-                            match transactions(
-                                &db,
-                                &chain_node_grpc,
-                                &chain_prefix,
-                                test_mode,
-                                test_block_limit,
-                            )
-                            .await
-                            {
+                            match transactions(&db, &pool, &options).await {
                                 Ok(_) => break,
                                 Err(e) => {
                                     error!("Error in transaction download retry: {:?}", e);
@@ -424,24 +704,58 @@ pub fn transaction_info_thread(
                 }
             }
         });
-    });
+        set_phase(&db, SyncPhase::Stopped);
+        info!("Transaction info thread stopped");
+    })
+}
+
+/// keeps a secondary instance opened by `serve` up to date with the process
+/// indexing into the primary. Nothing is indexed here, so the counters are
+/// reloaded from the checkpoint and the heartbeat tracks the catch-up instead.
+pub async fn follow_primary(db: Arc<DB>, pool: Arc<NodePool>, interval: Duration) {
+    // the chain tip is only needed for /status and /readyz, so the nodes are
+    // checked less often than the primary is followed
+    const POOL_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+    let mut last_refresh: Option<Instant> = None;
+
+    set_phase(&db, SyncPhase::Replica);
+    while !shutdown_requested() {
+        match db.try_catch_up_with_primary() {
+            Ok(()) => {
+                if let Some(counters) = load_counters(&db) {
+                    *COUNTER.write().unwrap() = counters;
+                }
+                match load_last_download_block(&db) {
+                    Ok(height) => notify_stored(height.unwrap_or(0)),
+                    Err(e) => error!("{}", e),
+                }
+                heartbeat();
+            }
+            Err(e) => error!("Failed to catch up with the primary database: {}", e),
+        }
+        if last_refresh.map_or(true, |time| time.elapsed() >= POOL_REFRESH_INTERVAL) {
+            pool.refresh().await;
+            last_refresh = Some(Instant::now());
+        }
+        sleep_or_shutdown(interval).await;
+    }
+    set_phase(&db, SyncPhase::Stopped);
 }
 
 /// creates batches of transactions found and sorted using the search function
 /// then writes them to the db
 pub async fn transactions(
     db: &DB,
-    chain_node_grpc: &str,
-    chain_prefix: &str,
-    test_mode: bool,
-    test_block_limit: u64,
+    pool: &NodePool,
+    options: &SyncOptions,
 ) -> Result<(), Box<dyn std::error::Error>> {
     info!("Started downloading & parsing transactions");
-    let contact: Contact = Contact::new(chain_node_grpc, REQUEST_TIMEOUT, chain_prefix)?;
+    set_phase(db, SyncPhase::Historical);
+    pool.refresh().await;
 
     let mut retries = 0;
     let status = loop {
-        let result = contact.get_chain_status().await;
+        let result = pool.get_chain_status().await;
 
         match result {
             Ok(chain_status) => {
@@ -449,11 +763,12 @@ pub async fn transactions(
             }
             Err(e) => {
                 retries += 1;
-                if retries >= MAX_RETRIES {
+                if retries >= options.max_retries {
                     error!("Failed to get chain status, grpc error: {:?}", e);
                     return Err(Box::new(e));
                 } else {
                     error!("Failed to get chain status, grpc error: {:?}, retrying", e);
+                    observe_retry("chain_status");
                     tokio::time::sleep(Duration::from_secs(1)).await;
                 }
             }
@@ -469,7 +784,7 @@ pub async fn transactions(
                 latest_block = Some(block_height);
                 break;
             }
-            _ => match contact.get_chain_status().await {
+            _ => match pool.get_chain_status().await {
                 Ok(chain_status) => {
                     if let deep_space::client::ChainStatus::Moving { block_height } = chain_status {
                         latest_block = Some(block_height);
@@ -479,11 +794,12 @@ pub async fn transactions(
                 }
                 Err(e) => {
                     retries += 1;
-                    if retries >= MAX_RETRIES {
+                    if retries >= options.max_retries {
                         error!("Failed to get chain status: {:?}", e);
                         return Err(Box::new(e));
                     } else {
                         error!("Failed to get chain status: {:?}, retrying", e);
+                        observe_retry("chain_status");
                         tokio::time::sleep(Duration::from_secs(1)).await;
                     }
                 }
@@ -493,67 +809,44 @@ pub async fn transactions(
 
     let latest_block = latest_block.expect("Node is not synced or not running");
 
-    // now we find the earliest block this node has via binary search, we could just read it from
-    // the error message you get when requesting an earlier block, but this was more fun
-    let earliest_block = get_earliest_block(&contact, 0, latest_block).await;
+    // the earliest block any of the nodes has, found via binary search during the
+    // health check, we could just read it from the error message you get when
+    // requesting an earlier block, but this was more fun
+    let earliest_block = pool.earliest_block().unwrap_or(1);
 
-    let earliest_block = match load_last_download_block(db) {
-        Some(block) => block,
-        None => earliest_block,
+    // start_height only applies to a fresh database or to skip ahead of the
+    // checkpoint, otherwise every restart would download from it again. The
+    // checkpoint block itself is indexed already, indexing it again would
+    // count its messages twice.
+    let earliest_block = match (options.start_height, load_last_download_block(db)?) {
+        (Some(height), Some(checkpoint)) if height > checkpoint => height,
+        (_, Some(checkpoint)) => checkpoint + 1,
+        (Some(height), None) => height,
+        (None, None) => earliest_block,
     };
 
-    let end_block = if test_mode {
-        std::cmp::min(earliest_block + test_block_limit, latest_block)
-    } else {
-        latest_block
-    };
+    let mut end_block = latest_block;
+    if let Some(end_height) = options.end_height {
+        end_block = std::cmp::min(end_height, end_block);
+    }
+    if options.test_mode {
+        end_block = std::cmp::min(earliest_block + options.test_block_limit, end_block);
+    }
 
     info!("There are already {} blocks in the database.", latest_block,);
 
     info!(
         "This node has {} blocks to download, downloading to database",
-        latest_block - earliest_block
+        (end_block + 1).saturating_sub(earliest_block)
     );
     let start = Instant::now();
 
-    // how many blocks to search per future
-    const BATCH_SIZE: u64 = 500;
-    // how many futures to execute at once
-    const EXECUTE_SIZE: usize = 10;
-    let mut pos = earliest_block;
-    let mut futures = Vec::new();
-    while pos < end_block {
-        let start = pos;
-        let end = if end_block - pos > BATCH_SIZE {
-            pos += BATCH_SIZE;
-            pos
-        } else {
-            pos = end_block;
-            end_block
-        };
-        let fut = search(&contact, start, end, db);
-        futures.push(fut);
+    if !index_range(db, pool, earliest_block, end_block, true, options).await? {
+        info!("Historical sync stopped for shutdown, progress has been saved");
+        return Ok(());
     }
 
-    let futures = futures.into_iter();
-
-    let mut buf = Vec::new();
-
-    for fut in futures {
-        if buf.len() < EXECUTE_SIZE {
-            buf.push(fut);
-        } else {
-            let _ = join_all(buf).await;
-            info!(
-                "Completed batch of {} blocks",
-                BATCH_SIZE * EXECUTE_SIZE as u64
-            );
-            buf = Vec::new();
-        }
-    }
-    let _ = join_all(buf).await;
-
-    let counter = COUNTER.read().unwrap();
+    let counter = counters();
     info!(
     "Successfully downloaded {} blocks and {} tx containing {} send msgs and {} ibc_transfer msgs in {} seconds",
     counter.blocks,
@@ -562,44 +855,896 @@ pub async fn transactions(
     counter.ibc_msgs,
     start.elapsed().as_secs()
 );
-    save_last_download_block(db, end_block);
+    save_last_download_block(db, end_block, &Counters::default());
+    Ok(())
+}
+
+/// indexes an arbitrary range of blocks, for filling gaps in the history,
+/// without moving the checkpoint used by the historical and live sync
+pub async fn backfill(
+    db: &DB,
+    pool: &NodePool,
+    from: u64,
+    to: u64,
+    options: &SyncOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if from > to {
+        return Err(format!("Invalid range, {} is after {}", from, to).into());
+    }
+    info!("Backfilling blocks {} to {}", from, to);
+    pool.refresh().await;
+    let start = Instant::now();
+
+    if !index_range(db, pool, from, to, false, options).await? {
+        return Err("Backfill interrupted by shutdown".into());
+    }
+
+    info!(
+        "Backfilled blocks {} to {} in {} seconds",
+        from,
+        to,
+        start.elapsed().as_secs()
+    );
+    Ok(())
+}
+
+/// deletes everything stored for `from..=to` and indexes it again with the
+/// current decoding logic, limited to `types` when not empty. Each block is
+/// replaced with a single atomic write so readers never see it half done.
+pub async fn reindex(
+    db: &DB,
+    pool: &NodePool,
+    from: u64,
+    to: u64,
+    types: &[String],
+    options: &SyncOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if from > to {
+        return Err(format!("Invalid range, {} is after {}", from, to).into());
+    }
+    if let Some(unknown) = types.iter().find(|t| !MSG_TYPES.contains(&t.as_str())) {
+        return Err(format!(
+            "Unknown message type {}, expected one of {}",
+            unknown,
+            MSG_TYPES.join(",")
+        )
+        .into());
+    }
+    // the blocks are decoded with the configured types, rows of a type that
+    // isn't enabled would be deleted without being written again
+    if let Some(disabled) = types.iter().find(|t| !options.msg_types.contains(t)) {
+        return Err(format!(
+            "Message type {} is not enabled, enabled types are {}",
+            disabled,
+            options.msg_types.join(",")
+        )
+        .into());
+    }
+    info!("Reindexing blocks {} to {}", from, to);
+    pool.refresh().await;
+    let start = Instant::now();
+
+    for (batch_start, batch_end) in pool.split_range(from, to, options.batch_size) {
+        let mut retries = 0;
+        let blocks = loop {
+            match pool.get_block_range(batch_start, batch_end).await {
+                Ok(blocks) => break blocks,
+                Err(e) => {
+                    retries += 1;
+                    if retries >= options.max_retries {
+                        return Err(Box::new(e));
+                    }
+                    error!("Error getting block range: {:?}, retrying", e);
+                    observe_retry("block_range");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        };
+
+        for (height, block) in (batch_start..=batch_end).zip(blocks) {
+            // a block the node doesn't have would keep its old rows, so the
+            // reindex stops instead of leaving the range half done
+            let block = block.ok_or_else(|| {
+                format!(
+                    "Block {} is not available, blocks {} to {} were not reindexed",
+                    height, height, to
+                )
+            })?;
+            let decoded = match decode_block(&block, &options.msg_types) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    error!("Skipping malformed block: {}", e);
+                    continue;
+                }
+            };
+            let mut batch = WriteBatch::default();
+            for key in block_keys(db, decoded.block_number) {
+                if matches_types(&key, types) {
+                    batch.delete(key);
+                }
+            }
+            // quarantined txs are decoded again as well, so the old entries
+            // are replaced regardless of the selected types
+            let quarantine = quarantine_cf(db);
+            for key in block_quarantine_keys(db, decoded.block_number) {
+                batch.delete_cf(quarantine, key);
+            }
+            for tx in decoded.quarantined.iter() {
+                batch.put_cf(quarantine, tx.key(), serde_json::to_vec(tx)?);
+            }
+            for msg in decoded.msgs.iter() {
+                if types.is_empty() || types.iter().any(|t| t == msg.msg_type) {
+                    batch.put(msg.key().as_bytes(), msg.data.as_bytes());
+                }
+            }
+            // hashes of txs whose messages are gone now stay in the index,
+            // looking them up finds nothing at their height
+            index_tx_hashes(db, &mut batch, &decoded.msgs);
+            // the fee and memo of a tx don't depend on the selected types,
+            // writing them again fills them in for blocks indexed before
+            // they were stored
+            let tx_info = tx_info_cf(db);
+            for tx in decoded.txs.iter() {
+                batch.put_cf(tx_info, tx.key(), serde_json::to_vec(&tx.info)?);
+            }
+            db.write(batch)?;
+            notify_stored(decoded.block_number);
+        }
+        info!("Reindexed blocks {} to {}", batch_start, batch_end);
+    }
+
+    info!(
+        "Reindexed blocks {} to {} in {} seconds",
+        from,
+        to,
+        start.elapsed().as_secs()
+    );
     Ok(())
 }
 
-//saves serialized transactions to database
-pub fn save_msg_send(db: &DB, key: &str, data: &CustomMsgSend) {
-    let data_json = serde_json::to_string(data).unwrap();
-    db.put(key.as_bytes(), data_json.as_bytes()).unwrap();
+/// all keys stored for a block, they share the zero padded height prefix
+fn block_keys(db: &DB, block_number: u64) -> Vec<Box<[u8]>> {
+    let prefix = format!("{:012}:", block_number);
+    db.iterator(IteratorMode::From(prefix.as_bytes(), Direction::Forward))
+        .filter_map(|item| item.ok())
+        .map(|(key, _)| key)
+        .take_while(|key| key.starts_with(prefix.as_bytes()))
+        .collect()
 }
 
-pub fn save_msg_ibc_transfer(db: &DB, key: &str, data: &CustomMsgTransfer) {
-    let data_json = serde_json::to_string(data).unwrap();
-    db.put(key.as_bytes(), data_json.as_bytes()).unwrap();
+/// all quarantine entries for a block, keyed by zero padded height as well
+fn block_quarantine_keys(db: &DB, block_number: u64) -> Vec<Box<[u8]>> {
+    let prefix = format!("{:012}:", block_number);
+    db.iterator_cf(
+        quarantine_cf(db),
+        IteratorMode::From(prefix.as_bytes(), Direction::Forward),
+    )
+    .filter_map(|item| item.ok())
+    .map(|(key, _)| key)
+    .take_while(|key| key.starts_with(prefix.as_bytes()))
+    .collect()
+}
+
+/// whether a message key of one of `types` is in an earlier format, without
+/// the message index or with an unpadded one
+fn is_legacy_key(key: &[u8], types: &HashSet<&str>) -> bool {
+    match parse_tx_key(key) {
+        Ok(Some(tx_key)) if types.contains(tx_key.msg_type.as_str()) => {
+            let parts: Vec<&[u8]> = key.split(|byte| *byte == b':').collect();
+            parts.len() != 5 || parts[4].len() != MSG_INDEX_WIDTH
+        }
+        _ => false,
+    }
+}
+
+fn matches_types(key: &[u8], types: &[String]) -> bool {
+    if types.is_empty() {
+        return true;
+    }
+    match parse_tx_key(key) {
+        Ok(Some(tx_key)) => types.iter().any(|t| *t == tx_key.msg_type),
+        _ => false,
+    }
+}
+
+/// The parts of a `{height:012}:{msgType}:{timestamp}:{tx_hash}:{msg_index}`
+/// key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxKey {
+    pub block_number: u64,
+    pub msg_type: String,
+    pub timestamp: i64,
+    pub tx_hash: String,
+    pub msg_index: u32,
+}
+
+/// parses the key of a stored message, `Ok(None)` for the checkpoint and
+/// other keys that don't hold a message and `Err` for corrupt keys. Keys
+/// written before the message index was added count as index 0.
+pub fn parse_tx_key(key: &[u8]) -> Result<Option<TxKey>, String> {
+    let key_str =
+        std::str::from_utf8(key).map_err(|_| format!("Key {:?} is not valid UTF-8", key))?;
+    let key_parts: Vec<&str> = key_str.split(':').collect();
+    if key_parts.len() != 4 && key_parts.len() != 5 {
+        return Ok(None);
+    }
+    let msg_index = match key_parts.get(4) {
+        Some(index) => index
+            .parse::<u32>()
+            .map_err(|e| format!("Invalid message index in key {}: {}", key_str, e))?,
+        None => 0,
+    };
+    let block_number = key_parts[0]
+        .parse::<u64>()
+        .map_err(|e| format!("Invalid height in key {}: {}", key_str, e))?;
+    let timestamp = key_parts[2]
+        .parse::<i64>()
+        .map_err(|e| format!("Invalid timestamp in key {}: {}", key_str, e))?;
+    Ok(Some(TxKey {
+        block_number,
+        msg_type: key_parts[1].to_string(),
+        timestamp,
+        tx_hash: key_parts[3].to_string(),
+        msg_index,
+    }))
+}
+
+/// downloads `start..=end` in concurrent batches using the search function,
+/// when `save_progress` is set the checkpoint is moved forward after every
+/// group of batches, but only over batches that all completed. Returns false
+/// if it stopped early for shutdown and an error if a batch failed.
+async fn index_range(
+    db: &DB,
+    pool: &NodePool,
+    start: u64,
+    end: u64,
+    save_progress: bool,
+    options: &SyncOptions,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    // batches are split where the configured node height ranges start and
+    // end, so every batch is fetched from a node that has all of its blocks
+    let ranges = pool.split_range(start, end, options.batch_size);
+
+    for group in ranges.chunks(options.concurrency) {
+        if shutdown_requested() {
+            return Ok(false);
+        }
+        let futures = group
+            .iter()
+            .map(|(batch_start, batch_end)| search(pool, *batch_start, *batch_end, db, options));
+        let completed = join_all(futures).await;
+
+        // batches finish in any order, the checkpoint only covers the ones
+        // up to the first that didn't complete so no block is left behind it.
+        // Only their counts are added, the others are indexed and counted
+        // again on the next attempt.
+        let done = completed
+            .iter()
+            .take_while(|completed| completed.is_some())
+            .count();
+        if save_progress && done > 0 {
+            let mut indexed = Counters::default();
+            for counts in completed.iter().take(done).flatten() {
+                indexed.add(counts);
+            }
+            save_last_download_block(db, group[done - 1].1, &indexed);
+        }
+        if done < group.len() {
+            // ranges abandoned for shutdown are downloaded again on the next
+            // start
+            if shutdown_requested() {
+                return Ok(false);
+            }
+            let (failed_start, failed_end) = group[done];
+            return Err(format!(
+                "Failed to index blocks {} to {}, blocks from {} on were not saved as progress",
+                failed_start, failed_end, failed_start
+            )
+            .into());
+        }
+
+        let (group_start, _) = group[0];
+        let (_, group_end) = group[group.len() - 1];
+        info!("Completed batch of {} blocks", group_end - group_start + 1);
+    }
+    Ok(true)
+}
+
+//saves serialized transactions and quarantined txs to database
+pub fn save_block(db: &DB, block: &DecodedBlock) {
+    let mut batch = WriteBatch::default();
+    // rows of the block stored in an earlier key format would stay next to
+    // the new ones, they are replaced for the types that are written
+    let types: HashSet<&str> = block.msgs.iter().map(|msg| msg.msg_type).collect();
+    if !types.is_empty() {
+        for key in block_keys(db, block.block_number) {
+            if is_legacy_key(&key, &types) {
+                batch.delete(key);
+            }
+        }
+    }
+    for msg in block.msgs.iter() {
+        batch.put(msg.key().as_bytes(), msg.data.as_bytes());
+    }
+    index_tx_hashes(db, &mut batch, &block.msgs);
+    let tx_info = tx_info_cf(db);
+    for tx in block.txs.iter() {
+        batch.put_cf(tx_info, tx.key(), serde_json::to_vec(&tx.info).unwrap());
+    }
+    let quarantine = quarantine_cf(db);
+    for tx in block.quarantined.iter() {
+        batch.put_cf(quarantine, tx.key(), serde_json::to_vec(tx).unwrap());
+    }
+    db.write(batch).unwrap();
+    notify_stored(block.block_number);
+}
+
+// transactions that failed to decode, kept with the raw bytes so they can be
+// inspected and retried once the decoding is fixed
+pub const QUARANTINE_CF: &str = "quarantine";
+
+fn quarantine_cf(db: &DB) -> &ColumnFamily {
+    db.cf_handle(QUARANTINE_CF)
+        .expect("Quarantine column family is missing")
+}
+
+// the fee and memo of every transaction with indexed messages
+pub const TX_INFO_CF: &str = "tx_info";
+
+fn tx_info_cf(db: &DB) -> &ColumnFamily {
+    db.cf_handle(TX_INFO_CF)
+        .expect("Tx info column family is missing")
+}
+
+// the height of every transaction with indexed messages by hash, so a tx can
+// be looked up without scanning every message
+pub const TX_HASH_CF: &str = "tx_hashes";
+
+// set in the tx hash column family once the messages stored before it
+// existed were added to it. Hashes are hex, so it can't clash with one.
+const TX_HASH_INDEX_BUILT_KEY: &str = "index_built";
+
+fn tx_hash_cf(db: &DB) -> &ColumnFamily {
+    db.cf_handle(TX_HASH_CF)
+        .expect("Tx hash column family is missing")
+}
+
+/// adds the height of the txs of `msgs` to the tx hash index
+fn index_tx_hashes(db: &DB, batch: &mut WriteBatch, msgs: &[IndexedMsg]) {
+    let tx_hashes = tx_hash_cf(db);
+    for msg in msgs.iter() {
+        batch.put_cf(
+            tx_hashes,
+            msg.tx_hash.as_bytes(),
+            msg.block_number.to_string().as_bytes(),
+        );
+    }
+}
+
+/// the height a transaction is stored at, None if it isn't indexed
+pub fn load_tx_height(db: &DB, tx_hash: &str) -> Result<Option<u64>, rocksdb::Error> {
+    Ok(db
+        .get_cf(tx_hash_cf(db), tx_hash.as_bytes())?
+        .and_then(|bytes| String::from_utf8_lossy(&bytes).parse::<u64>().ok()))
+}
+
+/// adds the messages stored before the tx hash index existed to it, once.
+/// Messages stored from then on are added as they are written.
+pub fn build_tx_hash_index(db: &DB) -> Result<(), Box<dyn std::error::Error>> {
+    let tx_hashes = tx_hash_cf(db);
+    if db
+        .get_cf(tx_hashes, TX_HASH_INDEX_BUILT_KEY.as_bytes())?
+        .is_some()
+    {
+        return Ok(());
+    }
+    info!("Building the tx hash index");
+    let start = Instant::now();
+    let mut batch = WriteBatch::default();
+    let mut indexed = 0;
+    for item in db.iterator(IteratorMode::Start) {
+        let (key, _) = item?;
+        let tx_key = match parse_tx_key(&key) {
+            Ok(Some(tx_key)) => tx_key,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Skipping corrupt key: {}", e);
+                continue;
+            }
+        };
+        batch.put_cf(
+            tx_hashes,
+            tx_key.tx_hash.as_bytes(),
+            tx_key.block_number.to_string().as_bytes(),
+        );
+        indexed += 1;
+        if batch.len() >= 10_000 {
+            db.write(std::mem::take(&mut batch))?;
+        }
+    }
+    batch.put_cf(tx_hashes, TX_HASH_INDEX_BUILT_KEY.as_bytes(), b"1");
+    db.write(batch)?;
+    info!(
+        "Built the tx hash index from {} messages in {} seconds",
+        indexed,
+        start.elapsed().as_secs()
+    );
+    Ok(())
+}
+
+/// the fee and memo of a transaction, None for transactions indexed before
+/// they were stored
+pub fn load_tx_info(db: &DB, block_number: u64, tx_hash: &str) -> Option<TxInfo> {
+    let key = tx_info_key(block_number, tx_hash);
+    let bytes = match db.get_cf(tx_info_cf(db), key.as_bytes()) {
+        Ok(bytes) => bytes?,
+        Err(e) => {
+            error!("Failed to read the info of tx {}: {}", tx_hash, e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&bytes) {
+        Ok(info) => Some(info),
+        Err(e) => {
+            warn!("Skipping corrupt info of tx {}: {}", tx_hash, e);
+            None
+        }
+    }
+}
+
+pub fn load_quarantined_txs(db: &DB) -> Vec<QuarantinedTx> {
+    let mut txs = Vec::new();
+    for item in db.iterator_cf(quarantine_cf(db), IteratorMode::Start) {
+        match item {
+            Ok((_, value)) => match serde_json::from_slice::<QuarantinedTx>(&value) {
+                Ok(tx) => txs.push(tx),
+                Err(e) => error!("Failed to deserialize quarantined tx: {}", e),
+            },
+            Err(err) => error!("RocksDB iterator error: {}", err),
+        }
+    }
+    txs
+}
+
+/// decodes every quarantined tx again, the ones that now succeed are indexed
+/// and released from the quarantine. Returns how many were released and how
+/// many are still failing. Feeds already past a released tx don't stream it,
+/// it is read by queries and new feeds.
+pub fn retry_quarantined_txs(
+    db: &DB,
+    msg_types: &[String],
+) -> Result<(usize, usize), rocksdb::Error> {
+    let quarantine = quarantine_cf(db);
+    let mut released = 0;
+    let mut failing = 0;
+    let mut released_height = None;
+    for mut tx in load_quarantined_txs(db) {
+        let decoded = hex::decode(&tx.raw_tx)
+            .map_err(|e| format!("Invalid raw tx: {}", e))
+            .and_then(|raw| decode_tx(tx.block_number, tx.timestamp, &tx.tx_hash, &raw, msg_types));
+        let mut batch = WriteBatch::default();
+        match decoded {
+            Ok((msgs, indexed_tx)) => {
+                for msg in msgs.iter() {
+                    batch.put(msg.key().as_bytes(), msg.data.as_bytes());
+                }
+                index_tx_hashes(db, &mut batch, &msgs);
+                if let Some(indexed_tx) = indexed_tx {
+                    batch.put_cf(
+                        tx_info_cf(db),
+                        indexed_tx.key(),
+                        serde_json::to_vec(&indexed_tx.info).unwrap(),
+                    );
+                }
+                batch.delete_cf(quarantine, tx.key());
+                released += 1;
+                released_height = released_height.max(Some(tx.block_number));
+            }
+            Err(e) => {
+                tx.error = e;
+                batch.put_cf(quarantine, tx.key(), serde_json::to_vec(&tx).unwrap());
+                failing += 1;
+            }
+        }
+        db.write(batch)?;
+    }
+    if let Some(height) = released_height {
+        notify_stored(height);
+    }
+    info!(
+        "Released {} txs from quarantine, {} still failing",
+        released, failing
+    );
+    Ok((released, failing))
 }
 
 // Load & deseralize transactions
+// missing, unreadable and corrupt entries all come back as None, the
+// failure is logged
 pub fn load_msg_send(db: &DB, key: &str) -> Option<CustomMsgSend> {
-    let res = db.get(key.as_bytes()).unwrap();
-    res.map(|bytes| serde_json::from_slice::<CustomMsgSend>(&bytes).unwrap())
+    load_msg(db, key)
 }
 
 pub fn load_msg_ibc_transfer(db: &DB, key: &str) -> Option<CustomMsgTransfer> {
-    let res = db.get(key.as_bytes()).unwrap();
-    res.map(|bytes| serde_json::from_slice::<CustomMsgTransfer>(&bytes).unwrap())
+    load_msg(db, key)
+}
+
+fn load_msg<T: DeserializeOwned>(db: &DB, key: &str) -> Option<T> {
+    let bytes = match db.get(key.as_bytes()) {
+        Ok(bytes) => bytes?,
+        Err(e) => {
+            error!("Failed to read {}: {}", key, e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&bytes) {
+        Ok(msg) => Some(msg),
+        Err(e) => {
+            warn!("Skipping corrupt entry {}: {}", key, e);
+            None
+        }
+    }
 }
 
 // timestamp function using downloaded blocks as a source of truth
-const LAST_DOWNLOAD_BLOCK_KEY: &str = "last_download_block";
+pub const LAST_DOWNLOAD_BLOCK_KEY: &str = "last_download_block";
+
+const COUNTERS_KEY: &str = "counters";
 
-fn save_last_download_block(db: &DB, timestamp: u64) {
-    db.put(
+// the counts of the blocks the checkpoint now covers are added to the counters
+// and saved with it so they survive restarts. The checkpoint never moves
+// backwards, e.g. to an end height below it.
+fn save_last_download_block(db: &DB, timestamp: u64, indexed: &Counters) {
+    let saved = match load_last_download_block(db) {
+        Ok(saved) => saved,
+        // without the saved checkpoint it could be moved backwards, the next
+        // save tries again
+        Err(e) => {
+            error!("{}, not saving progress", e);
+            return;
+        }
+    };
+    let timestamp = saved.map_or(timestamp, |saved| saved.max(timestamp));
+    // held until the write is done, so concurrent saves don't lose counts
+    let mut counter = COUNTER.write().unwrap();
+    let mut updated = *counter;
+    updated.add(indexed);
+    let mut batch = WriteBatch::default();
+    batch.put(
         LAST_DOWNLOAD_BLOCK_KEY.as_bytes(),
         timestamp.to_string().as_bytes(),
-    )
-    .unwrap();
+    );
+    batch.put(
+        COUNTERS_KEY.as_bytes(),
+        serde_json::to_vec(&updated).unwrap(),
+    );
+    db.write(batch).unwrap();
+    *counter = updated;
+    drop(counter);
+    // the feeds read up to the checkpoint, blocks stored above it are only
+    // streamed once it covers them
+    notify_stored(timestamp);
+}
+
+/// the last indexed height, None before anything was indexed. Fails if the
+/// database can't be read or the checkpoint is corrupt.
+pub fn load_last_download_block(db: &DB) -> Result<Option<u64>, String> {
+    let bytes = match db.get(LAST_DOWNLOAD_BLOCK_KEY.as_bytes()) {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(format!("Failed to read the checkpoint: {}", e)),
+    };
+    String::from_utf8_lossy(&bytes)
+        .parse::<u64>()
+        .map(Some)
+        .map_err(|e| format!("Corrupt checkpoint: {}", e))
 }
 
-fn load_last_download_block(db: &DB) -> Option<u64> {
-    let res = db.get(LAST_DOWNLOAD_BLOCK_KEY.as_bytes()).unwrap();
-    res.map(|bytes| String::from_utf8_lossy(&bytes).parse::<u64>().unwrap())
+fn load_counters(db: &DB) -> Option<Counters> {
+    match db.get(COUNTERS_KEY.as_bytes()) {
+        Ok(bytes) => bytes.and_then(|bytes| serde_json::from_slice::<Counters>(&bytes).ok()),
+        Err(e) => {
+            error!("Failed to read the counters: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::auth::API_KEYS_CF;
+    use crate::grpc::tests::{serve, MockNode};
+    use crate::webhooks::{WEBHOOKS_CF, WEBHOOK_DELIVERIES_CF, WEBHOOK_QUEUE_CF};
+    use rocksdb::Options;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    /// a database with the indexer's column families in the temp dir,
+    /// removed when dropped
+    pub(crate) struct TestDb {
+        pub(crate) db: Arc<DB>,
+        path: PathBuf,
+    }
+
+    impl TestDb {
+        pub(crate) fn new() -> TestDb {
+            let path =
+                std::env::temp_dir().join(format!("indexer-test-{:016x}", rand::random::<u64>()));
+            let mut options = Options::default();
+            options.create_if_missing(true);
+            options.create_missing_column_families(true);
+            let db = DB::open_cf(
+                &options,
+                &path,
+                [
+                    QUARANTINE_CF,
+                    TX_INFO_CF,
+                    TX_HASH_CF,
+                    API_KEYS_CF,
+                    WEBHOOKS_CF,
+                    WEBHOOK_DELIVERIES_CF,
+                    WEBHOOK_QUEUE_CF,
+                ],
+            )
+            .unwrap();
+            TestDb {
+                db: Arc::new(db),
+                path,
+            }
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    /// a msgSend stored in block `height`, blocks are 5 seconds apart
+    pub(crate) fn send_msg(
+        height: u64,
+        tx_hash: &str,
+        msg_index: u32,
+        from: &str,
+        to: &str,
+        amount: &str,
+        denom: &str,
+    ) -> IndexedMsg {
+        let msg = CustomMsgSend {
+            from_address: from.to_string(),
+            to_address: to.to_string(),
+            amount: vec![CustomCoin {
+                denom: denom.to_string(),
+                amount: amount.to_string(),
+            }],
+        };
+        IndexedMsg {
+            block_number: height,
+            timestamp: 1712757731 + height as i64 * 5,
+            tx_hash: tx_hash.to_string(),
+            msg_index,
+            msg_type: MSG_SEND,
+            data: serde_json::to_string(&msg).unwrap(),
+        }
+    }
+
+    /// stores the messages like the sync does, a block per height
+    pub(crate) fn seed(db: &DB, msgs: Vec<IndexedMsg>) {
+        let mut blocks: BTreeMap<u64, Vec<IndexedMsg>> = BTreeMap::new();
+        for msg in msgs {
+            blocks.entry(msg.block_number).or_default().push(msg);
+        }
+        for (block_number, msgs) in blocks {
+            save_block(
+                db,
+                &DecodedBlock {
+                    block_number,
+                    timestamp: msgs[0].timestamp,
+                    msgs,
+                    txs: Vec::new(),
+                    quarantined: Vec::new(),
+                },
+            );
+        }
+    }
+
+    fn options(batch_size: u64, concurrency: usize) -> SyncOptions {
+        SyncOptions {
+            test_mode: false,
+            test_block_limit: 0,
+            start_height: None,
+            end_height: None,
+            batch_size,
+            concurrency,
+            max_retries: 1,
+            msg_types: MSG_TYPES.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    async fn pool(node: MockNode) -> NodePool {
+        let endpoint = serve(node).await.parse().unwrap();
+        NodePool::new(
+            &[endpoint],
+            &BTreeMap::new(),
+            &BTreeMap::new(),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+    }
+
+    fn msg(block_number: u64, tx_hash: &str, msg_index: u32) -> IndexedMsg {
+        IndexedMsg {
+            block_number,
+            timestamp: 1712757731,
+            tx_hash: tx_hash.to_string(),
+            msg_index,
+            msg_type: MSG_SEND,
+            data: "{}".to_string(),
+        }
+    }
+
+    fn keys(db: &DB, block_number: u64) -> Vec<String> {
+        block_keys(db, block_number)
+            .iter()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn pads_the_message_index_in_keys() {
+        let key = msg(1850, "ABC", 3).key();
+        assert_eq!(key, "000000001850:msgSend:1712757731:ABC:00003");
+        assert_eq!(
+            parse_tx_key(key.as_bytes()).unwrap(),
+            Some(TxKey {
+                block_number: 1850,
+                msg_type: MSG_SEND.to_string(),
+                timestamp: 1712757731,
+                tx_hash: "ABC".to_string(),
+                msg_index: 3,
+            })
+        );
+        // the index sorts numerically
+        assert!(msg(1850, "ABC", 9).key() < msg(1850, "ABC", 10).key());
+    }
+
+    #[test]
+    fn parses_legacy_keys() {
+        let types = HashSet::from([MSG_SEND]);
+        let without_index = b"000000001850:msgSend:1712757731:ABC";
+        assert_eq!(parse_tx_key(without_index).unwrap().unwrap().msg_index, 0);
+        assert!(is_legacy_key(without_index, &types));
+
+        let unpadded = b"000000001850:msgSend:1712757731:ABC:3";
+        assert_eq!(parse_tx_key(unpadded).unwrap().unwrap().msg_index, 3);
+        assert!(is_legacy_key(unpadded, &types));
+
+        let current = msg(1850, "ABC", 3).key();
+        assert!(!is_legacy_key(current.as_bytes(), &types));
+        // only rows of the types being written are replaced
+        let ibc = b"000000001850:msgIbcTransfer:1712757731:ABC";
+        assert!(!is_legacy_key(ibc, &types));
+
+        assert_eq!(parse_tx_key(LAST_DOWNLOAD_BLOCK_KEY.as_bytes()), Ok(None));
+        assert_eq!(parse_tx_key(COUNTERS_KEY.as_bytes()), Ok(None));
+        assert!(parse_tx_key(b"0000000018x0:msgSend:1712757731:ABC").is_err());
+        assert!(parse_tx_key(b"000000001850:msgSend:1712757731:ABC:x").is_err());
+    }
+
+    #[test]
+    fn save_block_replaces_rows_in_legacy_formats() {
+        let test = TestDb::new();
+        let legacy = [
+            "000000000010:msgSend:1712757731:ABC",
+            "000000000010:msgSend:1712757731:ABC:1",
+            "000000000010:msgIbcTransfer:1712757731:DEF",
+        ];
+        for key in legacy {
+            test.db.put(key, "{}").unwrap();
+        }
+        // another block is left alone
+        test.db
+            .put("000000000011:msgSend:1712757736:GHI", "{}")
+            .unwrap();
+
+        save_block(
+            &test.db,
+            &DecodedBlock {
+                block_number: 10,
+                timestamp: 1712757731,
+                msgs: vec![msg(10, "ABC", 0), msg(10, "ABC", 1)],
+                txs: Vec::new(),
+                quarantined: Vec::new(),
+            },
+        );
+
+        assert_eq!(
+            keys(&test.db, 10),
+            vec![
+                "000000000010:msgIbcTransfer:1712757731:DEF".to_string(),
+                msg(10, "ABC", 0).key(),
+                msg(10, "ABC", 1).key(),
+            ]
+        );
+        assert_eq!(keys(&test.db, 11).len(), 1);
+        assert_eq!(load_tx_height(&test.db, "ABC").unwrap(), Some(10));
+    }
+
+    #[tokio::test]
+    async fn moves_the_checkpoint_over_complete_ranges() {
+        let test = TestDb::new();
+        let pool = pool(MockNode::new(20)).await;
+
+        assert!(index_range(&test.db, &pool, 1, 20, true, &options(5, 2))
+            .await
+            .unwrap());
+        assert_eq!(load_last_download_block(&test.db).unwrap(), Some(20));
+    }
+
+    #[tokio::test]
+    async fn stops_the_checkpoint_before_a_missing_block() {
+        let test = TestDb::new();
+        let pool = pool(MockNode {
+            missing: Some(13),
+            ..MockNode::new(20)
+        })
+        .await;
+
+        // blocks 1-10 complete in the first group, 11-15 fails in the second
+        // even though 16-20 completes next to it
+        assert!(index_range(&test.db, &pool, 1, 20, true, &options(5, 2))
+            .await
+            .is_err());
+        assert_eq!(load_last_download_block(&test.db).unwrap(), Some(10));
+        assert!(search(&pool, 11, 15, &test.db, &options(5, 1))
+            .await
+            .is_none());
+        assert!(search(&pool, 16, 20, &test.db, &options(5, 1))
+            .await
+            .is_some());
+    }
+
+    #[test]
+    fn keeps_the_messages_when_the_fee_can_not_be_decoded() {
+        use cosmos_sdk_proto_althea::cosmos::base::v1beta1::Coin;
+        use prost::Message;
+
+        let msg = MsgSend {
+            from_address: "manifest1alice".to_string(),
+            to_address: "manifest1bob".to_string(),
+            amount: vec![Coin {
+                denom: "umfx".to_string(),
+                amount: "10".to_string(),
+            }],
+        };
+        let body = TxBody {
+            messages: vec![prost_types::Any {
+                type_url: "/cosmos.bank.v1beta1.MsgSend".to_string(),
+                value: msg.encode_to_vec(),
+            }],
+            memo: "rent".to_string(),
+            ..TxBody::default()
+        };
+        let tx = TxRaw {
+            body_bytes: body.encode_to_vec(),
+            // a truncated field key
+            auth_info_bytes: vec![0xff, 0xff, 0xff],
+            signatures: Vec::new(),
+        };
+
+        let (msgs, indexed_tx) = decode_tx(
+            7,
+            1712757766,
+            "ABC",
+            &tx.encode_to_vec(),
+            &[MSG_SEND.to_string()],
+        )
+        .unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].key(), "000000000007:msgSend:1712757766:ABC:00000");
+        let info = indexed_tx.unwrap().info;
+        assert!(info.fee.is_empty());
+        assert_eq!(info.memo, "rent");
+    }
 }
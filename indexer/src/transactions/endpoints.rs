@@ -1,276 +1,717 @@
-use crate::types::{ApiResponse, CustomMsgSend, CustomMsgTransfer};
-
+use crate::error::ApiError;
+use crate::metrics;
+use crate::nodes::NodePool;
+use crate::transactions::database::{
+    counters, load_last_download_block, load_quarantined_txs, parse_tx_key, retry_quarantined_txs,
+    sync_status, Counters, SyncOptions, SyncPhase, TxKey, LAST_DOWNLOAD_BLOCK_KEY,
+};
+use crate::transactions::export::{CsvExport, CSV_HEADER};
+use crate::transactions::feed::{FeedEvent, Tail};
+use crate::transactions::query::{
+    block_time, decode_msg, for_each_msg, group_by_block, group_by_day, load_page, parse_tz,
+    Direction, FilterError, FilterParams, Order,
+};
+use crate::transactions::summary::load_summary;
+use crate::types::{ApiResponse, CustomMsgSend, CustomMsgTransfer, Transaction, TransactionList};
+
+use actix_web::http::header;
+use actix_web::web::Bytes;
 use actix_web::Responder;
-use actix_web::{web, HttpResponse};
-use chrono::{DateTime, Datelike, Local, NaiveDateTime, Utc};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{Datelike, Utc};
+use chrono_tz::Tz;
+use futures::stream::{self, StreamExt};
 
 use log::error;
 
 use rocksdb::DB;
+use rust_decimal::Decimal;
 
-use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize)]
-struct BlockTransactions {
+#[derive(Serialize, ToSchema)]
+pub struct BlockTransactions {
     block_number: u64,
     transactions: Vec<ApiResponse>,
     formatted_date: String,
 }
 
-#[derive(Serialize)]
-struct TransactionResponse {
+#[derive(Serialize, ToSchema)]
+pub struct TransactionResponse {
     tx_hash: String,
     block_number: u64,
     formatted_date: String,
     data: CustomMsgSend,
 }
 
-#[derive(Serialize)]
-struct AllTransactionResponse {
+#[derive(Serialize, ToSchema)]
+pub struct AllTransactionResponse {
     tx_hash: String,
     block_number: u64,
     formatted_date: String,
     transaction_type: String,
+    #[schema(value_type = Object)]
     data: serde_json::Value,
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct StatusResponse {
+    phase: SyncPhase,
+    last_indexed_height: Option<u64>,
+    chain_tip: Option<u64>,
+    lag_blocks: Option<u64>,
+    lag_seconds: Option<i64>,
+    blocks_per_second: f64,
+    eta_seconds: Option<u64>,
+    counters: Counters,
+}
+
+/// Thresholds used by the readiness probe
+#[derive(Debug, Clone, Copy)]
+pub struct ReadinessOptions {
+    /// how many blocks the indexer may be behind the chain tip
+    pub max_lag_blocks: u64,
+    /// how long the indexer thread and the gRPC endpoints may go without
+    /// making progress or answering
+    pub max_idle: Duration,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    ready: bool,
+    phase: SyncPhase,
+    indexer_alive: bool,
+    lag_blocks: Option<u64>,
+    lag_ok: bool,
+    last_grpc_success_seconds: Option<u64>,
+    grpc_ok: bool,
+}
+
 type BlockData = (String, Vec<ApiResponse>);
 
-pub async fn get_all_transactions(db: web::Data<Arc<DB>>) -> impl Responder {
-    let transactions = get_all_filtered_transactions(&db, None);
-    HttpResponse::Ok().json(transactions)
+/// like `decode_msg` but into a JSON value, as returned by the mixed endpoints
+fn decode_msg_value<T: DeserializeOwned + Serialize>(
+    tx_key: &TxKey,
+    value: &[u8],
+) -> Option<(T, serde_json::Value)> {
+    let msg: T = decode_msg(tx_key, value)?;
+    match serde_json::to_value(&msg) {
+        Ok(json) => Some((msg, json)),
+        Err(e) => {
+            error!("Failed to serialize {}: {}", tx_key.msg_type, e);
+            None
+        }
+    }
+}
+
+/// Query parameters of the `/v1/transactions` endpoints
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TransactionQuery {
+    /// only messages of this type, `msgSend` or `msgIbcTransfer`
+    #[serde(rename = "type")]
+    pub msg_type: Option<String>,
+    /// only with an address, whether it sent or received the messages
+    pub direction: Option<Direction>,
+    pub group_by: Option<GroupBy>,
+    /// only messages moving this denom
+    pub denom: Option<String>,
+    /// by height, newest first by default
+    pub order: Option<Order>,
+    /// IANA zone for the timestamps, days and the date filters, UTC if unset
+    pub tz: Option<String>,
+    /// first day to include, `YYYY-MM-DD`
+    pub from: Option<String>,
+    /// last day to include, `YYYY-MM-DD`
+    pub to: Option<String>,
+    /// messages per page, 100 by default and at most 1000
+    pub limit: Option<usize>,
+    /// continue after the message with this cursor, the `X-Next-Cursor`
+    /// header of the previous page
+    pub after: Option<String>,
+}
+
+const DEFAULT_PAGE_LIMIT: usize = 100;
+const MAX_PAGE_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Block,
+    Day,
+}
+
+/// Query parameter of the unversioned endpoints
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TimeZoneQuery {
+    /// IANA zone for `formatted_date`, UTC if unset
+    pub tz: Option<String>,
+}
+
+/// The result of retrying the quarantine
+#[derive(Serialize, ToSchema)]
+pub struct RetryResponse {
+    /// transactions that decoded and were stored
+    released: usize,
+    /// transactions that still fail to decode
+    failing: usize,
+}
+
+fn time_zone(tz: Option<&str>) -> Result<Tz, ApiError> {
+    match tz {
+        Some(name) => Ok(parse_tz(name)?),
+        None => Ok(Tz::UTC),
+    }
+}
+
+impl From<FilterError> for ApiError {
+    fn from(e: FilterError) -> Self {
+        ApiError::bad_request(e.code(), e.to_string())
+    }
+}
+
+/// cursors are storage keys, anything else is rejected before seeking to it
+fn check_cursor(cursor: &str) -> Result<(), ApiError> {
+    if !matches!(parse_tx_key(cursor.as_bytes()), Ok(Some(_))) {
+        return Err(ApiError::bad_request(
+            "invalid_cursor",
+            format!("Invalid cursor {}", cursor),
+        ));
+    }
+    Ok(())
+}
+
+/// one page of the transactions of every address or, when given, of one
+/// address, newest first unless `order=asc` is passed. The cursor of the
+/// next page is returned in the `X-Next-Cursor` header.
+pub async fn get_transactions_v1(
+    db: web::Data<Arc<DB>>,
+    address: Option<String>,
+    query: TransactionQuery,
+) -> Result<HttpResponse, ApiError> {
+    let order = query.order.unwrap_or(Order::Desc);
+    let group_by = query.group_by;
+    let (filter, tz) = FilterParams {
+        address,
+        direction: query.direction,
+        msg_type: query.msg_type,
+        denom: query.denom,
+        tz: query.tz,
+        from: query.from,
+        to: query.to,
+    }
+    .into_filter()?;
+    if let Some(cursor) = &query.after {
+        check_cursor(cursor)?;
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+    let page = load_page(&db, &filter, order, tz, query.after.as_deref(), limit)?;
+    let next_cursor = match page.transactions.last() {
+        Some((cursor, _)) if page.has_more => Some(cursor.clone()),
+        _ => None,
+    };
+    let transactions = page
+        .transactions
+        .into_iter()
+        .map(|(_, transaction)| transaction)
+        .collect();
+    let response = match group_by {
+        Some(GroupBy::Block) => TransactionList::Blocks(group_by_block(transactions)),
+        Some(GroupBy::Day) => TransactionList::Days(group_by_day(transactions, tz)),
+        None => TransactionList::Transactions(transactions),
+    };
+    let mut builder = HttpResponse::Ok();
+    if let Some(cursor) = next_cursor {
+        builder.insert_header(("X-Next-Cursor", cursor));
+    }
+    Ok(builder.json(response))
+}
+
+/// Query parameters of the `/v1/stream` endpoints
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    /// only messages this address sent or received
+    pub address: Option<String>,
+    /// only with an address, whether it sent or received the messages
+    pub direction: Option<Direction>,
+    /// only messages of this type, `msgSend` or `msgIbcTransfer`
+    #[serde(rename = "type")]
+    pub msg_type: Option<String>,
+    /// only messages moving this denom
+    pub denom: Option<String>,
+    /// only messages moving at least this amount, of `denom` if set
+    pub min_amount: Option<String>,
+    /// IANA zone for the timestamps, UTC if unset
+    pub tz: Option<String>,
+    /// send the stored messages from this height on first
+    pub from_height: Option<u64>,
+    /// continue after the message with this cursor, wins over `from_height`
+    pub after: Option<String>,
+}
+
+/// A message sent over the WebSocket stream
+#[derive(Serialize, ToSchema)]
+pub struct StreamEvent {
+    /// pass as `after` to resume after this message
+    cursor: String,
+    transaction: Transaction,
+}
+
+/// Query parameters of the export endpoint
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// `csv`, the default and only format so far
+    pub format: Option<ExportFormat>,
+    /// only messages of this type, `msgSend` or `msgIbcTransfer`
+    #[serde(rename = "type")]
+    pub msg_type: Option<String>,
+    /// only coins of this denom
+    pub denom: Option<String>,
+    /// IANA zone for the dates and the date filters, UTC if unset
+    pub tz: Option<String>,
+    /// first day to include, `YYYY-MM-DD`
+    pub from: Option<String>,
+    /// last day to include, `YYYY-MM-DD`
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+}
+
+/// streams the coins the address sent and received as CSV, oldest first,
+/// without loading the whole history
+pub async fn export_transactions(
+    db: web::Data<Arc<DB>>,
+    address: String,
+    query: ExportQuery,
+) -> Result<HttpResponse, ApiError> {
+    let format = query.format.unwrap_or(ExportFormat::Csv);
+    let (filter, tz) = FilterParams {
+        address: Some(address.clone()),
+        msg_type: query.msg_type,
+        denom: query.denom,
+        tz: query.tz,
+        from: query.from,
+        to: query.to,
+        ..FilterParams::default()
+    }
+    .into_filter()?;
+    let export = match format {
+        ExportFormat::Csv => CsvExport::new(db.get_ref().clone(), filter, tz),
+    };
+    let header = stream::once(async { Ok(Bytes::from_static(CSV_HEADER.as_bytes())) });
+    let rows = stream::unfold(export, |mut export| async move {
+        match export.next_rows() {
+            Ok(Some(rows)) => Some((Ok::<_, actix_web::Error>(Bytes::from(rows)), export)),
+            Ok(None) => None,
+            // the status was sent already, a truncated file is all that can
+            // be reported
+            Err(e) => {
+                error!("Database error while exporting transactions: {}", e);
+                None
+            }
+        }
+    });
+    // addresses are alphanumeric, anything else is kept out of the header
+    let file_name: String = address
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.csv\"", file_name),
+        ))
+        .streaming(header.chain(rows)))
+}
+
+/// Query parameters of the address summary
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SummaryQuery {
+    /// IANA zone for the timestamps and the date filters, UTC if unset
+    pub tz: Option<String>,
+    /// first day to include, `YYYY-MM-DD`
+    pub from: Option<String>,
+    /// last day to include, `YYYY-MM-DD`
+    pub to: Option<String>,
+    /// counterparties per denom, 10 by default and at most 100
+    pub top: Option<usize>,
+}
+
+const DEFAULT_TOP_COUNTERPARTIES: usize = 10;
+const MAX_TOP_COUNTERPARTIES: usize = 100;
+
+/// first and last activity, counts, totals per denom and the main
+/// counterparties of an address
+pub async fn get_address_summary(
+    db: web::Data<Arc<DB>>,
+    address: String,
+    query: SummaryQuery,
+) -> Result<HttpResponse, ApiError> {
+    let (filter, tz) = FilterParams {
+        address: Some(address),
+        tz: query.tz,
+        from: query.from,
+        to: query.to,
+        ..FilterParams::default()
+    }
+    .into_filter()?;
+    let top = query
+        .top
+        .unwrap_or(DEFAULT_TOP_COUNTERPARTIES)
+        .min(MAX_TOP_COUNTERPARTIES);
+    let summary = load_summary(&db, &filter, tz, top)?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+/// the feed a stream request asks for, `last_event_id` is the cursor an
+/// EventSource resends when it reconnects
+fn stream_tail(
+    db: &Arc<DB>,
+    query: StreamQuery,
+    last_event_id: Option<String>,
+) -> Result<Tail, ApiError> {
+    let (mut filter, tz) = FilterParams {
+        address: query.address,
+        direction: query.direction,
+        msg_type: query.msg_type,
+        denom: query.denom,
+        tz: query.tz,
+        ..FilterParams::default()
+    }
+    .into_filter()?;
+    if let Some(min_amount) = query.min_amount {
+        filter.min_amount = Some(Decimal::from_str(&min_amount).map_err(|_| {
+            ApiError::bad_request(
+                "invalid_amount",
+                format!("Invalid min_amount {}, expected a number", min_amount),
+            )
+        })?);
+    }
+    let after = last_event_id.or(query.after);
+    if let Some(cursor) = &after {
+        check_cursor(cursor)?;
+    }
+    Ok(Tail::new(db.clone(), filter, tz, after, query.from_height)?)
+}
+
+/// streams the messages matching the query as server-sent events as they are
+/// stored, with their cursor as the event id
+pub async fn stream_transactions_sse(
+    db: web::Data<Arc<DB>>,
+    query: StreamQuery,
+    last_event_id: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let tail = stream_tail(&db, query, last_event_id)?;
+    let events = stream::unfold(tail, |mut tail| async move {
+        let event = match tail.next().await {
+            Ok(FeedEvent::Transaction(cursor, transaction)) => {
+                match serde_json::to_string(&transaction) {
+                    Ok(data) => format!("id: {}\nevent: transaction\ndata: {}\n\n", cursor, data),
+                    Err(e) => {
+                        error!("Failed to serialize transaction: {}", e);
+                        return None;
+                    }
+                }
+            }
+            // a comment keeps proxies from closing a quiet connection
+            Ok(FeedEvent::Idle) => ": keep-alive\n\n".to_string(),
+            Ok(FeedEvent::Shutdown) => return None,
+            Err(e) => {
+                error!("Database error in transaction stream: {}", e);
+                return None;
+            }
+        };
+        Some((Ok::<_, actix_web::Error>(Bytes::from(event)), tail))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events))
+}
+
+/// streams the messages matching the query as JSON text frames over a
+/// WebSocket as they are stored
+pub async fn stream_transactions_ws(
+    db: web::Data<Arc<DB>>,
+    query: StreamQuery,
+    req: HttpRequest,
+    body: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut tail = stream_tail(&db, query, None)?;
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    actix_rt::spawn(async move {
+        loop {
+            tokio::select! {
+                event = tail.next() => match event {
+                    Ok(FeedEvent::Transaction(cursor, transaction)) => {
+                        let event = StreamEvent { cursor, transaction };
+                        let text = match serde_json::to_string(&event) {
+                            Ok(text) => text,
+                            Err(e) => {
+                                error!("Failed to serialize transaction: {}", e);
+                                break;
+                            }
+                        };
+                        if session.text(text).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(FeedEvent::Idle) => {
+                        if session.ping(b"").await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(FeedEvent::Shutdown) => break,
+                    Err(e) => {
+                        error!("Database error in transaction stream: {}", e);
+                        break;
+                    }
+                },
+                message = messages.next() => match message {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+pub async fn get_all_transactions(
+    db: web::Data<Arc<DB>>,
+    tz: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let tz = time_zone(tz.as_deref())?;
+    let transactions = get_all_filtered_transactions(&db, None, tz)?;
+    Ok(HttpResponse::Ok().json(transactions))
 }
 
 pub async fn get_all_transactions_by_address(
     db: web::Data<Arc<DB>>,
     address: String,
-) -> impl Responder {
-    let transactions = get_all_filtered_transactions(&db, Some(&address));
-    HttpResponse::Ok().json(transactions)
+    tz: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let tz = time_zone(tz.as_deref())?;
+    let transactions = get_all_filtered_transactions(&db, Some(&address), tz)?;
+    Ok(HttpResponse::Ok().json(transactions))
 }
 
 fn get_all_filtered_transactions(
     db: &Arc<DB>,
     address: Option<&str>,
-) -> Vec<AllTransactionResponse> {
+    tz: Tz,
+) -> Result<Vec<AllTransactionResponse>, ApiError> {
     let mut response_data = Vec::new();
-    let iterator = db.iterator(rocksdb::IteratorMode::Start);
-
-    for item in iterator {
-        if let Ok((key, value)) = item {
-            let key_str = String::from_utf8_lossy(&key);
-            let key_parts: Vec<&str> = key_str.split(':').collect();
-            if key_parts.len() == 4 {
-                let transaction_type = key_parts[1];
-                let block_number = key_parts[0].parse::<u64>().unwrap();
-                let timestamp = key_parts[2].parse::<i64>().unwrap();
-                let formatted_date = format_date(timestamp);
-
-                let (data, is_relevant) = match transaction_type {
-                    "msgSend" => {
-                        let msg_send: CustomMsgSend = serde_json::from_slice(&value).unwrap();
-                        let is_relevant = address.map_or(true, |addr| {
-                            msg_send.from_address == addr || msg_send.to_address == addr
-                        });
-                        (serde_json::to_value(&msg_send).unwrap(), is_relevant)
-                    }
-                    "msgIbcTransfer" => {
-                        let msg_transfer: CustomMsgTransfer =
-                            serde_json::from_slice(&value).unwrap();
-                        let is_relevant = address.map_or(true, |addr| {
-                            msg_transfer.sender == addr || msg_transfer.receiver == addr
-                        });
-                        (serde_json::to_value(&msg_transfer).unwrap(), is_relevant)
-                    }
-                    _ => continue,
-                };
-
-                if is_relevant {
-                    response_data.push(AllTransactionResponse {
-                        tx_hash: key_parts[3].to_string(),
-                        block_number,
-                        formatted_date,
-                        transaction_type: transaction_type.to_string(),
-                        data,
+
+    for_each_msg(db, |tx_key, value| {
+        let (data, is_relevant) = match tx_key.msg_type.as_str() {
+            "msgSend" => match decode_msg_value::<CustomMsgSend>(&tx_key, value) {
+                Some((msg_send, data)) => {
+                    let is_relevant = address.map_or(true, |addr| {
+                        msg_send.from_address == addr || msg_send.to_address == addr
                     });
+                    (data, is_relevant)
                 }
+                None => return,
+            },
+            "msgIbcTransfer" => match decode_msg_value::<CustomMsgTransfer>(&tx_key, value) {
+                Some((msg_transfer, data)) => {
+                    let is_relevant = address.map_or(true, |addr| {
+                        msg_transfer.sender == addr || msg_transfer.receiver == addr
+                    });
+                    (data, is_relevant)
+                }
+                None => return,
+            },
+            _ => return,
+        };
+
+        if is_relevant {
+            if let Some(formatted_date) = format_date(tx_key.timestamp, tz) {
+                response_data.push(AllTransactionResponse {
+                    tx_hash: tx_key.tx_hash,
+                    block_number: tx_key.block_number,
+                    formatted_date,
+                    transaction_type: tx_key.msg_type,
+                    data,
+                });
             }
         }
-    }
+    })?;
 
     response_data.sort_by(|a, b| b.block_number.cmp(&a.block_number));
-    response_data
+    Ok(response_data)
 }
 
 pub async fn get_msg_send_transactions_by_address(
     db: web::Data<Arc<DB>>,
     address: String,
-) -> impl Responder {
-    let transactions = get_filtered_transactions(&db, &address, None);
-    HttpResponse::Ok().json(transactions)
+    tz: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let tz = time_zone(tz.as_deref())?;
+    let transactions = get_filtered_transactions(&db, &address, None, tz)?;
+    Ok(HttpResponse::Ok().json(transactions))
 }
 
 pub async fn get_msg_send_transactions_by_address_and_direction(
     db: web::Data<Arc<DB>>,
     address: String,
     direction: String,
-) -> impl Responder {
+    tz: Option<String>,
+) -> Result<HttpResponse, ApiError> {
     let direction = match direction.as_str() {
         "send" => Some(true),
         "receive" => Some(false),
-        _ => return HttpResponse::BadRequest().body("Invalid direction. Use 'send' or 'receive'."),
+        _ => {
+            return Err(ApiError::bad_request(
+                "invalid_direction",
+                "Invalid direction. Use 'send' or 'receive'.",
+            ))
+        }
     };
 
-    let transactions = get_filtered_transactions(&db, &address, direction);
-    HttpResponse::Ok().json(transactions)
+    let tz = time_zone(tz.as_deref())?;
+    let transactions = get_filtered_transactions(&db, &address, direction, tz)?;
+    Ok(HttpResponse::Ok().json(transactions))
 }
 
 fn get_filtered_transactions(
     db: &Arc<DB>,
     address: &str,
     is_sender: Option<bool>,
-) -> Vec<TransactionResponse> {
+    tz: Tz,
+) -> Result<Vec<TransactionResponse>, ApiError> {
     let mut response_data = Vec::new();
-    let iterator = db.iterator(rocksdb::IteratorMode::Start);
-
-    for item in iterator {
-        if let Ok((key, value)) = item {
-            let key_str = String::from_utf8_lossy(&key);
-            let key_parts: Vec<&str> = key_str.split(':').collect();
-            if key_parts.len() == 4 && key_parts[1] == "msgSend" {
-                let msg_send: CustomMsgSend = serde_json::from_slice(&value).unwrap();
-
-                let is_sender_match = match is_sender {
-                    Some(true) => msg_send.from_address == address,
-                    Some(false) => msg_send.to_address == address,
-                    None => msg_send.from_address == address || msg_send.to_address == address,
-                };
-
-                if is_sender_match {
-                    let block_number = key_parts[0].parse::<u64>().unwrap();
-                    let timestamp = key_parts[2].parse::<i64>().unwrap();
-                    let formatted_date = format_date(timestamp);
-
-                    response_data.push(TransactionResponse {
-                        tx_hash: key_parts[3].to_string(),
-                        block_number,
-                        formatted_date,
-                        data: msg_send,
-                    });
-                }
+
+    for_each_msg(db, |tx_key, value| {
+        if tx_key.msg_type != "msgSend" {
+            return;
+        }
+        let msg_send: CustomMsgSend = match decode_msg(&tx_key, value) {
+            Some(msg_send) => msg_send,
+            None => return,
+        };
+
+        let is_sender_match = match is_sender {
+            Some(true) => msg_send.from_address == address,
+            Some(false) => msg_send.to_address == address,
+            None => msg_send.from_address == address || msg_send.to_address == address,
+        };
+
+        if is_sender_match {
+            if let Some(formatted_date) = format_date(tx_key.timestamp, tz) {
+                response_data.push(TransactionResponse {
+                    tx_hash: tx_key.tx_hash,
+                    block_number: tx_key.block_number,
+                    formatted_date,
+                    data: msg_send,
+                });
             }
         }
-    }
+    })?;
 
     response_data.sort_by(|a, b| b.block_number.cmp(&a.block_number));
-    response_data
+    Ok(response_data)
 }
 
-fn format_date(timestamp: i64) -> String {
-    let naive = chrono::NaiveDateTime::from_timestamp_opt(timestamp, 0).unwrap();
-    let datetime: chrono::DateTime<chrono::Utc> = chrono::DateTime::from_utc(naive, chrono::Utc);
-    let datetime_local: chrono::DateTime<chrono::Local> = datetime.into();
-    datetime_local.format("%Y-%m-%d %H:%M:%S").to_string()
+/// block time in the requested zone, None and logged if the timestamp is out
+/// of range
+fn format_date(timestamp: i64, tz: Tz) -> Option<String> {
+    let datetime = block_time(timestamp, tz)?;
+    Some(datetime.format("%Y-%m-%d %H:%M:%S").to_string())
 }
 
-pub async fn get_all_msg_send_transactions(db: web::Data<Arc<DB>>) -> impl Responder {
+pub async fn get_all_msg_send_transactions(
+    db: web::Data<Arc<DB>>,
+    tz: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let tz = time_zone(tz.as_deref())?;
     let mut response_data = Vec::new();
-    let iterator = db.iterator(rocksdb::IteratorMode::Start);
-
-    for item in iterator {
-        match item {
-            Ok((key, value)) => {
-                let key_str = String::from_utf8_lossy(&key);
-                let key_parts: Vec<&str> = key_str.split(':').collect();
-                if key_parts.len() == 4 && key_parts[1] == "msgSend" {
-                    match serde_json::from_slice::<CustomMsgSend>(&value) {
-                        Ok(msg_send) => {
-                            let block_number = key_parts[0].parse::<u64>().unwrap();
-                            let timestamp = key_parts[2].parse::<i64>().unwrap();
-
-                            let formatted_date = format_date(timestamp);
-
-                            response_data.push(TransactionResponse {
-                                tx_hash: key_parts[3].to_string(),
-                                block_number,
-                                formatted_date,
-                                data: msg_send,
-                            });
-                        }
-                        Err(e) => error!("Failed to deserialize msgSend: {}", e),
-                    }
-                }
-            }
-            Err(err) => {
-                error!("RocksDB iterator error: {}", err);
-            }
+
+    for_each_msg(&db, |tx_key, value| {
+        if tx_key.msg_type != "msgSend" {
+            return;
         }
-    }
+        if let (Some(msg_send), Some(formatted_date)) = (
+            decode_msg::<CustomMsgSend>(&tx_key, value),
+            format_date(tx_key.timestamp, tz),
+        ) {
+            response_data.push(TransactionResponse {
+                tx_hash: tx_key.tx_hash,
+                block_number: tx_key.block_number,
+                formatted_date,
+                data: msg_send,
+            });
+        }
+    })?;
 
     response_data.sort_by(|a, b| b.block_number.cmp(&a.block_number));
-    HttpResponse::Ok().json(response_data)
+    Ok(HttpResponse::Ok().json(response_data))
 }
 
-pub async fn get_all_msg_ibc_transfer_transactions(db: web::Data<Arc<DB>>) -> impl Responder {
+pub async fn get_all_msg_ibc_transfer_transactions(
+    db: web::Data<Arc<DB>>,
+    tz: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let tz = time_zone(tz.as_deref())?;
     let mut response_data: HashMap<u64, BlockData> = HashMap::new();
 
-    let iterator = db.iterator(rocksdb::IteratorMode::Start);
-
-    for item in iterator {
-        match item {
-            Ok((key, value)) => {
-                let key_str = String::from_utf8_lossy(&key);
-                let key_parts: Vec<&str> = key_str.split(':').collect();
-                if key_parts.len() == 4 && key_parts[1] == "msgIbcTransfer" {
-                    let msg_ibc_transfer: CustomMsgTransfer =
-                        serde_json::from_slice(&value).unwrap();
-                    let block_number = key_parts[0].parse::<u64>().unwrap();
-
-                    let timestamp = key_parts[2].parse::<i64>().unwrap();
-
-                    // Convert timestamp to Option<NaiveDateTime>
-                    let naive_opt = NaiveDateTime::from_timestamp_opt(timestamp, 0);
-
-                    let mut _datetime_utc: Option<DateTime<Utc>> = None;
-
-                    if let Some(naive_datetime) = naive_opt {
-                        // Convert Option<NaiveDateTime> to DateTime
-                        _datetime_utc = Some(DateTime::<Utc>::from_utc(naive_datetime, Utc));
-                    } else {
-                        error!("Invalid timestamp: {}", timestamp);
-                        continue; // skip this iteration if timestamp is invalid
-                    }
-
-                    let datetime_utc = _datetime_utc.unwrap(); // we can safely unwrap because of the `continue` above
-
-                    let datetime_local: DateTime<Local> = datetime_utc.into();
-
-                    // Extract month, day, and year
-                    let month = datetime_local.month();
-                    let day = datetime_local.day();
-                    let year = datetime_local.year();
-
-                    // Format the date string
-                    let formatted_date = format!("{:02}-{:02}-{}", month, day, year);
-                    let api_response = ApiResponse {
-                        tx_hash: key_parts[3].to_string(),
-                        data: serde_json::to_value(&msg_ibc_transfer).unwrap(),
-                    };
-
-                    response_data
-                        .entry(block_number)
-                        .or_insert((formatted_date, Vec::new()))
-                        .1
-                        .push(api_response);
-                }
-            }
-            Err(err) => {
-                error!("RocksDB iterator error: {}", err);
-            }
+    for_each_msg(&db, |tx_key, value| {
+        if tx_key.msg_type != "msgIbcTransfer" {
+            return;
         }
-    }
+        let data = match decode_msg_value::<CustomMsgTransfer>(&tx_key, value) {
+            Some((_, data)) => data,
+            None => return,
+        };
+        let datetime = match block_time(tx_key.timestamp, tz) {
+            Some(datetime) => datetime,
+            None => return,
+        };
+
+        // Extract month, day, and year
+        let month = datetime.month();
+        let day = datetime.day();
+        let year = datetime.year();
+
+        // Format the date string
+        let formatted_date = format!("{:02}-{:02}-{}", month, day, year);
+        let api_response = ApiResponse {
+            tx_hash: tx_key.tx_hash,
+            data,
+        };
+
+        response_data
+            .entry(tx_key.block_number)
+            .or_insert((formatted_date, Vec::new()))
+            .1
+            .push(api_response);
+    })?;
 
     // Converting the HashMap to a Vec and sorting it by block number
     let mut response_data: Vec<_> = response_data.into_iter().collect();
@@ -288,5 +729,147 @@ pub async fn get_all_msg_ibc_transfer_transactions(db: web::Data<Arc<DB>>) -> im
         )
         .collect();
 
-    HttpResponse::Ok().json(response_data)
+    Ok(HttpResponse::Ok().json(response_data))
+}
+
+pub async fn get_quarantined_transactions(db: web::Data<Arc<DB>>) -> impl Responder {
+    HttpResponse::Ok().json(load_quarantined_txs(&db))
+}
+
+pub async fn retry_quarantined_transactions(
+    db: web::Data<Arc<DB>>,
+    options: web::Data<SyncOptions>,
+) -> Result<HttpResponse, ApiError> {
+    match retry_quarantined_txs(&db, &options.msg_types) {
+        Ok((released, failing)) => Ok(HttpResponse::Ok().json(RetryResponse { released, failing })),
+        Err(e) => {
+            error!("Failed to retry quarantined transactions: {}", e);
+            Err(ApiError::internal(
+                "Failed to retry quarantined transactions.",
+            ))
+        }
+    }
+}
+
+/// the checkpoint for /status and /readyz, the details are only logged
+fn checkpoint(db: &DB) -> Result<Option<u64>, ApiError> {
+    load_last_download_block(db).map_err(|e| {
+        error!("{}", e);
+        ApiError::internal("The checkpoint could not be read.")
+    })
+}
+
+pub async fn get_status(
+    db: web::Data<Arc<DB>>,
+    pool: web::Data<Arc<NodePool>>,
+) -> Result<HttpResponse, ApiError> {
+    let status = sync_status();
+    let last_indexed_height = checkpoint(&db)?;
+    let chain_tip = pool.latest_block();
+
+    let lag_blocks = match (chain_tip, last_indexed_height) {
+        (Some(tip), Some(height)) => Some(tip.saturating_sub(height)),
+        _ => None,
+    };
+    let lag_seconds = status
+        .last_block_time
+        .map(|time| (Utc::now().timestamp() - time).max(0));
+
+    // throughput of the current phase, from the checkpoint it started at
+    let elapsed = status.phase_started.elapsed().as_secs_f64();
+    let indexed = last_indexed_height
+        .unwrap_or(0)
+        .saturating_sub(status.phase_start_height);
+    let blocks_per_second = if elapsed > 0.0 {
+        indexed as f64 / elapsed
+    } else {
+        0.0
+    };
+    let eta_seconds = match lag_blocks {
+        Some(0) => Some(0),
+        Some(lag) if blocks_per_second > 0.0 => {
+            Some((lag as f64 / blocks_per_second).ceil() as u64)
+        }
+        _ => None,
+    };
+
+    Ok(HttpResponse::Ok().json(StatusResponse {
+        phase: status.phase,
+        last_indexed_height,
+        chain_tip,
+        lag_blocks,
+        lag_seconds,
+        blocks_per_second,
+        eta_seconds,
+        counters: counters(),
+    }))
+}
+
+pub async fn get_metrics(
+    db: web::Data<Arc<DB>>,
+    pool: web::Data<Arc<NodePool>>,
+) -> Result<HttpResponse, ApiError> {
+    match metrics::render(&db, &pool) {
+        Ok(body) => Ok(HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body)),
+        Err(e) => {
+            error!("Failed to encode metrics: {}", e);
+            Err(ApiError::internal("Failed to encode metrics."))
+        }
+    }
+}
+
+pub async fn get_healthz(db: web::Data<Arc<DB>>) -> impl Responder {
+    // a read proves the database is open and usable
+    match db.get(LAST_DOWNLOAD_BLOCK_KEY.as_bytes()) {
+        Ok(_) => HttpResponse::Ok().json(json!({ "status": "ok" })),
+        Err(e) => {
+            error!("Health check failed to read the database: {}", e);
+            HttpResponse::ServiceUnavailable().json(json!({
+                "status": "error",
+                "message": "The database could not be read."
+            }))
+        }
+    }
+}
+
+pub async fn get_readyz(
+    db: web::Data<Arc<DB>>,
+    pool: web::Data<Arc<NodePool>>,
+    options: web::Data<ReadinessOptions>,
+) -> Result<HttpResponse, ApiError> {
+    let status = sync_status();
+
+    let indexer_alive = matches!(
+        status.phase,
+        SyncPhase::Historical | SyncPhase::Live | SyncPhase::Replica
+    ) && status
+        .last_heartbeat
+        .map_or(false, |heartbeat| heartbeat.elapsed() <= options.max_idle);
+
+    let lag_blocks = match (pool.latest_block(), checkpoint(&db)?) {
+        (Some(tip), Some(height)) => Some(tip.saturating_sub(height)),
+        _ => None,
+    };
+    let lag_ok = lag_blocks.map_or(false, |lag| lag <= options.max_lag_blocks);
+
+    let last_grpc_success = pool.last_success().map(|instant| instant.elapsed());
+    let grpc_ok = last_grpc_success.map_or(false, |elapsed| elapsed <= options.max_idle);
+
+    let ready = indexer_alive && lag_ok && grpc_ok;
+    let response = ReadinessResponse {
+        ready,
+        phase: status.phase,
+        indexer_alive,
+        lag_blocks,
+        lag_ok,
+        last_grpc_success_seconds: last_grpc_success.map(|elapsed| elapsed.as_secs()),
+        grpc_ok,
+    };
+    if ready {
+        Ok(HttpResponse::Ok().json(response))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(response))
+    }
 }
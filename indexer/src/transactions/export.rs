@@ -0,0 +1,269 @@
+use chrono_tz::Tz;
+use rocksdb::DB;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use super::database::load_tx_info;
+use super::query::{self, Order, TxFilter};
+use crate::types::{CustomCoin, Transaction};
+
+// how many messages are read per chunk of rows
+const BATCH_SIZE: usize = 500;
+
+pub const CSV_HEADER: &str =
+    "date,height,tx_hash,type,direction,counterparty,denom,amount,fee,memo\n";
+
+/// The rows of an address' history as CSV, one per coin it sent or received,
+/// oldest first. The history is read a page at a time so exports of any size
+/// run in constant memory.
+pub struct CsvExport {
+    db: Arc<DB>,
+    address: String,
+    filter: TxFilter,
+    tz: Tz,
+    cursor: Option<String>,
+    done: bool,
+    // txs of the current block whose fee was written already, so a tx with
+    // several messages counts it once. Only the sender pays it, so it is
+    // written on send rows.
+    fee_written: HashSet<String>,
+    fee_height: u64,
+}
+
+impl CsvExport {
+    /// exports the messages of `filter.address` matching the filter, `denom`
+    /// limits the rows to coins of that denom as well
+    pub fn new(db: Arc<DB>, filter: TxFilter, tz: Tz) -> CsvExport {
+        CsvExport {
+            db,
+            address: filter.address.clone().unwrap_or_default(),
+            filter,
+            tz,
+            cursor: None,
+            done: false,
+            fee_written: HashSet::new(),
+            fee_height: 0,
+        }
+    }
+
+    /// the rows of the next page of messages, None once every message was
+    /// exported. A page can be empty when none of its coins match.
+    pub fn next_rows(&mut self) -> Result<Option<String>, rocksdb::Error> {
+        if self.done {
+            return Ok(None);
+        }
+        let page = query::load_page(
+            &self.db,
+            &self.filter,
+            Order::Asc,
+            self.tz,
+            self.cursor.as_deref(),
+            BATCH_SIZE,
+        )?;
+        self.done = !page.has_more;
+        self.cursor = page.scanned_to;
+
+        let mut rows = String::new();
+        for (_, transaction) in page.transactions {
+            self.write_rows(&mut rows, &transaction);
+        }
+        Ok(Some(rows))
+    }
+
+    fn write_rows(&mut self, rows: &mut String, transaction: &Transaction) {
+        let msg = &transaction.data;
+        // a transfer to itself is both sent and received
+        let mut sides = Vec::new();
+        if msg.sender() == self.address {
+            sides.push(("send", msg.receiver()));
+        }
+        if msg.receiver() == self.address {
+            sides.push(("receive", msg.sender()));
+        }
+        if sides.is_empty() {
+            return;
+        }
+
+        let info = load_tx_info(&self.db, transaction.height, &transaction.tx_hash);
+        if transaction.height != self.fee_height {
+            self.fee_written.clear();
+            self.fee_height = transaction.height;
+        }
+        let height = transaction.height.to_string();
+        let memo = info.as_ref().map_or("", |info| info.memo.as_str());
+        let coins = msg.coins().iter().filter(|coin| {
+            self.filter
+                .denom
+                .as_ref()
+                .map_or(true, |denom| coin.denom == *denom)
+        });
+        for (direction, counterparty) in sides {
+            for coin in coins.clone() {
+                let fee = match &info {
+                    Some(info)
+                        if direction == "send"
+                            && self.fee_written.insert(transaction.tx_hash.clone()) =>
+                    {
+                        format_coins(&info.fee)
+                    }
+                    _ => String::new(),
+                };
+                let fields: [&str; 10] = [
+                    &transaction.timestamp,
+                    &height,
+                    &transaction.tx_hash,
+                    &transaction.msg_type,
+                    direction,
+                    counterparty,
+                    &coin.denom,
+                    &coin.amount,
+                    &fee,
+                    memo,
+                ];
+                let row: Vec<String> = fields.iter().map(|field| escape(field)).collect();
+                let _ = writeln!(rows, "{}", row.join(","));
+            }
+        }
+    }
+}
+
+/// coins in the usual `<amount><denom>` notation, e.g. `2500umfx`
+fn format_coins(coins: &[CustomCoin]) -> String {
+    coins
+        .iter()
+        .map(|coin| format!("{}{}", coin.amount, coin.denom))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// quotes a field if it contains a separator, a quote or a line break. A
+/// field starting like a formula, e.g. a memo of `=HYPERLINK(...)`, gets a
+/// `'` in front so spreadsheets show it as text instead of evaluating it.
+fn escape(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::database::tests::{send_msg, TestDb};
+    use crate::transactions::database::{save_block, DecodedBlock, IndexedMsg, IndexedTx};
+    use crate::types::TxInfo;
+
+    const ALICE: &str = "manifest1alice";
+    const BOB: &str = "manifest1bob";
+    const CAROL: &str = "manifest1carol";
+
+    /// stores the messages of a block with the fee of each tx
+    fn save(db: &DB, msgs: Vec<IndexedMsg>, fees: &[(&str, &str)]) {
+        save_block(
+            db,
+            &DecodedBlock {
+                block_number: msgs[0].block_number,
+                timestamp: msgs[0].timestamp,
+                txs: fees
+                    .iter()
+                    .map(|(tx_hash, fee)| IndexedTx {
+                        block_number: msgs[0].block_number,
+                        tx_hash: tx_hash.to_string(),
+                        info: TxInfo {
+                            fee: vec![CustomCoin {
+                                denom: "umfx".to_string(),
+                                amount: fee.to_string(),
+                            }],
+                            memo: "rent".to_string(),
+                        },
+                    })
+                    .collect(),
+                msgs,
+                quarantined: Vec::new(),
+            },
+        );
+    }
+
+    /// the tx hash, direction and fee of every row exported for `address`
+    fn export(db: &Arc<DB>, address: &str) -> Vec<(String, String, String)> {
+        let filter = TxFilter {
+            address: Some(address.to_string()),
+            ..TxFilter::default()
+        };
+        let mut export = CsvExport::new(db.clone(), filter, Tz::UTC);
+        let mut rows = Vec::new();
+        while let Some(page) = export.next_rows().unwrap() {
+            for row in page.lines() {
+                let fields: Vec<&str> = row.split(',').collect();
+                rows.push((
+                    fields[2].to_string(),
+                    fields[4].to_string(),
+                    fields[8].to_string(),
+                ));
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn escapes_formulas_and_separators() {
+        assert_eq!(escape("2500umfx"), "2500umfx");
+        assert_eq!(escape("=1+1"), "'=1+1");
+        assert_eq!(escape("+1"), "'+1");
+        assert_eq!(escape("-1"), "'-1");
+        assert_eq!(escape("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(escape("\tmemo"), "'\tmemo");
+        assert_eq!(escape("\rmemo"), "\"'\rmemo\"");
+        assert_eq!(escape("a,b"), "\"a,b\"");
+        assert_eq!(escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape("two\nlines"), "\"two\nlines\"");
+        assert_eq!(
+            escape("=HYPERLINK(\"x\",\"y\")"),
+            "\"'=HYPERLINK(\"\"x\"\",\"\"y\"\")\""
+        );
+    }
+
+    #[test]
+    fn writes_the_fee_once_per_sent_tx() {
+        let test = TestDb::new();
+        save(
+            &test.db,
+            vec![
+                send_msg(1, "MULTI", 0, ALICE, BOB, "10", "umfx"),
+                send_msg(1, "MULTI", 1, ALICE, CAROL, "20", "umfx"),
+            ],
+            &[("MULTI", "2500")],
+        );
+        save(
+            &test.db,
+            vec![send_msg(2, "INCOMING", 0, BOB, ALICE, "5", "umfx")],
+            &[("INCOMING", "100")],
+        );
+
+        let row = |tx_hash: &str, direction: &str, fee: &str| {
+            (tx_hash.to_string(), direction.to_string(), fee.to_string())
+        };
+        assert_eq!(
+            export(&test.db, ALICE),
+            vec![
+                row("MULTI", "send", "2500umfx"),
+                row("MULTI", "send", ""),
+                row("INCOMING", "receive", ""),
+            ]
+        );
+        assert_eq!(
+            export(&test.db, BOB),
+            vec![
+                row("MULTI", "receive", ""),
+                row("INCOMING", "send", "100umfx"),
+            ]
+        );
+    }
+}
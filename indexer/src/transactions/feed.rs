@@ -0,0 +1,167 @@
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use log::error;
+use rocksdb::DB;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+use super::database::load_last_download_block;
+use super::query::{self, Order, TxFilter};
+use crate::shutdown::{shutdown_requested, wait_for_shutdown};
+use crate::types::Transaction;
+
+// how many messages a feed reads at once while catching up
+const BATCH_SIZE: usize = 100;
+// a feed that saw nothing new for this long reports it, so connections can
+// be kept alive
+const IDLE_INTERVAL: Duration = Duration::from_secs(15);
+
+lazy_static! {
+    // the receiver is kept here so that sending never fails for lack of one
+    static ref STORED: (watch::Sender<u64>, watch::Receiver<u64>) = watch::channel(0);
+}
+
+/// wakes the feeds up after messages up to `height` were stored or the
+/// checkpoint moved, by this process or, for a secondary instance, by the
+/// primary
+pub fn notify_stored(height: u64) {
+    let _ = STORED.0.send(height);
+}
+
+/// the checkpoint, feeds only read the messages up to it. Blocks above it
+/// can still be stored out of order, by concurrent historical batches, and
+/// would land behind the cursor of a feed that read past them.
+fn readable_height(db: &DB) -> Option<u64> {
+    match load_last_download_block(db) {
+        Ok(height) => height,
+        Err(e) => {
+            error!("{}, waiting for the next block", e);
+            None
+        }
+    }
+}
+
+/// a cursor after every key of the block at `height`, `;` sorts right after
+/// the `:` that ends the height
+fn end_of_block(height: u64) -> String {
+    format!("{:012};", height)
+}
+
+pub enum FeedEvent {
+    /// the next matching message and its cursor
+    Transaction(String, Transaction),
+    /// nothing new was stored for a while
+    Idle,
+    Shutdown,
+}
+
+/// Follows the stored messages matching a filter in key order, reading the
+/// ones after its cursor whenever new blocks are stored
+pub struct Tail {
+    db: Arc<DB>,
+    filter: TxFilter,
+    tz: Tz,
+    cursor: Option<String>,
+    pending: VecDeque<(String, Transaction)>,
+    stored: watch::Receiver<u64>,
+}
+
+impl Tail {
+    /// starts after the `after` cursor, at `from_height`, or after the newest
+    /// readable message if neither is given
+    pub fn new(
+        db: Arc<DB>,
+        filter: TxFilter,
+        tz: Tz,
+        after: Option<String>,
+        from_height: Option<u64>,
+    ) -> Result<Tail, rocksdb::Error> {
+        let cursor = match (after, from_height) {
+            (Some(after), _) => Some(after),
+            // `{height}:` sorts before every key of the block
+            (None, Some(height)) => Some(format!("{:012}:", height)),
+            (None, None) => match readable_height(&db) {
+                Some(height) => Some(end_of_block(height)),
+                None => {
+                    query::load_page(&db, &TxFilter::default(), Order::Desc, tz, None, 1)?
+                        .scanned_to
+                }
+            },
+        };
+        Ok(Tail {
+            db,
+            filter,
+            tz,
+            cursor,
+            pending: VecDeque::new(),
+            stored: STORED.1.clone(),
+        })
+    }
+
+    /// waits for the next matching message. Dropping the future before it
+    /// completes loses nothing, so it can be raced against other events.
+    pub async fn next(&mut self) -> Result<FeedEvent, rocksdb::Error> {
+        loop {
+            if let Some((cursor, transaction)) = self.pending.pop_front() {
+                return Ok(FeedEvent::Transaction(cursor, transaction));
+            }
+            if shutdown_requested() {
+                return Ok(FeedEvent::Shutdown);
+            }
+            // anything stored from here on wakes the wait below
+            self.stored.borrow_and_update();
+            let caught_up = match readable_height(&self.db) {
+                Some(height) => self.read_page(height)?,
+                None => true,
+            };
+            if caught_up && self.pending.is_empty() {
+                tokio::select! {
+                    _ = self.stored.changed() => {}
+                    _ = sleep(IDLE_INTERVAL) => return Ok(FeedEvent::Idle),
+                    _ = wait_for_shutdown() => return Ok(FeedEvent::Shutdown),
+                }
+            }
+        }
+    }
+
+    /// queues the next matching messages up to `height`, returns whether
+    /// there are no more
+    fn read_page(&mut self, height: u64) -> Result<bool, rocksdb::Error> {
+        let end = end_of_block(height);
+        if self.cursor.as_ref().map_or(false, |cursor| *cursor >= end) {
+            return Ok(true);
+        }
+        let page = query::load_page(
+            &self.db,
+            &self.filter,
+            Order::Asc,
+            self.tz,
+            self.cursor.as_deref(),
+            BATCH_SIZE,
+        )?;
+        let read = page.transactions.len();
+        let readable: Vec<(String, Transaction)> = page
+            .transactions
+            .into_iter()
+            .take_while(|(_, transaction)| transaction.height <= height)
+            .collect();
+        let past_end = readable.len() < read
+            || page
+                .scanned_to
+                .as_ref()
+                .map_or(false, |scanned| *scanned > end);
+        self.pending.extend(readable);
+        // skipping the messages the filter rejected as well keeps a quiet
+        // feed from scanning them again on every block, but not past the
+        // checkpoint
+        if past_end {
+            self.cursor = Some(end);
+        } else if page.scanned_to.is_some() {
+            self.cursor = page.scanned_to;
+        }
+        Ok(past_end || !page.has_more)
+    }
+}
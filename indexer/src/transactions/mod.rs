@@ -1,2 +1,7 @@
 pub mod database;
 pub mod endpoints;
+pub mod export;
+pub mod feed;
+pub mod query;
+pub mod subscription;
+pub mod summary;
@@ -0,0 +1,540 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone};
+use chrono_tz::Tz;
+use log::warn;
+use rocksdb::{IteratorMode, DB};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use super::database::{load_tx_height, parse_tx_key, TxKey, MSG_IBC_TRANSFER, MSG_SEND, MSG_TYPES};
+use crate::types::{StoredMsg, Transaction, TransactionBlock, TransactionDay};
+
+/// Which side of a transfer an address is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+/// Order of the results by height
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+/// Selects stored messages, unset fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct TxFilter {
+    pub address: Option<String>,
+    /// only used together with an address
+    pub direction: Option<Direction>,
+    pub msg_type: Option<String>,
+    /// unix time of the earliest block, inclusive
+    pub from_time: Option<i64>,
+    /// unix time of the latest block, exclusive
+    pub until_time: Option<i64>,
+    pub tx_hash: Option<String>,
+    /// only messages moving this denom
+    pub denom: Option<String>,
+    /// only messages moving at least this amount of a coin, of the denom if
+    /// one is set
+    pub min_amount: Option<Decimal>,
+}
+
+impl TxFilter {
+    /// whether a message that was already loaded matches
+    pub fn matches_transaction(&self, transaction: &Transaction) -> bool {
+        self.matches(
+            &transaction.msg_type,
+            &transaction.tx_hash,
+            transaction.unix_time,
+            &transaction.data,
+        )
+    }
+
+    fn matches(&self, msg_type: &str, tx_hash: &str, timestamp: i64, msg: &StoredMsg) -> bool {
+        if let Some(wanted_type) = &self.msg_type {
+            if msg_type != wanted_type {
+                return false;
+            }
+        }
+        if let Some(wanted_hash) = &self.tx_hash {
+            if !tx_hash.eq_ignore_ascii_case(wanted_hash) {
+                return false;
+            }
+        }
+        if let Some(denom) = &self.denom {
+            if !msg.coins().iter().any(|coin| coin.denom == *denom) {
+                return false;
+            }
+        }
+        if let Some(min_amount) = self.min_amount {
+            let moves_enough = msg
+                .coins()
+                .iter()
+                .filter(|coin| {
+                    self.denom
+                        .as_ref()
+                        .map_or(true, |denom| coin.denom == *denom)
+                })
+                .any(|coin| {
+                    Decimal::from_str(&coin.amount).map_or(false, |amount| amount >= min_amount)
+                });
+            if !moves_enough {
+                return false;
+            }
+        }
+        if self.from_time.map_or(false, |from| timestamp < from)
+            || self.until_time.map_or(false, |until| timestamp >= until)
+        {
+            return false;
+        }
+        match (&self.address, self.direction) {
+            (None, _) => true,
+            (Some(address), Some(Direction::Send)) => msg.sender() == address,
+            (Some(address), Some(Direction::Receive)) => msg.receiver() == address,
+            (Some(address), None) => msg.sender() == address || msg.receiver() == address,
+        }
+    }
+}
+
+/// calls `f` with every stored message in key order, keys that can't be
+/// parsed are logged and skipped so a single corrupt row doesn't fail the
+/// whole request
+pub fn for_each_msg(db: &DB, mut f: impl FnMut(TxKey, &[u8])) -> Result<(), rocksdb::Error> {
+    scan_msgs(db, IteratorMode::Start, |_, tx_key, value| {
+        f(tx_key, value);
+        true
+    })
+}
+
+/// like `for_each_msg` but starting at `mode` and with the raw key, stops
+/// as soon as `f` returns false
+pub fn scan_msgs(
+    db: &DB,
+    mode: IteratorMode,
+    mut f: impl FnMut(&[u8], TxKey, &[u8]) -> bool,
+) -> Result<(), rocksdb::Error> {
+    for item in db.iterator(mode) {
+        let (key, value) = item?;
+        match parse_tx_key(&key) {
+            Ok(Some(tx_key)) => {
+                if !f(&key, tx_key, &value) {
+                    break;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Skipping corrupt row: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// deserializes a stored message, logging and returning None if it's corrupt
+pub fn decode_msg<T: DeserializeOwned>(tx_key: &TxKey, value: &[u8]) -> Option<T> {
+    match serde_json::from_slice(value) {
+        Ok(msg) => Some(msg),
+        Err(e) => {
+            warn!(
+                "Skipping corrupt {} in tx {} at height {}: {}",
+                tx_key.msg_type, tx_key.tx_hash, tx_key.block_number, e
+            );
+            None
+        }
+    }
+}
+
+/// Filter parameters as clients pass them, shared by the APIs
+#[derive(Debug, Clone, Default)]
+pub struct FilterParams {
+    pub address: Option<String>,
+    pub direction: Option<Direction>,
+    pub msg_type: Option<String>,
+    pub denom: Option<String>,
+    /// IANA zone for the timestamps and the dates, UTC if unset
+    pub tz: Option<String>,
+    /// first and last day to include, `YYYY-MM-DD`
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// Why filter parameters were rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    InvalidType(String),
+    DirectionWithoutAddress,
+    InvalidTz(String),
+    InvalidDate(String),
+}
+
+impl FilterError {
+    /// the machine readable code returned to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            FilterError::InvalidType(_) => "invalid_type",
+            FilterError::DirectionWithoutAddress => "invalid_direction",
+            FilterError::InvalidTz(_) => "invalid_tz",
+            FilterError::InvalidDate(_) => "invalid_date",
+        }
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::InvalidType(msg_type) => write!(
+                f,
+                "Unknown type {}, expected one of {}.",
+                msg_type,
+                MSG_TYPES.join(", ")
+            ),
+            FilterError::DirectionWithoutAddress => {
+                write!(f, "A direction can only be used with an address.")
+            }
+            FilterError::InvalidTz(tz) => write!(
+                f,
+                "Unknown time zone {}, expected an IANA name such as Europe/Berlin.",
+                tz
+            ),
+            FilterError::InvalidDate(date) => {
+                write!(f, "Invalid date {}, expected YYYY-MM-DD.", date)
+            }
+        }
+    }
+}
+
+impl FilterParams {
+    /// validates the parameters, returns the filter and the zone to show
+    /// times in
+    pub fn into_filter(self) -> Result<(TxFilter, Tz), FilterError> {
+        if let Some(msg_type) = &self.msg_type {
+            if !MSG_TYPES.contains(&msg_type.as_str()) {
+                return Err(FilterError::InvalidType(msg_type.clone()));
+            }
+        }
+        if self.address.is_none() && self.direction.is_some() {
+            return Err(FilterError::DirectionWithoutAddress);
+        }
+        let tz = match &self.tz {
+            Some(name) => parse_tz(name)?,
+            None => Tz::UTC,
+        };
+
+        // the days are taken in the requested zone, `to` includes the whole day
+        let from_time = match &self.from {
+            Some(from) => Some(start_of_day(parse_date(from)?, tz)),
+            None => None,
+        };
+        let until_time = match &self.to {
+            Some(to) => parse_date(to)?
+                .succ_opt()
+                .map(|next_day| start_of_day(next_day, tz)),
+            None => None,
+        };
+
+        let filter = TxFilter {
+            address: self.address,
+            direction: self.direction,
+            msg_type: self.msg_type,
+            from_time,
+            until_time,
+            tx_hash: None,
+            denom: self.denom,
+            min_amount: None,
+        };
+        Ok((filter, tz))
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, FilterError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| FilterError::InvalidDate(date.to_string()))
+}
+
+/// decodes a stored message according to the type in its key
+pub fn decode_stored_msg(tx_key: &TxKey, value: &[u8]) -> Option<StoredMsg> {
+    match tx_key.msg_type.as_str() {
+        MSG_SEND => decode_msg(tx_key, value).map(StoredMsg::Send),
+        MSG_IBC_TRANSFER => decode_msg(tx_key, value).map(StoredMsg::IbcTransfer),
+        _ => None,
+    }
+}
+
+/// parses an IANA zone name such as `Europe/Berlin`
+pub fn parse_tz(name: &str) -> Result<Tz, FilterError> {
+    name.parse::<Tz>()
+        .map_err(|_| FilterError::InvalidTz(name.to_string()))
+}
+
+/// block time in the given zone, None and logged if it's out of range
+pub fn block_time(timestamp: i64, tz: Tz) -> Option<DateTime<Tz>> {
+    match NaiveDateTime::from_timestamp_opt(timestamp, 0) {
+        Some(naive) => Some(tz.from_utc_datetime(&naive)),
+        None => {
+            warn!("Skipping row with invalid timestamp {}", timestamp);
+            None
+        }
+    }
+}
+
+/// RFC 3339 with the offset of the zone, `Z` for UTC
+pub fn format_timestamp(time: &DateTime<Tz>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// unix time of the first second of `date` in the zone
+pub fn start_of_day(date: NaiveDate, tz: Tz) -> i64 {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("Invalid date");
+    // where DST starts at midnight the day starts when the clocks jump
+    (0..24)
+        .find_map(|hours| {
+            tz.from_local_datetime(&(midnight + Duration::hours(hours)))
+                .earliest()
+        })
+        .map_or_else(
+            || tz.from_utc_datetime(&midnight).timestamp(),
+            |time| time.timestamp(),
+        )
+}
+
+/// decodes a stored message into the `/v1` schema if it matches the filter
+fn to_transaction(tx_key: TxKey, value: &[u8], filter: &TxFilter, tz: Tz) -> Option<Transaction> {
+    let msg = decode_stored_msg(&tx_key, value)?;
+    if !filter.matches(&tx_key.msg_type, &tx_key.tx_hash, tx_key.timestamp, &msg) {
+        return None;
+    }
+    let time = block_time(tx_key.timestamp, tz)?;
+    Some(Transaction {
+        height: tx_key.block_number,
+        tx_hash: tx_key.tx_hash,
+        msg_index: tx_key.msg_index,
+        msg_type: tx_key.msg_type,
+        timestamp: format_timestamp(&time),
+        unix_time: tx_key.timestamp,
+        data: msg,
+    })
+}
+
+/// calls `f` with every stored message matching the filter in key order,
+/// without holding more than one of them in memory
+pub fn for_each_transaction(
+    db: &DB,
+    filter: &TxFilter,
+    tz: Tz,
+    mut f: impl FnMut(Transaction),
+) -> Result<(), rocksdb::Error> {
+    for_each_msg(db, |tx_key, value| {
+        if let Some(transaction) = to_transaction(tx_key, value, filter, tz) {
+            f(transaction);
+        }
+    })
+}
+
+/// whether any stored message matches the filter, the scan stops at the
+/// first one
+pub fn any_transaction(db: &DB, filter: &TxFilter) -> Result<bool, rocksdb::Error> {
+    let mut found = false;
+    scan_msgs(db, IteratorMode::Start, |_, tx_key, value| {
+        found = to_transaction(tx_key, value, filter, Tz::UTC).is_some();
+        !found
+    })?;
+    Ok(found)
+}
+
+/// the messages of one block in key order
+pub fn load_block(db: &DB, height: u64, tz: Tz) -> Result<Vec<Transaction>, rocksdb::Error> {
+    let prefix = format!("{:012}:", height);
+    let mut transactions = Vec::new();
+    scan_msgs(
+        db,
+        IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward),
+        |_, tx_key, value| {
+            if tx_key.block_number != height {
+                return false;
+            }
+            if let Some(transaction) = to_transaction(tx_key, value, &TxFilter::default(), tz) {
+                transactions.push(transaction);
+            }
+            true
+        },
+    )?;
+    Ok(transactions)
+}
+
+/// the messages of a transaction in key order, found through the tx hash
+/// index. Empty if the transaction isn't indexed.
+pub fn load_tx(db: &DB, tx_hash: &str, tz: Tz) -> Result<Vec<Transaction>, rocksdb::Error> {
+    let height = match load_tx_height(db, tx_hash)? {
+        Some(height) => height,
+        None => return Ok(Vec::new()),
+    };
+    let mut transactions = load_block(db, height, tz)?;
+    transactions.retain(|transaction| transaction.tx_hash == tx_hash);
+    Ok(transactions)
+}
+
+/// up to `limit` blocks with indexed messages, starting after the block at
+/// `after`, with their messages in key order. Returns whether more follow.
+pub fn load_blocks(
+    db: &DB,
+    order: Order,
+    tz: Tz,
+    after: Option<u64>,
+    limit: usize,
+) -> Result<(Vec<Vec<Transaction>>, bool), rocksdb::Error> {
+    // `;` sorts right after the `:` that ends the height, so seeking to it
+    // skips every key of the block going forward, and seeking backwards to
+    // the bare prefix lands on the block before
+    let start = after.map(|height| match order {
+        Order::Asc => format!("{:012};", height),
+        Order::Desc => format!("{:012}:", height),
+    });
+    let mode = match (&start, order) {
+        (None, Order::Asc) => IteratorMode::Start,
+        (None, Order::Desc) => IteratorMode::End,
+        (Some(start), Order::Asc) => {
+            IteratorMode::From(start.as_bytes(), rocksdb::Direction::Forward)
+        }
+        (Some(start), Order::Desc) => {
+            IteratorMode::From(start.as_bytes(), rocksdb::Direction::Reverse)
+        }
+    };
+
+    let mut blocks: Vec<Vec<Transaction>> = Vec::new();
+    let mut has_more = false;
+    scan_msgs(db, mode, |_, tx_key, value| {
+        let is_new_block = blocks
+            .last()
+            .and_then(|block| block.first())
+            .map_or(true, |first| first.height != tx_key.block_number);
+        if is_new_block && blocks.len() == limit {
+            has_more = true;
+            return false;
+        }
+        if let Some(transaction) = to_transaction(tx_key, value, &TxFilter::default(), tz) {
+            if is_new_block {
+                blocks.push(Vec::new());
+            }
+            if let Some(block) = blocks.last_mut() {
+                block.push(transaction);
+            }
+        }
+        true
+    })?;
+
+    // going backwards the messages of each block come in reverse
+    if order == Order::Desc {
+        for block in blocks.iter_mut() {
+            block.reverse();
+        }
+    }
+    Ok((blocks, has_more))
+}
+
+/// One page of messages, each with the cursor of its position
+pub struct Page {
+    pub transactions: Vec<(String, Transaction)>,
+    /// whether more messages match after the last one
+    pub has_more: bool,
+    /// key of the last message read whether it matched or not, continuing
+    /// from it skips the messages the filter already rejected
+    pub scanned_to: Option<String>,
+}
+
+/// up to `limit` messages matching the filter, starting after the message
+/// with the `after` cursor. Cursors are storage keys, so a page is read
+/// without scanning the messages before it.
+pub fn load_page(
+    db: &DB,
+    filter: &TxFilter,
+    order: Order,
+    tz: Tz,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<Page, rocksdb::Error> {
+    let mode = match (after, order) {
+        (None, Order::Asc) => IteratorMode::Start,
+        (None, Order::Desc) => IteratorMode::End,
+        (Some(cursor), Order::Asc) => {
+            IteratorMode::From(cursor.as_bytes(), rocksdb::Direction::Forward)
+        }
+        (Some(cursor), Order::Desc) => {
+            IteratorMode::From(cursor.as_bytes(), rocksdb::Direction::Reverse)
+        }
+    };
+
+    let mut transactions = Vec::new();
+    let mut has_more = false;
+    let mut scanned_to = None;
+    scan_msgs(db, mode, |key, tx_key, value| {
+        // the iterator starts at the cursor itself
+        if after.map_or(false, |cursor| key == cursor.as_bytes()) {
+            return true;
+        }
+        let cursor = String::from_utf8_lossy(key).into_owned();
+        let transaction = match to_transaction(tx_key, value, filter, tz) {
+            Some(transaction) => transaction,
+            None => {
+                scanned_to = Some(cursor);
+                return true;
+            }
+        };
+        if transactions.len() == limit {
+            has_more = true;
+            return false;
+        }
+        scanned_to = Some(cursor.clone());
+        transactions.push((cursor, transaction));
+        true
+    })?;
+    Ok(Page {
+        transactions,
+        has_more,
+        scanned_to,
+    })
+}
+
+/// groups transactions sorted by height into blocks, keeping their order
+pub fn group_by_block(transactions: Vec<Transaction>) -> Vec<TransactionBlock> {
+    let mut blocks: Vec<TransactionBlock> = Vec::new();
+    for transaction in transactions {
+        match blocks.last_mut() {
+            Some(block) if block.height == transaction.height => {
+                block.transactions.push(transaction)
+            }
+            _ => blocks.push(TransactionBlock {
+                height: transaction.height,
+                timestamp: transaction.timestamp.clone(),
+                unix_time: transaction.unix_time,
+                transactions: vec![transaction],
+            }),
+        }
+    }
+    blocks
+}
+
+/// groups transactions sorted by height by the date of their block in the
+/// given zone, keeping their order
+pub fn group_by_day(transactions: Vec<Transaction>, tz: Tz) -> Vec<TransactionDay> {
+    let mut days: Vec<TransactionDay> = Vec::new();
+    for transaction in transactions {
+        let date = match block_time(transaction.unix_time, tz) {
+            Some(time) => time.date_naive().format("%Y-%m-%d").to_string(),
+            None => continue,
+        };
+        match days.last_mut() {
+            Some(day) if day.date == date => day.transactions.push(transaction),
+            _ => days.push(TransactionDay {
+                date,
+                transactions: vec![transaction],
+            }),
+        }
+    }
+    days
+}
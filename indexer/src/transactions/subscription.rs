@@ -0,0 +1,137 @@
+use futures::{SinkExt, StreamExt};
+use log::info;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+const NEW_BLOCK_QUERY: &str = "tm.event='NewBlock'";
+
+// a new block is expected well within this time, if nothing arrives the
+// subscription is treated as dropped even if the socket is still open
+const EVENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A NewBlock event subscription over the CometBFT websocket RPC
+/// (usually `ws://<node>:26657/websocket`)
+pub struct NewBlockSubscription {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl NewBlockSubscription {
+    pub async fn connect(rpc_url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (mut stream, _) = connect_async(rpc_url).await?;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "subscribe",
+            "id": 0,
+            "params": { "query": NEW_BLOCK_QUERY }
+        });
+        stream.send(Message::Text(request.to_string())).await?;
+        info!("Subscribed to NewBlock events on {}", rpc_url);
+        Ok(NewBlockSubscription { stream })
+    }
+
+    /// waits for the next NewBlock event and returns its height, any error
+    /// means the subscription is gone and a new one has to be made
+    pub async fn next_height(&mut self) -> Result<u64, Box<dyn std::error::Error>> {
+        loop {
+            let message = match timeout(EVENT_TIMEOUT, self.stream.next()).await {
+                Ok(Some(message)) => message?,
+                Ok(None) => return Err("NewBlock subscription closed".into()),
+                Err(_) => return Err("No NewBlock event received in time".into()),
+            };
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(frame) => {
+                    return Err(format!("NewBlock subscription closed: {:?}", frame).into())
+                }
+                _ => continue,
+            };
+            let event: Value = serde_json::from_str(&text)?;
+            if let Some(error) = event.get("error") {
+                return Err(format!("NewBlock subscription error: {}", error).into());
+            }
+            // the first reply only acknowledges the subscription and has no block
+            if let Some(height) = event["result"]["data"]["value"]["block"]["header"]["height"]
+                .as_str()
+                .and_then(|height| height.parse::<u64>().ok())
+            {
+                return Ok(height);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    /// serves one websocket connection that answers the subscribe request
+    /// with `replies`, then closes
+    async fn mock_rpc(replies: Vec<Value>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(socket).await.unwrap();
+            let request = match ws.next().await {
+                Some(Ok(Message::Text(text))) => serde_json::from_str::<Value>(&text).unwrap(),
+                other => panic!("Expected a subscribe request, got {:?}", other),
+            };
+            assert_eq!(request["method"], "subscribe");
+            assert_eq!(request["params"]["query"], NEW_BLOCK_QUERY);
+            for reply in replies {
+                ws.send(Message::Text(reply.to_string())).await.unwrap();
+            }
+            let _ = ws.close(None).await;
+        });
+        format!("ws://{}/websocket", address)
+    }
+
+    fn new_block(height: u64) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "result": {
+                "query": NEW_BLOCK_QUERY,
+                "data": {
+                    "type": "tendermint/event/NewBlock",
+                    "value": { "block": { "header": { "height": height.to_string() } } }
+                }
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn skips_the_acknowledgement_and_returns_heights() {
+        let ack = json!({ "jsonrpc": "2.0", "id": 0, "result": {} });
+        let url = mock_rpc(vec![ack, new_block(41), new_block(42)]).await;
+        let mut subscription = NewBlockSubscription::connect(&url).await.unwrap();
+        assert_eq!(subscription.next_height().await.unwrap(), 41);
+        assert_eq!(subscription.next_height().await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn fails_once_the_node_closes_the_socket() {
+        let url = mock_rpc(vec![new_block(7)]).await;
+        let mut subscription = NewBlockSubscription::connect(&url).await.unwrap();
+        assert_eq!(subscription.next_height().await.unwrap(), 7);
+        assert!(subscription.next_height().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_rpc_error() {
+        let error = json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "error": { "code": -32603, "message": "subscription limit reached" }
+        });
+        let url = mock_rpc(vec![error]).await;
+        let mut subscription = NewBlockSubscription::connect(&url).await.unwrap();
+        let e = subscription.next_height().await.unwrap_err();
+        assert!(e.to_string().contains("subscription limit reached"));
+    }
+}
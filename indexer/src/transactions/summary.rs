@@ -0,0 +1,344 @@
+use chrono_tz::Tz;
+use log::warn;
+use rocksdb::DB;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use super::query::{for_each_transaction, TxFilter};
+use crate::types::Transaction;
+
+/// The message an address was first or last seen in
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Sighting {
+    pub height: u64,
+    pub tx_hash: String,
+    pub timestamp: String,
+    pub unix_time: i64,
+}
+
+impl Sighting {
+    fn of(transaction: &Transaction) -> Sighting {
+        Sighting {
+            height: transaction.height,
+            tx_hash: transaction.tx_hash.clone(),
+            timestamp: transaction.timestamp.clone(),
+            unix_time: transaction.unix_time,
+        }
+    }
+}
+
+/// What an address exchanged with another one in a denom, amounts are in
+/// base units
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Counterparty {
+    pub address: String,
+    /// sent to the counterparty
+    pub sent: String,
+    /// received from the counterparty
+    pub received: String,
+    /// sent and received
+    pub volume: String,
+    /// messages between the two addresses moving the denom
+    pub msgs: u64,
+    /// an amount couldn't be read or a sum didn't fit, the amounts above
+    /// are then too low
+    pub incomplete: bool,
+}
+
+/// The totals of an address in one denom, amounts are in base units
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DenomSummary {
+    pub denom: String,
+    pub sent: String,
+    pub received: String,
+    /// received minus sent
+    pub net: String,
+    /// the addresses with the most volume first
+    pub top_counterparties: Vec<Counterparty>,
+    /// an amount couldn't be read or a sum didn't fit, the amounts above
+    /// are then too low
+    pub incomplete: bool,
+}
+
+/// Activity and totals of an address over the indexed messages
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AddressSummary {
+    pub address: String,
+    /// None if the address has no indexed messages
+    pub first_seen: Option<Sighting>,
+    pub last_seen: Option<Sighting>,
+    /// distinct transactions the address sent or received messages in
+    pub transactions: u64,
+    /// messages by type
+    #[schema(value_type = Object)]
+    pub msgs_by_type: BTreeMap<String, u64>,
+    /// sorted by denom
+    pub denoms: Vec<DenomSummary>,
+}
+
+#[derive(Default)]
+struct Flow {
+    sent: Decimal,
+    received: Decimal,
+    msgs: u64,
+    incomplete: bool,
+}
+
+impl Flow {
+    /// sums that don't fit a Decimal stay at its maximum and flag the flow
+    /// rather than failing the request
+    fn add(&mut self, sent: bool, amount: Decimal) {
+        let total = if sent { self.sent } else { self.received };
+        self.incomplete |= total.checked_add(amount).is_none();
+        let total = total.saturating_add(amount);
+        if sent {
+            self.sent = total;
+        } else {
+            self.received = total;
+        }
+    }
+
+    fn volume(&mut self) -> Decimal {
+        self.incomplete |= self.sent.checked_add(self.received).is_none();
+        self.sent.saturating_add(self.received)
+    }
+
+    fn net(&mut self) -> Decimal {
+        self.incomplete |= self.received.checked_sub(self.sent).is_none();
+        self.received.saturating_sub(self.sent)
+    }
+}
+
+#[derive(Default)]
+struct DenomTotals {
+    flow: Flow,
+    counterparties: HashMap<String, Flow>,
+}
+
+/// aggregates the messages of `filter.address` matching the filter, keeping
+/// `top` counterparties per denom. Only the totals are held in memory, not
+/// the messages.
+pub fn load_summary(
+    db: &DB,
+    filter: &TxFilter,
+    tz: Tz,
+    top: usize,
+) -> Result<AddressSummary, rocksdb::Error> {
+    let address = filter.address.clone().unwrap_or_default();
+    let mut first_seen = None;
+    let mut last_seen = None;
+    let mut transactions = 0;
+    // keys sort by height, so a tx hash only has to be remembered until the
+    // next block
+    let mut block_txs: (u64, HashSet<String>) = (0, HashSet::new());
+    let mut msgs_by_type: BTreeMap<String, u64> = BTreeMap::new();
+    let mut denoms: BTreeMap<String, DenomTotals> = BTreeMap::new();
+
+    for_each_transaction(db, filter, tz, |transaction| {
+        if block_txs.0 != transaction.height {
+            block_txs = (transaction.height, HashSet::new());
+        }
+        if block_txs.1.insert(transaction.tx_hash.clone()) {
+            transactions += 1;
+        }
+        *msgs_by_type
+            .entry(transaction.msg_type.clone())
+            .or_default() += 1;
+
+        let msg = &transaction.data;
+        // a transfer to itself is both sent and received
+        let mut sides = Vec::new();
+        if msg.sender() == address {
+            sides.push((true, msg.receiver()));
+        }
+        if msg.receiver() == address {
+            sides.push((false, msg.sender()));
+        }
+        for coin in msg.coins() {
+            // an amount that can't be read is left out of the totals, which
+            // are flagged as incomplete
+            let amount = match Decimal::from_str(&coin.amount) {
+                Ok(amount) => Some(amount),
+                Err(e) => {
+                    warn!(
+                        "Skipping amount {} in tx {} at height {}: {}",
+                        coin.amount, transaction.tx_hash, transaction.height, e
+                    );
+                    None
+                }
+            };
+            let totals = denoms.entry(coin.denom.clone()).or_default();
+            for (sent, counterparty) in sides.iter() {
+                let flow = totals
+                    .counterparties
+                    .entry(counterparty.to_string())
+                    .or_default();
+                flow.msgs += 1;
+                match amount {
+                    Some(amount) => {
+                        totals.flow.add(*sent, amount);
+                        flow.add(*sent, amount);
+                    }
+                    None => {
+                        totals.flow.incomplete = true;
+                        flow.incomplete = true;
+                    }
+                }
+            }
+        }
+
+        if first_seen.is_none() {
+            first_seen = Some(Sighting::of(&transaction));
+        }
+        last_seen = Some(transaction);
+    })?;
+
+    let denoms = denoms
+        .into_iter()
+        .map(|(denom, mut totals)| {
+            let mut counterparties: Vec<(String, Decimal, Flow)> = totals
+                .counterparties
+                .into_iter()
+                .map(|(address, mut flow)| (address, flow.volume(), flow))
+                .collect();
+            counterparties.sort_by(|(a, a_volume, _), (b, b_volume, _)| {
+                b_volume.cmp(a_volume).then_with(|| a.cmp(b))
+            });
+            let top_counterparties = counterparties
+                .into_iter()
+                .take(top)
+                .map(|(address, volume, flow)| Counterparty {
+                    address,
+                    sent: flow.sent.to_string(),
+                    received: flow.received.to_string(),
+                    volume: volume.to_string(),
+                    msgs: flow.msgs,
+                    incomplete: flow.incomplete,
+                })
+                .collect();
+            let net = totals.flow.net();
+            DenomSummary {
+                denom,
+                sent: totals.flow.sent.to_string(),
+                received: totals.flow.received.to_string(),
+                net: net.to_string(),
+                top_counterparties,
+                incomplete: totals.flow.incomplete,
+            }
+        })
+        .collect();
+
+    Ok(AddressSummary {
+        address,
+        first_seen,
+        last_seen: last_seen.as_ref().map(Sighting::of),
+        transactions,
+        msgs_by_type,
+        denoms,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::database::tests::{seed, send_msg, TestDb};
+
+    const ALICE: &str = "manifest1alice";
+    const BOB: &str = "manifest1bob";
+    const CAROL: &str = "manifest1carol";
+    const ERIN: &str = "manifest1erin";
+
+    fn summary(test: &TestDb, top: usize) -> AddressSummary {
+        seed(
+            &test.db,
+            vec![
+                // one tx with two messages
+                send_msg(1, "TX1", 0, ALICE, BOB, "100", "umfx"),
+                send_msg(1, "TX1", 1, ALICE, CAROL, "50", "umfx"),
+                send_msg(2, "TX2", 0, BOB, ALICE, "30", "umfx"),
+                send_msg(2, "TX3", 0, ALICE, ALICE, "10", "umfx"),
+                send_msg(3, "TX4", 0, CAROL, ALICE, "abc", "uatom"),
+                send_msg(3, "TX5", 0, ERIN, ALICE, "50", "umfx"),
+            ],
+        );
+        let filter = TxFilter {
+            address: Some(ALICE.to_string()),
+            ..TxFilter::default()
+        };
+        load_summary(&test.db, &filter, Tz::UTC, top).unwrap()
+    }
+
+    fn denom<'a>(summary: &'a AddressSummary, denom: &str) -> &'a DenomSummary {
+        summary
+            .denoms
+            .iter()
+            .find(|summary| summary.denom == denom)
+            .unwrap()
+    }
+
+    #[test]
+    fn sums_what_an_address_sent_and_received() {
+        let test = TestDb::new();
+        let summary = summary(&test, 10);
+        // the transfer to itself is both sent and received
+        let umfx = denom(&summary, "umfx");
+        assert_eq!(
+            (
+                umfx.sent.as_str(),
+                umfx.received.as_str(),
+                umfx.net.as_str()
+            ),
+            ("160", "90", "-70")
+        );
+        assert!(!umfx.incomplete);
+        let itself = umfx
+            .top_counterparties
+            .iter()
+            .find(|counterparty| counterparty.address == ALICE)
+            .unwrap();
+        assert_eq!(
+            (itself.sent.as_str(), itself.received.as_str(), itself.msgs),
+            ("10", "10", 2)
+        );
+    }
+
+    #[test]
+    fn flags_amounts_that_can_not_be_read() {
+        let test = TestDb::new();
+        let summary = summary(&test, 10);
+        let uatom = denom(&summary, "uatom");
+        assert!(uatom.incomplete);
+        assert_eq!((uatom.sent.as_str(), uatom.received.as_str()), ("0", "0"));
+        assert_eq!(uatom.top_counterparties[0].address, CAROL);
+        assert_eq!(uatom.top_counterparties[0].msgs, 1);
+        assert!(uatom.top_counterparties[0].incomplete);
+    }
+
+    #[test]
+    fn counts_each_transaction_once() {
+        let test = TestDb::new();
+        let summary = summary(&test, 10);
+        assert_eq!(summary.transactions, 5);
+        assert_eq!(summary.msgs_by_type.get("msgSend"), Some(&6));
+        let first_seen = summary.first_seen.unwrap();
+        assert_eq!((first_seen.height, first_seen.tx_hash.as_str()), (1, "TX1"));
+        let last_seen = summary.last_seen.unwrap();
+        assert_eq!((last_seen.height, last_seen.tx_hash.as_str()), (3, "TX5"));
+    }
+
+    #[test]
+    fn orders_counterparties_by_volume_then_address() {
+        let test = TestDb::new();
+        let summary = summary(&test, 3);
+        // carol and erin both moved 50, alice's transfer to itself 20
+        let top: Vec<(&str, &str)> = denom(&summary, "umfx")
+            .top_counterparties
+            .iter()
+            .map(|counterparty| (counterparty.address.as_str(), counterparty.volume.as_str()))
+            .collect();
+        assert_eq!(top, vec![(BOB, "130"), (CAROL, "50"), (ERIN, "50")]);
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CustomMsgSendToEth {
@@ -9,7 +10,7 @@ pub struct CustomMsgSendToEth {
     pub chain_fee: Vec<CustomCoin>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct CustomMsgTransfer {
     pub source_port: String,
     pub source_channel: String,
@@ -20,25 +21,26 @@ pub struct CustomMsgTransfer {
     pub timeout_timestamp: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct CustomHeight {
     pub revision_number: u64,
     pub revision_height: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct CustomCoin {
     pub denom: String,
     pub amount: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ApiResponse {
     pub tx_hash: String,
+    #[schema(value_type = Object)]
     pub data: serde_json::Value,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct CustomMsgSend {
     pub from_address: String,
     pub to_address: String,
@@ -51,10 +53,100 @@ pub struct CustomMsg {
     pub value: serde_json::Value,
 }
 
-#[derive(Serialize)]
-pub struct TransactionResponse {
-    pub tx_hash: String,
+/// A transaction that could not be decoded, stored with its raw bytes (hex
+/// encoded) so it can be inspected and retried
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct QuarantinedTx {
     pub block_number: u64,
-    pub formatted_date: String,
-    pub data: CustomMsgSend,
+    pub timestamp: i64,
+    pub tx_hash: String,
+    pub error: String,
+    pub raw_tx: String,
+}
+
+impl QuarantinedTx {
+    pub fn key(&self) -> String {
+        format!("{:012}:{}", self.block_number, self.tx_hash)
+    }
+}
+
+/// The parts of a transaction that aren't in its messages
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TxInfo {
+    pub fee: Vec<CustomCoin>,
+    pub memo: String,
+}
+
+/// A stored message, serialized as the message itself
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(untagged)]
+pub enum StoredMsg {
+    Send(CustomMsgSend),
+    IbcTransfer(CustomMsgTransfer),
+}
+
+impl StoredMsg {
+    pub fn sender(&self) -> &str {
+        match self {
+            StoredMsg::Send(msg) => &msg.from_address,
+            StoredMsg::IbcTransfer(msg) => &msg.sender,
+        }
+    }
+
+    pub fn receiver(&self) -> &str {
+        match self {
+            StoredMsg::Send(msg) => &msg.to_address,
+            StoredMsg::IbcTransfer(msg) => &msg.receiver,
+        }
+    }
+
+    /// the coins the message moves
+    pub fn coins(&self) -> &[CustomCoin] {
+        match self {
+            StoredMsg::Send(msg) => &msg.amount,
+            StoredMsg::IbcTransfer(msg) => &msg.token,
+        }
+    }
+}
+
+/// A single message in the schema of the `/v1` API
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Transaction {
+    pub height: u64,
+    pub tx_hash: String,
+    /// position of the message in the transaction
+    pub msg_index: u32,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    /// block time in RFC 3339, in UTC unless another zone is requested
+    pub timestamp: String,
+    /// block time in unix seconds
+    pub unix_time: i64,
+    pub data: StoredMsg,
+}
+
+/// The messages of one block, returned with `group_by=block`
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TransactionBlock {
+    pub height: u64,
+    pub timestamp: String,
+    pub unix_time: i64,
+    pub transactions: Vec<Transaction>,
+}
+
+/// The messages of one day, returned with `group_by=day`
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TransactionDay {
+    /// `YYYY-MM-DD` in the requested time zone
+    pub date: String,
+    pub transactions: Vec<Transaction>,
+}
+
+/// The response of the `/v1/transactions` endpoints, depending on `group_by`
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(untagged)]
+pub enum TransactionList {
+    Transactions(Vec<Transaction>),
+    Blocks(Vec<TransactionBlock>),
+    Days(Vec<TransactionDay>),
 }
@@ -0,0 +1,1239 @@
+use actix_web::http::Uri;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use chrono_tz::Tz;
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use rand::Rng;
+use rocksdb::{ColumnFamily, Direction as IterDirection, IteratorMode, WriteBatch, DB};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::lookup_host;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::shutdown::{shutdown_requested, sleep_or_shutdown};
+use crate::transactions::feed::{FeedEvent, Tail};
+use crate::transactions::query::{Direction, FilterParams, TxFilter};
+use crate::types::Transaction;
+
+// webhooks under `subscription:{id}` and the position of the matcher
+// in the stored messages under `cursor`
+pub const WEBHOOKS_CF: &str = "webhooks";
+// the delivery log under `{webhook_id}:{created:012}:{delivery_id}`
+pub const WEBHOOK_DELIVERIES_CF: &str = "webhook_deliveries";
+// pending deliveries under `{next_attempt:012}:{log key}`, so the due ones
+// come first
+pub const WEBHOOK_QUEUE_CF: &str = "webhook_queue";
+
+const SUBSCRIPTION_PREFIX: &str = "subscription:";
+const CURSOR_KEY: &str = "cursor";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// a delivery is tried this many times, waiting twice as long after every
+// failure, before it is given up on
+const MAX_ATTEMPTS: u32 = 10;
+const FIRST_RETRY_DELAY: i64 = 10;
+const MAX_RETRY_DELAY: i64 = 3600;
+// how many due deliveries are sent at once and how often the queue is checked
+const SEND_BATCH_SIZE: usize = 20;
+const SEND_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_LOG_ENTRIES: usize = 100;
+// cutting a log walks all of it, so the logs are cut this often rather than
+// on every queued delivery
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The hosts webhooks may be sent to. Loopback, private and link local
+/// addresses are refused unless `allow_private` is set, so webhooks can't be
+/// used to reach services next to the indexer.
+#[derive(Debug, Clone, Default)]
+pub struct WebhookTargets {
+    /// only these hosts when not empty, `*.example.com` matches subdomains
+    pub allowed_hosts: Vec<String>,
+    /// never these hosts, in the same notation
+    pub denied_hosts: Vec<String>,
+    pub allow_private: bool,
+}
+
+fn matches_host(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => host == pattern,
+    }
+}
+
+/// loopback, private, link local and other addresses that aren't reachable
+/// on the internet
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => is_internal_v6(ip),
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || first == 0
+        // shared address space of carrier grade NAT, 100.64.0.0/10
+        || (first == 100 && (second & 0xc0) == 64)
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // unique local fc00::/7 and link local fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || ip.to_ipv4_mapped().map_or(false, is_internal_v4)
+}
+
+impl WebhookTargets {
+    /// the host and port of a url webhooks may be sent to, without resolving
+    /// the host
+    fn check_url(&self, url: &str) -> Result<(String, u16), String> {
+        let uri = url
+            .parse::<Uri>()
+            .map_err(|e| format!("Invalid url {}: {}", url, e))?;
+        let port = match uri.scheme_str() {
+            Some("http") => uri.port_u16().unwrap_or(80),
+            Some("https") => uri.port_u16().unwrap_or(443),
+            _ => {
+                return Err(format!(
+                    "Invalid url {}, expected an http:// or https:// url",
+                    url
+                ))
+            }
+        };
+        let host = uri
+            .host()
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| format!("Invalid url {}, it has no host", url))?
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+
+        if self
+            .denied_hosts
+            .iter()
+            .any(|pattern| matches_host(pattern, &host))
+        {
+            return Err(format!("Webhooks to {} are not allowed", host));
+        }
+        if !self.allowed_hosts.is_empty()
+            && !self
+                .allowed_hosts
+                .iter()
+                .any(|pattern| matches_host(pattern, &host))
+        {
+            return Err(format!("Webhooks to {} are not allowed", host));
+        }
+        if !self.allow_private {
+            let ip = host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>();
+            let internal = match ip {
+                Ok(ip) => is_internal(ip),
+                Err(_) => host == "localhost" || host.ends_with(".localhost"),
+            };
+            if internal {
+                return Err(format!(
+                    "Webhooks to the internal address {} are not allowed",
+                    host
+                ));
+            }
+        }
+        Ok((host, port))
+    }
+
+    /// checks the url and, unless private addresses are allowed, every
+    /// address its host resolves to, as that can change after the webhook
+    /// was created. Returns the address to connect to, so the host isn't
+    /// resolved again to a different one when the request is sent.
+    async fn check_target(&self, url: &str) -> Result<SocketAddr, String> {
+        let (host, port) = self.check_url(url)?;
+        self.check_resolved(&host, port).await
+    }
+
+    async fn check_resolved(&self, host: &str, port: u16) -> Result<SocketAddr, String> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addresses: Vec<SocketAddr> = lookup_host((host, port))
+            .await
+            .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+            .collect();
+        if !self.allow_private {
+            if let Some(address) = addresses.iter().find(|address| is_internal(address.ip())) {
+                return Err(format!(
+                    "{} resolves to the internal address {}",
+                    host,
+                    address.ip()
+                ));
+            }
+        }
+        addresses
+            .first()
+            .copied()
+            .ok_or_else(|| format!("No address found for {}", host))
+    }
+}
+
+/// What a webhook is notified about, unset filters match every message
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct WebhookFilter {
+    /// only messages this address sent or received
+    pub address: Option<String>,
+    /// only with an address, whether it sent or received the messages
+    pub direction: Option<Direction>,
+    /// `msgSend` or `msgIbcTransfer`
+    #[serde(rename = "type")]
+    pub msg_type: Option<String>,
+    pub denom: Option<String>,
+    /// only messages moving at least this amount, of `denom` if set
+    pub min_amount: Option<String>,
+}
+
+impl WebhookFilter {
+    fn to_filter(&self) -> Result<TxFilter, ApiError> {
+        let (mut filter, _) = FilterParams {
+            address: self.address.clone(),
+            direction: self.direction,
+            msg_type: self.msg_type.clone(),
+            denom: self.denom.clone(),
+            ..FilterParams::default()
+        }
+        .into_filter()?;
+        if let Some(min_amount) = &self.min_amount {
+            filter.min_amount = Some(Decimal::from_str(min_amount).map_err(|_| {
+                ApiError::bad_request(
+                    "invalid_amount",
+                    format!("Invalid min_amount {}, expected a number", min_amount),
+                )
+            })?);
+        }
+        Ok(filter)
+    }
+}
+
+/// The body of a request creating a webhook
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewWebhook {
+    /// receives a POST for every matching message
+    pub url: String,
+    #[serde(flatten)]
+    pub filter: WebhookFilter,
+}
+
+/// A webhook as listed, without its secret
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    #[serde(flatten)]
+    pub filter: WebhookFilter,
+    pub created: i64,
+}
+
+/// A webhook as stored, the secret is only returned when it is created
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct StoredWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    /// HMAC-SHA256 key of the `X-Webhook-Signature` header
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+/// An entry of the delivery log
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Delivery {
+    pub id: String,
+    pub webhook_id: String,
+    /// cursor of the message in the stored messages
+    pub cursor: String,
+    /// the message as in `/v1/transactions`
+    #[schema(value_type = Object)]
+    pub transaction: serde_json::Value,
+    pub created: i64,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub last_attempt: Option<i64>,
+    /// when a pending delivery is tried next
+    pub next_attempt: Option<i64>,
+    /// HTTP status of the last attempt, if the receiver answered
+    pub response_status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl Delivery {
+    fn log_key(&self) -> String {
+        format!("{}:{:012}:{}", self.webhook_id, self.created, self.id)
+    }
+}
+
+/// The JSON body POSTed to a webhook
+#[derive(Serialize)]
+struct DeliveryBody<'a> {
+    /// the same for every attempt, receivers can use it to skip duplicates
+    id: &'a str,
+    webhook_id: &'a str,
+    transaction: &'a serde_json::Value,
+}
+
+fn webhooks_cf(db: &DB) -> &ColumnFamily {
+    db.cf_handle(WEBHOOKS_CF)
+        .expect("Webhooks column family is missing")
+}
+
+fn deliveries_cf(db: &DB) -> &ColumnFamily {
+    db.cf_handle(WEBHOOK_DELIVERIES_CF)
+        .expect("Webhook deliveries column family is missing")
+}
+
+fn queue_cf(db: &DB) -> &ColumnFamily {
+    db.cf_handle(WEBHOOK_QUEUE_CF)
+        .expect("Webhook queue column family is missing")
+}
+
+fn queue_key(next_attempt: i64, log_key: &str) -> String {
+    format!("{:012}:{}", next_attempt, log_key)
+}
+
+fn random_id(bytes: usize) -> String {
+    let mut id = vec![0u8; bytes];
+    rand::thread_rng().fill(&mut id[..]);
+    hex::encode(id)
+}
+
+/// validates and stores a new webhook
+pub fn create_webhook(
+    db: &DB,
+    new: NewWebhook,
+    targets: &WebhookTargets,
+) -> Result<StoredWebhook, ApiError> {
+    targets
+        .check_url(&new.url)
+        .map_err(|e| ApiError::bad_request("invalid_url", e))?;
+    new.filter.to_filter()?;
+    let stored = StoredWebhook {
+        webhook: Webhook {
+            id: random_id(8),
+            url: new.url,
+            filter: new.filter,
+            created: Utc::now().timestamp(),
+        },
+        secret: random_id(32),
+    };
+    db.put_cf(
+        webhooks_cf(db),
+        format!("{}{}", SUBSCRIPTION_PREFIX, stored.webhook.id),
+        serde_json::to_vec(&stored).unwrap(),
+    )?;
+    info!(
+        "Created webhook {} for {}",
+        stored.webhook.id, stored.webhook.url
+    );
+    Ok(stored)
+}
+
+fn load_stored_webhooks(db: &DB) -> Vec<StoredWebhook> {
+    let mut webhooks = Vec::new();
+    let mode = IteratorMode::From(SUBSCRIPTION_PREFIX.as_bytes(), IterDirection::Forward);
+    for item in db.iterator_cf(webhooks_cf(db), mode) {
+        match item {
+            Ok((key, value)) => {
+                if !key.starts_with(SUBSCRIPTION_PREFIX.as_bytes()) {
+                    break;
+                }
+                match serde_json::from_slice::<StoredWebhook>(&value) {
+                    Ok(webhook) => webhooks.push(webhook),
+                    Err(e) => error!("Failed to deserialize webhook: {}", e),
+                }
+            }
+            Err(err) => error!("RocksDB iterator error: {}", err),
+        }
+    }
+    webhooks
+}
+
+fn load_stored_webhook(db: &DB, id: &str) -> Result<Option<StoredWebhook>, rocksdb::Error> {
+    Ok(db
+        .get_cf(webhooks_cf(db), format!("{}{}", SUBSCRIPTION_PREFIX, id))?
+        .and_then(|value| serde_json::from_slice(&value).ok()))
+}
+
+pub fn list_webhooks(db: &DB) -> Vec<Webhook> {
+    load_stored_webhooks(db)
+        .into_iter()
+        .map(|stored| stored.webhook)
+        .collect()
+}
+
+/// deletes the webhook, its pending deliveries fail once they are due.
+/// Returns whether it existed.
+pub fn delete_webhook(db: &DB, id: &str) -> Result<bool, rocksdb::Error> {
+    if load_stored_webhook(db, id)?.is_none() {
+        return Ok(false);
+    }
+    db.delete_cf(webhooks_cf(db), format!("{}{}", SUBSCRIPTION_PREFIX, id))?;
+    info!("Deleted webhook {}", id);
+    Ok(true)
+}
+
+/// the newest entries of a webhook's delivery log, newest first
+pub fn load_deliveries(db: &DB, webhook_id: &str) -> Result<Vec<Delivery>, rocksdb::Error> {
+    let prefix = format!("{}:", webhook_id);
+    // `;` sorts right after `:`, so this starts at the webhook's last entry
+    let end = format!("{};", webhook_id);
+    let mode = IteratorMode::From(end.as_bytes(), IterDirection::Reverse);
+    let mut deliveries = Vec::new();
+    for item in db.iterator_cf(deliveries_cf(db), mode) {
+        let (key, value) = item?;
+        if !key.starts_with(prefix.as_bytes()) || deliveries.len() == MAX_LOG_ENTRIES {
+            break;
+        }
+        match serde_json::from_slice::<Delivery>(&value) {
+            Ok(delivery) => deliveries.push(delivery),
+            Err(e) => error!("Failed to deserialize webhook delivery: {}", e),
+        }
+    }
+    Ok(deliveries)
+}
+
+pub async fn post_webhook(
+    db: web::Data<Arc<DB>>,
+    targets: web::Data<WebhookTargets>,
+    new: NewWebhook,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Created().json(create_webhook(&db, new, &targets)?))
+}
+
+pub async fn get_webhooks(db: web::Data<Arc<DB>>) -> HttpResponse {
+    HttpResponse::Ok().json(list_webhooks(&db))
+}
+
+pub async fn delete_webhook_by_id(
+    db: web::Data<Arc<DB>>,
+    id: String,
+) -> Result<HttpResponse, ApiError> {
+    if delete_webhook(&db, &id)? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiError::not_found(format!(
+            "Webhook {} doesn't exist.",
+            id
+        )))
+    }
+}
+
+/// the log is kept after the webhook is deleted
+pub async fn get_webhook_deliveries(
+    db: web::Data<Arc<DB>>,
+    id: String,
+) -> Result<HttpResponse, ApiError> {
+    let deliveries = load_deliveries(&db, &id)?;
+    if deliveries.is_empty() && load_stored_webhook(&db, &id)?.is_none() {
+        return Err(ApiError::not_found(format!(
+            "Webhook {} doesn't exist.",
+            id
+        )));
+    }
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+/// deletes the entries of a webhook's log after the newest `keep`, pending
+/// deliveries stay until they are delivered or given up
+fn prune_log(
+    db: &DB,
+    batch: &mut WriteBatch,
+    webhook_id: &str,
+    keep: usize,
+) -> Result<(), rocksdb::Error> {
+    let prefix = format!("{}:", webhook_id);
+    let end = format!("{};", webhook_id);
+    let mode = IteratorMode::From(end.as_bytes(), IterDirection::Reverse);
+    let mut kept = 0;
+    for item in db.iterator_cf(deliveries_cf(db), mode) {
+        let (key, value) = item?;
+        if !key.starts_with(prefix.as_bytes()) {
+            break;
+        }
+        if kept < keep {
+            kept += 1;
+            continue;
+        }
+        let pending = serde_json::from_slice::<Delivery>(&value)
+            .map_or(false, |delivery| delivery.status == DeliveryStatus::Pending);
+        if !pending {
+            batch.delete_cf(deliveries_cf(db), key);
+        }
+    }
+    Ok(())
+}
+
+/// cuts the log of every webhook to the newest MAX_LOG_ENTRIES
+fn prune_logs(db: &DB) -> Result<(), rocksdb::Error> {
+    let mut batch = WriteBatch::default();
+    for stored in load_stored_webhooks(db) {
+        prune_log(db, &mut batch, &stored.webhook.id, MAX_LOG_ENTRIES)?;
+    }
+    db.write(batch)
+}
+
+/// queues a delivery of the message for every webhook it matches and moves
+/// the matcher past it, in one write
+fn enqueue(db: &DB, cursor: &str, transaction: &Transaction) -> Result<(), rocksdb::Error> {
+    let mut batch = WriteBatch::default();
+    let now = Utc::now().timestamp();
+    for stored in load_stored_webhooks(db) {
+        let webhook = stored.webhook;
+        let matches = match webhook.filter.to_filter() {
+            // messages from before the webhook existed aren't delivered,
+            // e.g. while the indexer catches up
+            Ok(mut filter) => {
+                filter.from_time = Some(webhook.created);
+                filter.matches_transaction(transaction)
+            }
+            Err(e) => {
+                warn!(
+                    "Skipping webhook {} with an invalid filter: {}",
+                    webhook.id, e
+                );
+                false
+            }
+        };
+        if !matches {
+            continue;
+        }
+        let delivery = Delivery {
+            id: random_id(8),
+            webhook_id: webhook.id,
+            cursor: cursor.to_string(),
+            transaction: serde_json::to_value(transaction).unwrap(),
+            created: now,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            last_attempt: None,
+            next_attempt: Some(now),
+            response_status: None,
+            error: None,
+        };
+        let log_key = delivery.log_key();
+        batch.put_cf(
+            deliveries_cf(db),
+            &log_key,
+            serde_json::to_vec(&delivery).unwrap(),
+        );
+        batch.put_cf(queue_cf(db), queue_key(now, &log_key), log_key.as_bytes());
+    }
+    batch.put_cf(webhooks_cf(db), CURSOR_KEY, cursor.as_bytes());
+    db.write(batch)
+}
+
+/// follows the indexed messages and queues a delivery for each match
+async fn match_messages(db: Arc<DB>) {
+    let cursor = match db.get_cf(webhooks_cf(&db), CURSOR_KEY) {
+        Ok(cursor) => cursor.map(|cursor| String::from_utf8_lossy(&cursor).into_owned()),
+        Err(e) => {
+            error!("Failed to load the webhook cursor: {}", e);
+            return;
+        }
+    };
+    // without a cursor only messages indexed from now on are delivered. The
+    // tail only reads up to the checkpoint, so blocks the historical sync
+    // stores out of order aren't skipped.
+    let mut tail = match Tail::new(db.clone(), TxFilter::default(), Tz::UTC, cursor, None) {
+        Ok(tail) => tail,
+        Err(e) => {
+            error!("Failed to start the webhook matcher: {}", e);
+            return;
+        }
+    };
+    loop {
+        match tail.next().await {
+            Ok(FeedEvent::Transaction(cursor, transaction)) => {
+                if let Err(e) = enqueue(&db, &cursor, &transaction) {
+                    error!("Failed to queue webhook deliveries: {}", e);
+                }
+            }
+            Ok(FeedEvent::Idle) => {}
+            Ok(FeedEvent::Shutdown) => return,
+            Err(e) => {
+                error!("Database error in the webhook matcher: {}", e);
+                sleep_or_shutdown(SEND_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// `sha256=<hex>` of the HMAC of `{timestamp}.{body}` with the secret
+fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs the delivery to the checked `address` of the webhook's host,
+/// returns the response status if the receiver answered
+async fn post(
+    client: &awc::Client,
+    webhook: &StoredWebhook,
+    delivery: &Delivery,
+    address: SocketAddr,
+) -> Result<u16, String> {
+    let body = serde_json::to_string(&DeliveryBody {
+        id: &delivery.id,
+        webhook_id: &delivery.webhook_id,
+        transaction: &delivery.transaction,
+    })
+    .map_err(|e| e.to_string())?;
+    let timestamp = Utc::now().timestamp();
+    let response = client
+        .post(&webhook.webhook.url)
+        .address(address)
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("X-Webhook-Id", delivery.id.as_str()))
+        .insert_header(("X-Webhook-Timestamp", timestamp.to_string()))
+        .insert_header((
+            "X-Webhook-Signature",
+            signature(&webhook.secret, timestamp, &body),
+        ))
+        .send_body(body)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(response.status().as_u16())
+}
+
+/// seconds to wait after the given number of failed attempts
+fn retry_delay(attempts: u32) -> i64 {
+    FIRST_RETRY_DELAY
+        .saturating_mul(1i64 << attempts.saturating_sub(1).min(30))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// tries a due delivery once and reschedules, completes or gives it up
+async fn attempt(
+    db: &DB,
+    client: &awc::Client,
+    targets: &WebhookTargets,
+    queued: Box<[u8]>,
+    log_key: String,
+) {
+    let mut delivery = match db.get_cf(deliveries_cf(db), &log_key) {
+        Ok(Some(value)) => match serde_json::from_slice::<Delivery>(&value) {
+            Ok(delivery) => delivery,
+            Err(e) => {
+                error!("Dropping corrupt webhook delivery {}: {}", log_key, e);
+                let _ = db.delete_cf(queue_cf(db), &queued);
+                return;
+            }
+        },
+        Ok(None) => {
+            let _ = db.delete_cf(queue_cf(db), &queued);
+            return;
+        }
+        Err(e) => {
+            error!("Failed to load webhook delivery {}: {}", log_key, e);
+            return;
+        }
+    };
+
+    let webhook = match load_stored_webhook(db, &delivery.webhook_id) {
+        Ok(webhook) => webhook,
+        Err(e) => {
+            error!("Failed to load webhook {}: {}", delivery.webhook_id, e);
+            return;
+        }
+    };
+    let now = Utc::now().timestamp();
+    // deliveries to a deleted webhook or a target that isn't allowed are
+    // given up right away, retrying wouldn't change anything
+    let mut give_up = true;
+    let result = match &webhook {
+        Some(webhook) => match targets.check_target(&webhook.webhook.url).await {
+            Ok(address) => {
+                give_up = false;
+                post(client, webhook, &delivery, address).await
+            }
+            Err(e) => Err(e),
+        },
+        None => Err("The webhook was deleted".to_string()),
+    };
+    delivery.attempts += 1;
+    delivery.last_attempt = Some(now);
+    delivery.next_attempt = None;
+    match result {
+        Ok(status) if (200..300).contains(&status) => {
+            delivery.status = DeliveryStatus::Delivered;
+            delivery.response_status = Some(status);
+            delivery.error = None;
+        }
+        Ok(status) => {
+            delivery.response_status = Some(status);
+            delivery.error = Some(format!("The receiver answered with {}", status));
+        }
+        Err(e) => {
+            delivery.response_status = None;
+            delivery.error = Some(e);
+        }
+    }
+
+    let mut batch = WriteBatch::default();
+    batch.delete_cf(queue_cf(db), &queued);
+    if delivery.status != DeliveryStatus::Delivered {
+        if give_up || delivery.attempts >= MAX_ATTEMPTS {
+            delivery.status = DeliveryStatus::Failed;
+            warn!(
+                "Giving up on webhook delivery {} after {} attempts: {}",
+                delivery.id,
+                delivery.attempts,
+                delivery.error.as_deref().unwrap_or_default()
+            );
+        } else {
+            let next_attempt = now + retry_delay(delivery.attempts);
+            delivery.next_attempt = Some(next_attempt);
+            batch.put_cf(
+                queue_cf(db),
+                queue_key(next_attempt, &log_key),
+                log_key.as_bytes(),
+            );
+        }
+    }
+    batch.put_cf(
+        deliveries_cf(db),
+        &log_key,
+        serde_json::to_vec(&delivery).unwrap(),
+    );
+    if let Err(e) = db.write(batch) {
+        error!("Failed to save webhook delivery {}: {}", delivery.id, e);
+    }
+}
+
+/// the queued deliveries that are due, oldest first
+fn due_deliveries(db: &DB) -> Vec<(Box<[u8]>, String)> {
+    let now = Utc::now().timestamp();
+    let mut due = Vec::new();
+    for item in db.iterator_cf(queue_cf(db), IteratorMode::Start) {
+        let (key, value) = match item {
+            Ok(item) => item,
+            Err(e) => {
+                error!("RocksDB iterator error: {}", e);
+                break;
+            }
+        };
+        let next_attempt = String::from_utf8_lossy(&key)
+            .split(':')
+            .next()
+            .and_then(|time| time.parse::<i64>().ok());
+        match next_attempt {
+            Some(time) if time > now => break,
+            Some(_) => due.push((key, String::from_utf8_lossy(&value).into_owned())),
+            None => {
+                error!("Dropping corrupt webhook queue entry");
+                let _ = db.delete_cf(queue_cf(db), &key);
+            }
+        }
+        if due.len() == SEND_BATCH_SIZE {
+            break;
+        }
+    }
+    due
+}
+
+/// sends the queued deliveries as they become due
+async fn send_deliveries(db: Arc<DB>, targets: WebhookTargets) {
+    // a redirect could lead to a target that isn't allowed
+    let client = awc::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .disable_redirects()
+        .finish();
+    let mut last_prune: Option<Instant> = None;
+    while !shutdown_requested() {
+        if last_prune.map_or(true, |time| time.elapsed() >= PRUNE_INTERVAL) {
+            if let Err(e) = prune_logs(&db) {
+                error!("Failed to prune the webhook logs: {}", e);
+            }
+            last_prune = Some(Instant::now());
+        }
+        let due = due_deliveries(&db);
+        if due.is_empty() {
+            sleep_or_shutdown(SEND_INTERVAL).await;
+            continue;
+        }
+        join_all(
+            due.into_iter()
+                .map(|(queued, log_key)| attempt(&db, &client, &targets, queued, log_key)),
+        )
+        .await;
+    }
+}
+
+/// delivers webhooks for the messages this process indexes, until shutdown
+pub async fn run_webhooks(db: Arc<DB>, targets: WebhookTargets) {
+    info!("Starting webhook delivery");
+    futures::join!(match_messages(db.clone()), send_deliveries(db, targets));
+    info!("Webhook delivery stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{App, HttpRequest, HttpServer};
+    use rocksdb::Options;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    use crate::types::{CustomCoin, CustomMsgSend, StoredMsg};
+
+    const ALICE: &str = "manifest1alice";
+    const BOB: &str = "manifest1bob";
+
+    /// a database with the webhook column families in the temp dir, removed
+    /// when dropped
+    struct TestDb {
+        db: DB,
+        path: PathBuf,
+    }
+
+    impl TestDb {
+        fn new() -> TestDb {
+            let path = std::env::temp_dir().join(format!("webhooks-test-{}", random_id(8)));
+            let mut options = Options::default();
+            options.create_if_missing(true);
+            options.create_missing_column_families(true);
+            let db = DB::open_cf(
+                &options,
+                &path,
+                [WEBHOOKS_CF, WEBHOOK_DELIVERIES_CF, WEBHOOK_QUEUE_CF],
+            )
+            .unwrap();
+            TestDb { db, path }
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    /// a request the receiver got
+    #[derive(Debug, Clone)]
+    struct Received {
+        id: String,
+        timestamp: String,
+        signature: String,
+        body: String,
+    }
+
+    struct ReceiverState {
+        status: u16,
+        received: Mutex<Vec<Received>>,
+    }
+
+    async fn record(
+        req: HttpRequest,
+        body: String,
+        state: web::Data<ReceiverState>,
+    ) -> HttpResponse {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        state.received.lock().unwrap().push(Received {
+            id: header("X-Webhook-Id"),
+            timestamp: header("X-Webhook-Timestamp"),
+            signature: header("X-Webhook-Signature"),
+            body,
+        });
+        HttpResponse::build(StatusCode::from_u16(state.status).unwrap()).finish()
+    }
+
+    /// serves a receiver answering every request with `status`, returns its
+    /// url and what it received
+    fn receiver(status: u16) -> (String, web::Data<ReceiverState>) {
+        let state = web::Data::new(ReceiverState {
+            status,
+            received: Mutex::new(Vec::new()),
+        });
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .default_service(web::to(record))
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_rt::spawn(server.run());
+        (format!("http://{}/hooks", address), state)
+    }
+
+    /// the receiver runs on localhost
+    fn local_targets() -> WebhookTargets {
+        WebhookTargets {
+            allow_private: true,
+            ..WebhookTargets::default()
+        }
+    }
+
+    fn no_filter() -> WebhookFilter {
+        WebhookFilter {
+            address: None,
+            direction: None,
+            msg_type: None,
+            denom: None,
+            min_amount: None,
+        }
+    }
+
+    fn new_webhook(db: &DB, url: &str, filter: WebhookFilter) -> StoredWebhook {
+        let new = NewWebhook {
+            url: url.to_string(),
+            filter,
+        };
+        create_webhook(db, new, &local_targets()).unwrap()
+    }
+
+    /// a send of `amount` umfx from alice to bob in a block produced after
+    /// the webhooks of the test were created
+    fn send(amount: &str) -> Transaction {
+        Transaction {
+            height: 42,
+            tx_hash: "A1B2C3".to_string(),
+            msg_index: 0,
+            msg_type: "msgSend".to_string(),
+            timestamp: "2030-01-01T00:00:00Z".to_string(),
+            unix_time: Utc::now().timestamp() + 60,
+            data: StoredMsg::Send(CustomMsgSend {
+                from_address: ALICE.to_string(),
+                to_address: BOB.to_string(),
+                amount: vec![CustomCoin {
+                    denom: "umfx".to_string(),
+                    amount: amount.to_string(),
+                }],
+            }),
+        }
+    }
+
+    /// attempts every due delivery once
+    async fn attempt_due(db: &DB) {
+        let client = awc::Client::default();
+        for (queued, log_key) in due_deliveries(db) {
+            attempt(db, &client, &local_targets(), queued, log_key).await;
+        }
+    }
+
+    fn queue_len(db: &DB) -> usize {
+        db.iterator_cf(queue_cf(db), IteratorMode::Start).count()
+    }
+
+    fn log_len(db: &DB, webhook_id: &str) -> usize {
+        let prefix = format!("{}:", webhook_id);
+        db.iterator_cf(deliveries_cf(db), IteratorMode::Start)
+            .filter_map(|item| item.ok())
+            .filter(|(key, _)| key.starts_with(prefix.as_bytes()))
+            .count()
+    }
+
+    #[actix_rt::test]
+    async fn signs_deliveries_with_the_webhook_secret() {
+        let test = TestDb::new();
+        let (url, state) = receiver(200);
+        let webhook = new_webhook(&test.db, &url, no_filter());
+        enqueue(&test.db, "cursor-1", &send("5")).unwrap();
+        attempt_due(&test.db).await;
+
+        let received = state.received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        let request = &received[0];
+        let mut mac = HmacSha256::new_from_slice(webhook.secret.as_bytes()).unwrap();
+        mac.update(format!("{}.{}", request.timestamp, request.body).as_bytes());
+        let signature = request.signature.strip_prefix("sha256=").unwrap();
+        mac.verify_slice(&hex::decode(signature).unwrap()).unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["id"], request.id.as_str());
+        assert_eq!(body["webhook_id"], webhook.webhook.id.as_str());
+        assert_eq!(body["transaction"]["tx_hash"], "A1B2C3");
+
+        let deliveries = load_deliveries(&test.db, &webhook.webhook.id).unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].id, request.id);
+        assert_eq!(deliveries[0].status, DeliveryStatus::Delivered);
+        assert_eq!(deliveries[0].response_status, Some(200));
+        assert_eq!(queue_len(&test.db), 0);
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_an_hour() {
+        let delays: Vec<i64> = (1..=MAX_ATTEMPTS).map(retry_delay).collect();
+        assert_eq!(
+            delays,
+            vec![10, 20, 40, 80, 160, 320, 640, 1280, 2560, 3600]
+        );
+        assert_eq!(retry_delay(64), MAX_RETRY_DELAY);
+    }
+
+    #[actix_rt::test]
+    async fn reschedules_failed_deliveries() {
+        let test = TestDb::new();
+        let (url, state) = receiver(500);
+        let webhook = new_webhook(&test.db, &url, no_filter());
+        enqueue(&test.db, "cursor-1", &send("5")).unwrap();
+        attempt_due(&test.db).await;
+
+        assert_eq!(state.received.lock().unwrap().len(), 1);
+        let delivery = &load_deliveries(&test.db, &webhook.webhook.id).unwrap()[0];
+        assert_eq!(delivery.status, DeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.response_status, Some(500));
+        let last_attempt = delivery.last_attempt.unwrap();
+        assert_eq!(
+            delivery.next_attempt,
+            Some(last_attempt + FIRST_RETRY_DELAY)
+        );
+        // queued for later, not due again yet
+        assert_eq!(queue_len(&test.db), 1);
+        assert!(due_deliveries(&test.db).is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn gives_up_after_the_last_attempt() {
+        let test = TestDb::new();
+        let (url, state) = receiver(503);
+        let webhook = new_webhook(&test.db, &url, no_filter());
+        enqueue(&test.db, "cursor-1", &send("5")).unwrap();
+        let mut delivery = load_deliveries(&test.db, &webhook.webhook.id).unwrap()[0].clone();
+        delivery.attempts = MAX_ATTEMPTS - 1;
+        test.db
+            .put_cf(
+                deliveries_cf(&test.db),
+                delivery.log_key(),
+                serde_json::to_vec(&delivery).unwrap(),
+            )
+            .unwrap();
+        attempt_due(&test.db).await;
+
+        assert_eq!(state.received.lock().unwrap().len(), 1);
+        let delivery = &load_deliveries(&test.db, &webhook.webhook.id).unwrap()[0];
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, MAX_ATTEMPTS);
+        assert_eq!(delivery.next_attempt, None);
+        assert_eq!(queue_len(&test.db), 0);
+    }
+
+    #[actix_rt::test]
+    async fn queues_deliveries_for_matching_webhooks() {
+        let test = TestDb::new();
+        let url = "http://127.0.0.1:1/hooks";
+        let to_bob = new_webhook(
+            &test.db,
+            url,
+            WebhookFilter {
+                address: Some(BOB.to_string()),
+                direction: Some(Direction::Receive),
+                ..no_filter()
+            },
+        );
+        let from_bob = new_webhook(
+            &test.db,
+            url,
+            WebhookFilter {
+                address: Some(BOB.to_string()),
+                direction: Some(Direction::Send),
+                ..no_filter()
+            },
+        );
+        let ibc = new_webhook(
+            &test.db,
+            url,
+            WebhookFilter {
+                msg_type: Some("msgIbcTransfer".to_string()),
+                ..no_filter()
+            },
+        );
+        let large = new_webhook(
+            &test.db,
+            url,
+            WebhookFilter {
+                denom: Some("umfx".to_string()),
+                min_amount: Some("1000".to_string()),
+                ..no_filter()
+            },
+        );
+
+        enqueue(&test.db, "cursor-1", &send("500")).unwrap();
+        assert_eq!(log_len(&test.db, &to_bob.webhook.id), 1);
+        assert_eq!(log_len(&test.db, &from_bob.webhook.id), 0);
+        assert_eq!(log_len(&test.db, &ibc.webhook.id), 0);
+        assert_eq!(log_len(&test.db, &large.webhook.id), 0);
+
+        enqueue(&test.db, "cursor-2", &send("1000")).unwrap();
+        assert_eq!(log_len(&test.db, &to_bob.webhook.id), 2);
+        assert_eq!(log_len(&test.db, &large.webhook.id), 1);
+
+        // messages from before the webhooks existed aren't delivered
+        let mut old = send("5000");
+        old.unix_time = Utc::now().timestamp() - 3600;
+        enqueue(&test.db, "cursor-3", &old).unwrap();
+        assert_eq!(log_len(&test.db, &to_bob.webhook.id), 2);
+        assert_eq!(log_len(&test.db, &large.webhook.id), 1);
+
+        let cursor = test.db.get_cf(webhooks_cf(&test.db), CURSOR_KEY).unwrap();
+        assert_eq!(cursor.as_deref(), Some(&b"cursor-3"[..]));
+    }
+
+    #[actix_rt::test]
+    async fn keeps_the_newest_log_entries_and_pending_deliveries() {
+        let test = TestDb::new();
+        let webhook = new_webhook(&test.db, "http://127.0.0.1:1/hooks", no_filter());
+        for i in 0..MAX_LOG_ENTRIES {
+            enqueue(&test.db, &format!("cursor-{}", i), &send("5")).unwrap();
+        }
+        // pending deliveries are never pruned
+        enqueue(&test.db, "cursor-pending", &send("5")).unwrap();
+        prune_logs(&test.db).unwrap();
+        assert_eq!(log_len(&test.db, &webhook.webhook.id), MAX_LOG_ENTRIES + 1);
+
+        let log_cf = deliveries_cf(&test.db);
+        let prefix = format!("{}:", webhook.webhook.id);
+        let entries: Vec<(Box<[u8]>, Box<[u8]>)> = test
+            .db
+            .iterator_cf(log_cf, IteratorMode::Start)
+            .filter_map(|item| item.ok())
+            .filter(|(key, _)| key.starts_with(prefix.as_bytes()))
+            .collect();
+        for (key, value) in entries {
+            let mut delivery: Delivery = serde_json::from_slice(&value).unwrap();
+            delivery.status = DeliveryStatus::Delivered;
+            test.db
+                .put_cf(log_cf, key, serde_json::to_vec(&delivery).unwrap())
+                .unwrap();
+        }
+        enqueue(&test.db, "cursor-last", &send("5")).unwrap();
+        assert_eq!(log_len(&test.db, &webhook.webhook.id), MAX_LOG_ENTRIES + 2);
+        prune_logs(&test.db).unwrap();
+        assert_eq!(log_len(&test.db, &webhook.webhook.id), MAX_LOG_ENTRIES);
+    }
+
+    #[test]
+    fn refuses_internal_targets() {
+        let targets = WebhookTargets::default();
+        for url in [
+            "http://127.0.0.1:8080/hooks",
+            "http://10.0.0.1/hooks",
+            "http://192.168.1.20/hooks",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/hooks",
+            "http://[::1]/hooks",
+            "http://[fd00::1]/hooks",
+            "http://[::ffff:127.0.0.1]/hooks",
+            "http://localhost/hooks",
+            "ftp://example.com/hooks",
+            "example.com/hooks",
+        ] {
+            assert!(targets.check_url(url).is_err(), "{} was allowed", url);
+        }
+        assert_eq!(
+            targets
+                .check_url("https://Hooks.Example.com/deposits")
+                .unwrap(),
+            ("hooks.example.com".to_string(), 443)
+        );
+        assert_eq!(
+            targets.check_url("http://8.8.8.8:8080/hooks").unwrap(),
+            ("8.8.8.8".to_string(), 8080)
+        );
+
+        let test = TestDb::new();
+        let new = NewWebhook {
+            url: "http://127.0.0.1:8080/hooks".to_string(),
+            filter: no_filter(),
+        };
+        assert!(create_webhook(&test.db, new, &targets).is_err());
+        assert!(list_webhooks(&test.db).is_empty());
+    }
+
+    #[test]
+    fn applies_the_allowed_and_denied_hosts() {
+        let targets = WebhookTargets {
+            allowed_hosts: vec!["hooks.example.com".to_string(), "*.pay.example".to_string()],
+            denied_hosts: vec!["old.pay.example".to_string()],
+            allow_private: false,
+        };
+        assert!(targets.check_url("https://hooks.example.com/a").is_ok());
+        assert!(targets.check_url("https://eu.pay.example/a").is_ok());
+        assert!(targets.check_url("https://example.com/a").is_err());
+        assert!(targets.check_url("https://pay.example/a").is_err());
+        assert!(targets.check_url("https://old.pay.example/a").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn refuses_hosts_resolving_to_internal_addresses() {
+        let targets = WebhookTargets::default();
+        assert!(targets.check_resolved("localhost", 80).await.is_err());
+        let address = local_targets()
+            .check_target("http://localhost:1/hooks")
+            .await
+            .unwrap();
+        assert!(address.ip().is_loopback());
+        assert_eq!(address.port(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn connects_to_the_checked_address() {
+        let test = TestDb::new();
+        let (url, state) = receiver(200);
+        let address: SocketAddr = url
+            .trim_start_matches("http://")
+            .trim_end_matches("/hooks")
+            .parse()
+            .unwrap();
+        // the host resolves to nothing, so only the checked address can be
+        // what the request went to
+        let webhook = new_webhook(
+            &test.db,
+            &format!("http://rebinding.invalid:{}/hooks", address.port()),
+            no_filter(),
+        );
+        enqueue(&test.db, "cursor-1", &send("5")).unwrap();
+        let delivery = &load_deliveries(&test.db, &webhook.webhook.id).unwrap()[0];
+
+        let status = post(&awc::Client::default(), &webhook, delivery, address)
+            .await
+            .unwrap();
+        assert_eq!(status, 200);
+        let received = state.received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].id, delivery.id);
+    }
+}